@@ -0,0 +1,291 @@
+//! Compares the ordered `Ledger` against the previous `HashMap` based implementation.
+//!
+//! By default a synthetic depth stream is generated. To bench against a recorded stream, point
+//! `POPPY_DEPTH_STREAM` at a file holding one raw Binance `depthUpdate` message per line, e.g.:
+//!
+//! `POPPY_DEPTH_STREAM=adausdt.jsonl cargo bench --bench ledger`
+#![feature(test)]
+
+extern crate test;
+
+use decorum::Finite;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use poppy::crypto::orderbook::{Ledger, TailOrdering};
+use poppy::exchanges::mandala::utils::DepthUpdate;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use test::{black_box, Bencher};
+
+const SYNTHETIC_UPDATES: usize = 5000;
+const LEVELS_PER_UPDATE: usize = 20;
+const TOP_N: usize = 10;
+
+// (price, quantity) changes per side, quantity 0 meaning "remove the level".
+struct Update {
+    bids: Vec<(Decimal, Decimal)>,
+    asks: Vec<(Decimal, Decimal)>,
+}
+
+fn load_stream() -> Vec<Update> {
+    match env::var("POPPY_DEPTH_STREAM") {
+        Ok(path) => {
+            let file = File::open(&path).expect("Couldn't open depth stream");
+
+            BufReader::new(file)
+                .lines()
+                .filter_map(|line| serde_json::from_str::<DepthUpdate>(&line.ok()?).ok())
+                .map(|update| Update {
+                    bids: update.bids.iter().map(|o| (o.0, o.1)).collect(),
+                    asks: update.asks.iter().map(|o| (o.0, o.1)).collect(),
+                })
+                .collect()
+        }
+        Err(_) => synthetic_stream(),
+    }
+}
+
+// A random walk around a mid price with a fixed tick size, roughly shaped like a busy
+// @depth@100ms stream: most changes happen close to the top of the book, some levels get removed.
+fn synthetic_stream() -> Vec<Update> {
+    let mut seed: u64 = 0x5EED;
+    let mut next = move || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as f64 / (1u64 << 31) as f64
+    };
+
+    // Prices are expressed in ticks of 0.0001, starting at 1.3000.
+    let mut mid: i64 = 13000;
+
+    (0..SYNTHETIC_UPDATES)
+        .map(|_| {
+            mid += ((next() - 0.5) * 4.0).round() as i64;
+
+            let mut side = |direction: i64| {
+                (0..LEVELS_PER_UPDATE)
+                    .map(|_| {
+                        let distance = (next() * next() * 500.0) as i64 + 1;
+                        let price = Decimal::new(mid + direction * distance, 4);
+                        let quantity = if next() < 0.1 {
+                            Decimal::ZERO
+                        } else {
+                            Decimal::new((next() * 1_000_000.0) as i64, 3)
+                        };
+
+                        (price, quantity)
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            Update {
+                bids: side(-1),
+                asks: side(1),
+            }
+        })
+        .collect()
+}
+
+// The ledger as it was before it moved to an ordered map, copied over unchanged (f64 prices behind
+// `Finite`, a HashMap and a full key scan when the tail is removed) so it can serve as the baseline.
+type Fin64 = Finite<f64>;
+
+#[derive(Debug)]
+enum TailUpdate {
+    Set(Fin64),
+    Remove(Fin64),
+}
+
+fn ordering_cmp<T: Ord>(ordering: &TailOrdering, a: &T, b: &T) -> Ordering {
+    let ordering_result = a.cmp(b);
+
+    if let TailOrdering::Lowest = ordering {
+        ordering_result.reverse()
+    } else {
+        ordering_result
+    }
+}
+
+struct HashLedger {
+    map: HashMap<Fin64, f64>,
+    tail: Option<Fin64>,
+    ordering: TailOrdering,
+}
+
+impl HashLedger {
+    fn new(ordering: TailOrdering) -> Self {
+        Self {
+            map: HashMap::new(),
+            tail: None,
+            ordering,
+        }
+    }
+
+    fn put(&mut self, price: Fin64, quantity: Option<f64>) {
+        match quantity {
+            Some(q) => {
+                self.set(price, q);
+                self.maybe_update_tail(TailUpdate::Set(price));
+            }
+            None => {
+                if self.remove(price) {
+                    self.maybe_update_tail(TailUpdate::Remove(price))
+                }
+            }
+        }
+    }
+
+    fn maybe_update_tail(&mut self, update: TailUpdate) {
+        match update {
+            TailUpdate::Set(at) => {
+                if {
+                    match self.tail {
+                        None => true,
+                        Some(old) => ordering_cmp(&self.ordering, &at, &old) == Ordering::Greater,
+                    }
+                } {
+                    self.tail = Some(at)
+                }
+            }
+            TailUpdate::Remove(at) => {
+                if let Some(old) = self.tail {
+                    match ordering_cmp(&self.ordering, &old, &at) {
+                        Ordering::Equal => self.tail = self.find_new_tail(),
+                        Ordering::Less => {
+                            panic!("Found Ordering::Less for TailOrdering {:?} for values (a) {} and (b) {}", self.ordering, old, at)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn find_new_tail(&self) -> Option<Fin64> {
+        let keys = self.map.keys();
+        match self.ordering {
+            TailOrdering::Lowest => keys.min(),
+            TailOrdering::Highest => keys.max(),
+        }
+        .cloned()
+    }
+
+    #[inline]
+    fn tail(&self) -> Option<Fin64> {
+        self.tail
+    }
+
+    #[inline]
+    fn set<F: Into<Fin64>>(&mut self, at: F, with: f64) {
+        self.map.insert(at.into(), with);
+    }
+
+    #[inline]
+    fn remove<F: Into<Fin64>>(&mut self, at: F) -> bool {
+        self.map.remove(&at.into()).is_some()
+    }
+
+    fn iter(&self) -> std::vec::IntoIter<(&Fin64, &f64)> {
+        self.map
+            .iter()
+            .sorted_by(|a, b| ordering_cmp(&self.ordering, &b.0, &a.0))
+    }
+}
+
+// The stream as the old ledger took it, converted up front so the conversion isn't measured.
+struct FloatUpdate {
+    bids: Vec<(Fin64, f64)>,
+    asks: Vec<(Fin64, f64)>,
+}
+
+fn to_floats(stream: &[Update]) -> Vec<FloatUpdate> {
+    let side = |levels: &[(Decimal, Decimal)]| {
+        levels
+            .iter()
+            .map(|(p, q)| (Fin64::from(p.to_f64().unwrap()), q.to_f64().unwrap()))
+            .collect()
+    };
+
+    stream
+        .iter()
+        .map(|update| FloatUpdate {
+            bids: side(&update.bids),
+            asks: side(&update.asks),
+        })
+        .collect()
+}
+
+fn quantity(q: Decimal) -> Option<Decimal> {
+    if q.is_zero() {
+        None
+    } else {
+        Some(q)
+    }
+}
+
+fn apply_ordered(stream: &[Update]) -> (Ledger, Ledger) {
+    let mut bids = Ledger::new(TailOrdering::Highest);
+    let mut asks = Ledger::new(TailOrdering::Lowest);
+
+    for update in stream {
+        for (p, q) in update.bids.iter() {
+            bids.put(*p, quantity(*q));
+        }
+        for (p, q) in update.asks.iter() {
+            asks.put(*p, quantity(*q));
+        }
+        black_box((bids.tail(), asks.tail()));
+    }
+
+    (bids, asks)
+}
+
+fn apply_hashed(stream: &[FloatUpdate]) -> (HashLedger, HashLedger) {
+    let mut bids = HashLedger::new(TailOrdering::Highest);
+    let mut asks = HashLedger::new(TailOrdering::Lowest);
+
+    for update in stream {
+        for (p, q) in update.bids.iter() {
+            bids.put(*p, if *q == 0.0 { None } else { Some(*q) });
+        }
+        for (p, q) in update.asks.iter() {
+            asks.put(*p, if *q == 0.0 { None } else { Some(*q) });
+        }
+        black_box((bids.tail(), asks.tail()));
+    }
+
+    (bids, asks)
+}
+
+#[bench]
+fn apply_stream_ordered(b: &mut Bencher) {
+    let stream = load_stream();
+    b.iter(|| apply_ordered(&stream));
+}
+
+#[bench]
+fn apply_stream_hashmap(b: &mut Bencher) {
+    let stream = to_floats(&load_stream());
+    b.iter(|| apply_hashed(&stream));
+}
+
+#[bench]
+fn top_n_ordered(b: &mut Bencher) {
+    let (bids, asks) = apply_ordered(&load_stream());
+    b.iter(|| {
+        black_box(bids.top(TOP_N).count());
+        black_box(asks.top(TOP_N).count());
+    });
+}
+
+#[bench]
+fn top_n_hashmap(b: &mut Bencher) {
+    let (bids, asks) = apply_hashed(&to_floats(&load_stream()));
+    b.iter(|| {
+        black_box(bids.iter().take(TOP_N).count());
+        black_box(asks.iter().take(TOP_N).count());
+    });
+}
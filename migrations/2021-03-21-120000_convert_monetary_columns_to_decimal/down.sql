@@ -0,0 +1,9 @@
+ALTER TABLE transactions
+    MODIFY amount double(8,2) NOT NULL,
+    MODIFY price double(8,2) NOT NULL;
+
+ALTER TABLE finished_transactions
+    MODIFY amount_bought double(8,2) NOT NULL,
+    MODIFY buy_price double(8,2) NOT NULL,
+    MODIFY amount_sold double(8,2) NOT NULL,
+    MODIFY sell_price double(8,2) NOT NULL;
@@ -0,0 +1,9 @@
+ALTER TABLE transactions
+    MODIFY amount decimal(36,18) NOT NULL,
+    MODIFY price decimal(36,18) NOT NULL;
+
+ALTER TABLE finished_transactions
+    MODIFY amount_bought decimal(36,18) NOT NULL,
+    MODIFY buy_price decimal(36,18) NOT NULL,
+    MODIFY amount_sold decimal(36,18) NOT NULL,
+    MODIFY sell_price decimal(36,18) NOT NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE candles (
+    exchange varchar(32) NOT NULL,
+    symbol varchar(16) NOT NULL,
+    timeframe varchar(8) NOT NULL,
+    open_time bigint NOT NULL,
+    open decimal(36,18) NOT NULL,
+    high decimal(36,18) NOT NULL,
+    low decimal(36,18) NOT NULL,
+    close decimal(36,18) NOT NULL,
+    volume decimal(36,18) NOT NULL,
+    PRIMARY KEY (exchange, symbol, timeframe, open_time)
+);
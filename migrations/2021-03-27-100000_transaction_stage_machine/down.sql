@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS transaction_stage_history;
+ALTER TABLE transactions MODIFY stage varchar(255) NOT NULL;
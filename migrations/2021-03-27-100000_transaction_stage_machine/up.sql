@@ -0,0 +1,36 @@
+-- BuyTransactionFilled and SellTransactionFilled were never written, filled orders go to Hodl and Finished.
+ALTER TABLE transactions
+    MODIFY stage ENUM(
+        'BuyTransactionOpen',
+        'BuyTransactionPartiallyFilled',
+        'Hodl',
+        'SellTransactionOpen',
+        'SellTransactionPartiallyFilled',
+        'Finished',
+        'Canceled'
+    ) NOT NULL;
+
+CREATE TABLE transaction_stage_history (
+    id bigint NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    transaction_id char(36) NOT NULL,
+    from_stage ENUM(
+        'BuyTransactionOpen',
+        'BuyTransactionPartiallyFilled',
+        'Hodl',
+        'SellTransactionOpen',
+        'SellTransactionPartiallyFilled',
+        'Finished',
+        'Canceled'
+    ) NULL DEFAULT NULL,
+    to_stage ENUM(
+        'BuyTransactionOpen',
+        'BuyTransactionPartiallyFilled',
+        'Hodl',
+        'SellTransactionOpen',
+        'SellTransactionPartiallyFilled',
+        'Finished',
+        'Canceled'
+    ) NOT NULL,
+    created_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX transaction_stage_history_transaction_id (transaction_id)
+);
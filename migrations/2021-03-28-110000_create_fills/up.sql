@@ -0,0 +1,15 @@
+CREATE TABLE fills (
+    id bigint NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    transaction_id char(36) NOT NULL,
+    exchange_name varchar(64) NOT NULL,
+    order_id varchar(64) NOT NULL,
+    trade_id varchar(64) NOT NULL,
+    side ENUM('Buy', 'Sell') NOT NULL,
+    quantity decimal(36,18) NOT NULL,
+    price decimal(36,18) NOT NULL,
+    fee decimal(36,18) NOT NULL,
+    fee_asset varchar(16) NOT NULL,
+    executed_at timestamp NOT NULL,
+    UNIQUE KEY fills_exchange_trade (exchange_name, trade_id),
+    INDEX fills_transaction_id (transaction_id)
+);
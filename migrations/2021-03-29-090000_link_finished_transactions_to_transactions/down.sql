@@ -0,0 +1,16 @@
+ALTER TABLE finished_transactions
+    DROP FOREIGN KEY finished_transactions_transaction_id_foreign;
+
+UPDATE finished_transactions
+SET transaction_id = sell_exchange_id
+WHERE sell_exchange_id IS NOT NULL;
+
+INSERT INTO finished_transactions SELECT * FROM finished_transactions_orphans;
+DROP TABLE IF EXISTS finished_transactions_orphans;
+
+ALTER TABLE finished_transactions
+    DROP COLUMN buy_exchange_id,
+    DROP COLUMN sell_exchange_id;
+
+ALTER TABLE finished_transactions
+    ADD CONSTRAINT finished_transactions_transaction_id_foreign FOREIGN KEY (transaction_id) REFERENCES transactions (sell_exchange_id);
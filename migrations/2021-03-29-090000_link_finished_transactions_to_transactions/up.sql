@@ -0,0 +1,30 @@
+-- transaction_id used to hold the sell order id and referenced transactions (sell_exchange_id).
+-- It now references transactions (id), with both exchange order ids stored next to it.
+ALTER TABLE finished_transactions
+    DROP FOREIGN KEY finished_transactions_transaction_id_foreign;
+
+-- MODIFY drops the column's collation unless it's restated, and the key needs it to match transactions.
+ALTER TABLE finished_transactions
+    MODIFY transaction_id char(36) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NOT NULL,
+    ADD buy_exchange_id varchar(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NULL DEFAULT NULL,
+    ADD sell_exchange_id varchar(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci NULL DEFAULT NULL;
+
+UPDATE finished_transactions f
+    INNER JOIN transactions t ON t.sell_exchange_id = f.transaction_id
+SET f.buy_exchange_id = t.buy_exchange_id,
+    f.sell_exchange_id = t.sell_exchange_id,
+    f.transaction_id = t.id;
+
+-- Rows whose sell order can't be traced back to a transaction can't satisfy the new key, they're
+-- kept aside for inspection instead.
+CREATE TABLE finished_transactions_orphans AS
+    SELECT f.* FROM finished_transactions f
+    LEFT JOIN transactions t ON t.id = f.transaction_id
+    WHERE t.id IS NULL;
+
+DELETE f FROM finished_transactions f
+    LEFT JOIN transactions t ON t.id = f.transaction_id
+    WHERE t.id IS NULL;
+
+ALTER TABLE finished_transactions
+    ADD CONSTRAINT finished_transactions_transaction_id_foreign FOREIGN KEY (transaction_id) REFERENCES transactions (id);
@@ -0,0 +1,11 @@
+CREATE TABLE equity_snapshots (
+    id bigint NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    exchange_name varchar(64) NOT NULL,
+    taken_at timestamp NOT NULL,
+    asset varchar(16) NOT NULL,
+    free decimal(36,18) NOT NULL,
+    locked decimal(36,18) NOT NULL,
+    price decimal(36,18) NULL DEFAULT NULL,
+    value decimal(36,18) NULL DEFAULT NULL,
+    INDEX equity_snapshots_exchange_taken_at (exchange_name, taken_at)
+);
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS fills;
+DROP TABLE IF EXISTS transaction_stage_history;
+DROP TABLE IF EXISTS candles;
+DROP TABLE IF EXISTS finished_transactions;
+DROP TABLE IF EXISTS transactions;
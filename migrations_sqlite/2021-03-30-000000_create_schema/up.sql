@@ -0,0 +1,69 @@
+-- The MySQL migrations in migrations/ up to 2021-03-29 folded into one. SQLite has no DECIMAL or
+-- ENUM: money is stored as TEXT to keep it exact and stages as TEXT holding the variant name.
+CREATE TABLE transactions (
+    id char(36) NOT NULL PRIMARY KEY,
+    exchange_name varchar(64) NOT NULL,
+    buy_exchange_id varchar(64),
+    sell_exchange_id varchar(64),
+    amount TEXT NOT NULL,
+    symbol varchar(16) NOT NULL,
+    price TEXT NOT NULL,
+    stage TEXT NOT NULL,
+    created_at timestamp NULL DEFAULT NULL,
+    updated_at timestamp NULL DEFAULT NULL
+);
+
+CREATE TABLE finished_transactions (
+    id char(36) NOT NULL PRIMARY KEY,
+    transaction_id char(36) NOT NULL REFERENCES transactions (id),
+    amount_bought TEXT NOT NULL,
+    buy_price TEXT NOT NULL,
+    amount_sold TEXT NOT NULL,
+    sell_price TEXT NOT NULL,
+    created_at timestamp NULL DEFAULT NULL,
+    updated_at timestamp NULL DEFAULT NULL,
+    buy_exchange_id varchar(64) NULL DEFAULT NULL,
+    sell_exchange_id varchar(64) NULL DEFAULT NULL
+);
+
+CREATE INDEX finished_transactions_transaction_id ON finished_transactions (transaction_id);
+
+CREATE TABLE candles (
+    exchange varchar(32) NOT NULL,
+    symbol varchar(16) NOT NULL,
+    timeframe varchar(8) NOT NULL,
+    open_time bigint NOT NULL,
+    open TEXT NOT NULL,
+    high TEXT NOT NULL,
+    low TEXT NOT NULL,
+    close TEXT NOT NULL,
+    volume TEXT NOT NULL,
+    PRIMARY KEY (exchange, symbol, timeframe, open_time)
+);
+
+CREATE TABLE transaction_stage_history (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    transaction_id char(36) NOT NULL,
+    from_stage TEXT NULL DEFAULT NULL,
+    to_stage TEXT NOT NULL,
+    created_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX transaction_stage_history_transaction_id ON transaction_stage_history (transaction_id);
+
+CREATE TABLE fills (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    transaction_id char(36) NOT NULL,
+    exchange_name varchar(64) NOT NULL,
+    order_id varchar(64) NOT NULL,
+    trade_id varchar(64) NOT NULL,
+    side TEXT NOT NULL,
+    quantity TEXT NOT NULL,
+    price TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    fee_asset varchar(16) NOT NULL,
+    executed_at timestamp NOT NULL,
+    UNIQUE (exchange_name, trade_id)
+);
+
+CREATE INDEX fills_transaction_id ON fills (transaction_id);
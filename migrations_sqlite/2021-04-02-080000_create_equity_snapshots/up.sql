@@ -0,0 +1,12 @@
+CREATE TABLE equity_snapshots (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    exchange_name varchar(64) NOT NULL,
+    taken_at timestamp NOT NULL,
+    asset varchar(16) NOT NULL,
+    free TEXT NOT NULL,
+    locked TEXT NOT NULL,
+    price TEXT NULL DEFAULT NULL,
+    value TEXT NULL DEFAULT NULL
+);
+
+CREATE INDEX equity_snapshots_exchange_taken_at ON equity_snapshots (exchange_name, taken_at);
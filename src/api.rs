@@ -0,0 +1,212 @@
+use crate::bot::control::Control;
+use crate::metrics;
+use crate::utils::config::MIN_ADMIN_TOKEN_LENGTH;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reply::{Json, WithStatus};
+use warp::{Filter, Rejection, Reply};
+
+// An HTTP API on localhost to look into and steer a running bot. Reading is open to anything on the
+// machine, endpoints that change something want `Authorization: Bearer <admin.token>`.
+//
+//   GET  /books?depth=10                 top levels of every book
+//   GET  /balances
+//   GET  /transactions                   open transactions
+//   GET  /brokers                        strategy parameters per coin
+//   GET  /health
+//   GET  /metrics                        Prometheus text format, see `metrics`
+//   POST /coins/<symbol>/pause           stop buying and selling a coin
+//   POST /coins/<symbol>/resume
+//   POST /buying/pause                   stop buying anything, held coins still sell
+//   POST /buying/resume
+//   POST /transactions/<id>/sell?price=  sell a held transaction, at the best bid without a price
+pub async fn serve(control: Control, port: u16, token: String) {
+    if token.len() < MIN_ADMIN_TOKEN_LENGTH {
+        error!("Not starting the admin API, admin.token has to be at least {} characters", MIN_ADMIN_TOKEN_LENGTH);
+        return;
+    }
+
+    info!("Admin API listening on 127.0.0.1:{}", port);
+
+    warp::serve(routes(control, Arc::new(token)))
+        .run(([127, 0, 0, 1], port))
+        .await;
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(Deserialize)]
+struct BooksQuery {
+    depth: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct SellQuery {
+    price: Option<Decimal>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    message: String,
+}
+
+fn with_control(control: Control) -> impl Filter<Extract = (Control,), Error = Infallible> + Clone {
+    warp::any().map(move || control.clone())
+}
+
+fn authorized(token: Arc<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let expected = format!("Bearer {}", token);
+
+            async move {
+                if header.map_or(false, |header| constant_time_eq(header.as_bytes(), expected.as_bytes())) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Compares without returning early, so the time taken doesn't tell how much of the token was right.
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+
+    left.iter().zip(right).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn reply<T: Serialize>(result: anyhow::Result<T>) -> WithStatus<Json> {
+    match result {
+        Ok(value) => warp::reply::with_status(warp::reply::json(&value), StatusCode::OK),
+        Err(error) => warp::reply::with_status(
+            warp::reply::json(&Message { message: error.to_string() }),
+            StatusCode::BAD_REQUEST,
+        ),
+    }
+}
+
+fn done<T: Into<String>>(message: T) -> anyhow::Result<Message> {
+    Ok(Message { message: message.into() })
+}
+
+fn routes(control: Control, token: Arc<String>) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
+    let books = warp::path!("books")
+        .and(warp::get())
+        .and(warp::query::<BooksQuery>())
+        .and(with_control(control.clone()))
+        .and_then(|query: BooksQuery, control: Control| async move {
+            Ok::<_, Rejection>(reply(Ok(control.books(query.depth.unwrap_or(10)).await)))
+        });
+
+    let balances = warp::path!("balances")
+        .and(warp::get())
+        .and(with_control(control.clone()))
+        .and_then(|control: Control| async move { Ok::<_, Rejection>(reply(Ok(control.balances().await))) });
+
+    let transactions = warp::path!("transactions")
+        .and(warp::get())
+        .and(with_control(control.clone()))
+        .map(|control: Control| reply(control.open_transactions()));
+
+    let brokers = warp::path!("brokers")
+        .and(warp::get())
+        .and(with_control(control.clone()))
+        .map(|control: Control| reply(Ok(control.brokers())));
+
+    let health = warp::path!("health")
+        .and(warp::get())
+        .and(with_control(control.clone()))
+        .and_then(|control: Control| async move { Ok::<_, Rejection>(reply(Ok(control.health().await))) });
+
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .map(|| warp::reply::with_header(metrics::render(), "content-type", "text/plain; version=0.0.4"));
+
+    let pause_coin = warp::path!("coins" / String / "pause")
+        .and(warp::post())
+        .and(authorized(Arc::clone(&token)))
+        .and(with_control(control.clone()))
+        .map(|symbol: String, control: Control| {
+            reply(control.pause_coin(&symbol).and_then(|_| done(format!("Paused {}", symbol))))
+        });
+
+    let resume_coin = warp::path!("coins" / String / "resume")
+        .and(warp::post())
+        .and(authorized(Arc::clone(&token)))
+        .and(with_control(control.clone()))
+        .map(|symbol: String, control: Control| {
+            reply(control.resume_coin(&symbol).and_then(|_| done(format!("Resumed {}", symbol))))
+        });
+
+    let pause_buying = warp::path!("buying" / "pause")
+        .and(warp::post())
+        .and(authorized(Arc::clone(&token)))
+        .and(with_control(control.clone()))
+        .map(|control: Control| {
+            control.pause_buying();
+            reply(done("Paused buying"))
+        });
+
+    let resume_buying = warp::path!("buying" / "resume")
+        .and(warp::post())
+        .and(authorized(Arc::clone(&token)))
+        .and(with_control(control.clone()))
+        .map(|control: Control| {
+            control.resume_buying();
+            reply(done("Resumed buying"))
+        });
+
+    let sell = warp::path!("transactions" / String / "sell")
+        .and(warp::post())
+        .and(authorized(Arc::clone(&token)))
+        .and(warp::query::<SellQuery>())
+        .and(with_control(control))
+        .and_then(|id: String, query: SellQuery, control: Control| async move {
+            let result = control
+                .force_sell(&id, query.price)
+                .await
+                .and_then(|order_id| done(format!("Placed sell order {}", order_id)));
+
+            Ok::<_, Rejection>(reply(result))
+        });
+
+    books
+        .or(balances)
+        .or(transactions)
+        .or(brokers)
+        .or(health)
+        .or(metrics)
+        .or(pause_coin)
+        .or(resume_coin)
+        .or(pause_buying)
+        .or(resume_buying)
+        .or(sell)
+        .recover(handle_rejection)
+}
+
+async fn handle_rejection(rejection: Rejection) -> Result<WithStatus<Json>, Infallible> {
+    let (status, message) = if rejection.find::<Unauthorized>().is_some() {
+        (StatusCode::UNAUTHORIZED, "Missing or wrong token")
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found")
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+    } else {
+        (StatusCode::BAD_REQUEST, "Bad request")
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&Message { message: message.to_string() }),
+        status,
+    ))
+}
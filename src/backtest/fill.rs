@@ -0,0 +1,245 @@
+use crate::crypto::orderbook::{LedgerIter, OrderBook, OrderSide};
+use crate::exchanges::mandala::utils::OrderStatus;
+use hashbrown::HashMap;
+use rust_decimal::Decimal;
+use std::cmp::{max, min};
+
+// A limit order resting on the simulated exchange.
+#[derive(Debug, Clone)]
+pub struct SimulatedOrder {
+    pub id: String,
+    pub transaction_id: String,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub executed_quantity: Decimal,
+    pub status: OrderStatus,
+    // Simulated time in milliseconds since the epoch.
+    pub placed_at: i64,
+}
+
+impl SimulatedOrder {
+    #[inline]
+    pub fn remaining(&self) -> Decimal {
+        self.quantity - self.executed_quantity
+    }
+
+    #[inline]
+    pub fn is_open(&self) -> bool {
+        self.status == OrderStatus::New || self.status == OrderStatus::PartiallyFilled
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub quantity: Decimal,
+    pub price: Decimal,
+    // Always in the quote currency.
+    pub fee: Decimal,
+}
+
+pub trait FillModel {
+    // Called for every open order on every book update. Returns the part of the order that got
+    // executed since the last call, if any.
+    fn fill(&mut self, order: &SimulatedOrder, book: &OrderBook, now: i64) -> Option<Fill>;
+
+    // Called once an order is done (filled or canceled), so models can drop any state they kept.
+    fn forget(&mut self, _order: &SimulatedOrder) {}
+}
+
+// Fills orders completely at their limit price as soon as the book trades through it, without any
+// latency or queueing. Optimistic, but good enough to get a feel for a strategy.
+pub struct ImmediateFillModel {
+    fee_rate: Decimal,
+}
+
+impl ImmediateFillModel {
+    pub fn new(fee_rate: Decimal) -> Self {
+        Self { fee_rate }
+    }
+}
+
+impl FillModel for ImmediateFillModel {
+    fn fill(&mut self, order: &SimulatedOrder, book: &OrderBook, _now: i64) -> Option<Fill> {
+        let crossed = match order.side {
+            OrderSide::Buy => book.lowest_ask().map_or(false, |ask| ask <= order.price),
+            OrderSide::Sell => book.highest_bid().map_or(false, |bid| bid >= order.price),
+        };
+
+        if !crossed {
+            return None;
+        }
+
+        let quantity = order.remaining();
+
+        Some(Fill {
+            quantity,
+            price: order.price,
+            fee: quantity * order.price * self.fee_rate,
+        })
+    }
+}
+
+// Where a resting order stands at its price level.
+#[derive(Debug)]
+struct Queue {
+    // Quantity that was at the level before the order arrived and has to trade first.
+    ahead: Decimal,
+    // Quantity at the level on the previous update.
+    level: Decimal,
+    // Quantity the order already filled against, per price level of the other side. A level that keeps
+    // crossing the order only fills it again once more volume shows up there.
+    taken: HashMap<Decimal, Decimal>,
+}
+
+// Simulates an order's way through the exchange. It only arrives after `latency` milliseconds, then
+// takes whatever liquidity it crosses as a taker. The rest of it rests at its price, behind the quantity
+// that was already there. While the level is at the top of the book, decreases of it are taken to be
+// trades, which work through the queue ahead before they fill the order. If the other side moves to or
+// through the order's price, it gets filled against the volume there.
+pub struct QueueFillModel {
+    latency: i64,
+    maker_fee: Decimal,
+    taker_fee: Decimal,
+    queues: HashMap<String, Queue>,
+}
+
+impl QueueFillModel {
+    pub fn new(latency: i64, maker_fee: Decimal, taker_fee: Decimal) -> Self {
+        Self {
+            latency,
+            maker_fee,
+            taker_fee,
+            queues: HashMap::new(),
+        }
+    }
+
+    fn maker(&self, order: &SimulatedOrder, quantity: Decimal) -> Option<Fill> {
+        if quantity <= Decimal::new(0, 0) {
+            return None;
+        }
+
+        Some(Fill {
+            quantity,
+            price: order.price,
+            fee: quantity * order.price * self.maker_fee,
+        })
+    }
+
+    // Walks the other side of the book from its best level for as long as it crosses the order's price.
+    fn taker(&self, order: &SimulatedOrder, opposite: LedgerIter<'_>, taken: &mut HashMap<Decimal, Decimal>) -> Option<Fill> {
+        let mut quantity = Decimal::new(0, 0);
+        let mut cost = Decimal::new(0, 0);
+
+        for (price, available) in opposite.take_while(|(price, _)| crosses(order, **price)) {
+            let taking = min(*available, order.remaining() - quantity);
+            quantity += taking;
+            cost += taking * *price;
+            taken.insert(*price, taking);
+
+            if quantity >= order.remaining() {
+                break;
+            }
+        }
+
+        if quantity.is_zero() {
+            return None;
+        }
+
+        Some(Fill {
+            quantity,
+            price: cost / quantity,
+            fee: cost * self.taker_fee,
+        })
+    }
+}
+
+// Whether the other side trading at `price` would execute the order.
+fn crosses(order: &SimulatedOrder, price: Decimal) -> bool {
+    match order.side {
+        OrderSide::Buy => price <= order.price,
+        OrderSide::Sell => price >= order.price,
+    }
+}
+
+impl FillModel for QueueFillModel {
+    fn fill(&mut self, order: &SimulatedOrder, book: &OrderBook, now: i64) -> Option<Fill> {
+        if now < order.placed_at + self.latency {
+            // Still on its way to the exchange.
+            return None;
+        }
+
+        let (own, opposite) = match order.side {
+            OrderSide::Buy => (&book.bids, &book.asks),
+            OrderSide::Sell => (&book.asks, &book.bids),
+        };
+
+        let level = own.get(&order.price).unwrap_or_default();
+
+        if !self.queues.contains_key(&order.id) {
+            // Just arrived.
+            let mut queue = Queue {
+                ahead: level,
+                level,
+                taken: HashMap::new(),
+            };
+            let fill = self.taker(order, opposite.iter(), &mut queue.taken);
+            self.queues.insert(order.id.clone(), queue);
+
+            return fill;
+        }
+
+        let crossing = opposite
+            .iter()
+            .take_while(|(price, _)| crosses(order, **price))
+            .map(|(price, quantity)| (*price, *quantity))
+            .collect::<Vec<_>>();
+
+        let queue = self.queues.get_mut(&order.id)?;
+        let previous = queue.level;
+        queue.level = level;
+
+        // Levels that moved away no longer hold anything the order took.
+        queue.taken.retain(|price, _| crossing.iter().any(|(crossing, _)| crossing == price));
+
+        if !crossing.is_empty() {
+            // Traded through, everything ahead of us is gone.
+            queue.ahead = Decimal::new(0, 0);
+
+            let mut quantity = Decimal::new(0, 0);
+
+            for (price, available) in crossing {
+                let taken = queue.taken.entry(price).or_insert_with(|| Decimal::new(0, 0));
+                // What went off the level since is gone, only what's beyond the rest is new.
+                *taken = min(*taken, available);
+
+                let taking = min(available - *taken, order.remaining() - quantity);
+                *taken += taking;
+                quantity += taking;
+
+                if quantity >= order.remaining() {
+                    break;
+                }
+            }
+
+            return self.maker(order, quantity);
+        }
+
+        // Only the top of the book trades, further down a shrinking level means cancellations.
+        let at_top = own.tail().map_or(true, |best| crosses(order, best));
+
+        if !at_top {
+            return None;
+        }
+
+        let traded = max(previous - level, Decimal::new(0, 0));
+        let consumed = min(queue.ahead, traded);
+        queue.ahead -= consumed;
+
+        self.maker(order, min(order.remaining(), traded - consumed))
+    }
+
+    fn forget(&mut self, order: &SimulatedOrder) {
+        self.queues.remove(&order.id);
+    }
+}
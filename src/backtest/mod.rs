@@ -0,0 +1,519 @@
+use crate::backtest::fill::{FillModel, SimulatedOrder};
+use crate::bot::buy_amount;
+use crate::bot::trading::strategy::{Positions, SupportBand};
+use crate::crypto::candle::Candle;
+use crate::crypto::orderbook::{Order, OrderBook, OrderSide};
+use crate::crypto::treasury::TransactionIntent;
+use crate::database::{Transaction, TransactionStage};
+use crate::exchanges::mandala::recorder::{records, BookReplay};
+use crate::exchanges::mandala::utils::OrderStatus;
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use hashbrown::HashMap;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::path::PathBuf;
+
+pub mod fill;
+pub mod optimise;
+pub mod report;
+
+// Equity is sampled at most this often (in simulated time), depth data comes in every 100ms.
+const EQUITY_SAMPLE_INTERVAL: i64 = 60_000;
+
+// Time as the backtest sees it, in milliseconds since the epoch. Only moves forward.
+#[derive(Debug, Default)]
+pub struct SimulatedClock {
+    now: i64,
+}
+
+impl SimulatedClock {
+    #[inline]
+    pub fn now(&self) -> i64 {
+        self.now
+    }
+
+    pub fn advance_to(&mut self, timestamp: i64) {
+        self.now = std::cmp::max(self.now, timestamp);
+    }
+}
+
+// The transactions the simulated bot has made, standing in for the `transactions` table.
+#[derive(Debug, Default)]
+pub struct SimulatedPortfolio {
+    transactions: Vec<Transaction>,
+}
+
+impl SimulatedPortfolio {
+    fn get_mut(&mut self, id: &str) -> Option<&mut Transaction> {
+        self.transactions.iter_mut().find(|t| t.id == id)
+    }
+
+    fn has_position(&self) -> bool {
+        self.transactions.iter().any(|t| {
+            t.stage != TransactionStage::Finished
+                && t.stage != TransactionStage::BuyTransactionOpen
+        })
+    }
+}
+
+impl Positions for SimulatedPortfolio {
+    fn count_open(&self, symbol: &str) -> Result<i64> {
+        let open = TransactionStage::open();
+
+        Ok(self
+            .transactions
+            .iter()
+            .filter(|t| t.symbol == symbol && open.contains(&t.stage))
+            .count() as i64)
+    }
+
+    fn held(&self, symbol: &str) -> Result<Vec<Transaction>> {
+        Ok(self
+            .transactions
+            .iter()
+            .filter(|t| t.symbol == symbol && t.stage == TransactionStage::Hodl)
+            .cloned()
+            .collect())
+    }
+}
+
+// A round trip: bought, then sold.
+#[derive(Debug, Clone, Serialize)]
+pub struct Trade {
+    pub transaction_id: String,
+    pub symbol: String,
+    pub entry_time: i64,
+    pub exit_time: i64,
+    pub amount: Decimal,
+    pub buy_price: Decimal,
+    pub sell_price: Decimal,
+    pub fees: Decimal,
+}
+
+impl Trade {
+    pub fn pnl(&self) -> Decimal {
+        (self.sell_price - self.buy_price) * self.amount - self.fees
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktestResult {
+    pub symbol: String,
+    pub starting_balance: Decimal,
+    pub trades: Vec<Trade>,
+    // (timestamp, equity in quote currency)
+    pub equity_curve: Vec<(i64, Decimal)>,
+    pub started_at: i64,
+    pub finished_at: i64,
+    // Milliseconds during which coins were held.
+    pub exposed: i64,
+}
+
+impl BacktestResult {
+    pub fn final_equity(&self) -> Decimal {
+        self.equity_curve.last().map(|(_, e)| *e).unwrap_or(self.starting_balance)
+    }
+
+    pub fn pnl(&self) -> Decimal {
+        self.final_equity() - self.starting_balance
+    }
+
+    pub fn realised_pnl(&self) -> Decimal {
+        self.trades.iter().map(Trade::pnl).sum()
+    }
+
+    // Largest drop from a peak of the equity curve, as a fraction of that peak.
+    pub fn max_drawdown(&self) -> Decimal {
+        let mut peak = Decimal::new(0, 0);
+        let mut max_drawdown = Decimal::new(0, 0);
+
+        for (_, equity) in self.equity_curve.iter() {
+            if *equity > peak {
+                peak = *equity;
+            }
+
+            if !peak.is_zero() {
+                max_drawdown = std::cmp::max(max_drawdown, (peak - *equity) / peak);
+            }
+        }
+
+        max_drawdown
+    }
+
+    pub fn win_rate(&self) -> Decimal {
+        if self.trades.is_empty() {
+            return Decimal::new(0, 0);
+        }
+
+        let wins = self.trades.iter().filter(|t| t.pnl() > Decimal::new(0, 0)).count();
+
+        Decimal::from(wins) / Decimal::from(self.trades.len())
+    }
+
+    // Fraction of the backtested period coins were held.
+    pub fn exposure(&self) -> Decimal {
+        let duration = self.finished_at - self.started_at;
+
+        if duration <= 0 {
+            return Decimal::new(0, 0);
+        }
+
+        Decimal::from(self.exposed) / Decimal::from(duration)
+    }
+
+    pub fn print_summary(&self) {
+        println!("Trades for {}:", self.symbol);
+        println!("{:<20} {:<20} {:>14} {:>14} {:>14} {:>14}", "Entry", "Exit", "Amount", "Buy", "Sell", "PnL");
+
+        for trade in self.trades.iter() {
+            println!(
+                "{:<20} {:<20} {:>14} {:>14} {:>14} {:>14}",
+                format_time(trade.entry_time),
+                format_time(trade.exit_time),
+                trade.amount,
+                trade.buy_price,
+                trade.sell_price,
+                trade.pnl().round_dp(4)
+            );
+        }
+
+        println!();
+        println!("Period:        {} - {}", format_time(self.started_at), format_time(self.finished_at));
+        println!("Trades:        {}", self.trades.len());
+        println!("Realised PnL:  {}", self.realised_pnl().round_dp(4));
+        println!("Total PnL:     {} ({} -> {})", self.pnl().round_dp(4), self.starting_balance, self.final_equity().round_dp(4));
+        println!("Max drawdown:  {}%", (self.max_drawdown() * dec!(100)).round_dp(2));
+        println!("Win rate:      {}%", (self.win_rate() * dec!(100)).round_dp(2));
+        println!("Exposure:      {}%", (self.exposure() * dec!(100)).round_dp(2));
+    }
+}
+
+// The time between candles, going by the closest two so gaps in the data don't stretch it.
+fn candle_interval(candles: &[Candle]) -> i64 {
+    candles
+        .windows(2)
+        .map(|pair| pair[1].open_time - pair[0].open_time)
+        .filter(|gap| *gap > 0)
+        .min()
+        .unwrap_or(60_000)
+}
+
+pub fn format_time(timestamp: i64) -> String {
+    NaiveDateTime::from_timestamp(timestamp / 1000, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+// Drives the live strategy over historical books, executing its intents on a simulated exchange.
+pub struct Backtest<F: FillModel> {
+    strategy: SupportBand,
+    fill_model: F,
+    clock: SimulatedClock,
+    portfolio: SimulatedPortfolio,
+    quote_balance: Decimal,
+    starting_balance: Decimal,
+    orders: Vec<SimulatedOrder>,
+    entry_times: HashMap<String, i64>,
+    fees: HashMap<String, Decimal>,
+    trades: Vec<Trade>,
+    equity_curve: Vec<(i64, Decimal)>,
+    started_at: Option<i64>,
+    last_bid: Option<Decimal>,
+    exposed: i64,
+    next_order_id: u64,
+}
+
+impl<F: FillModel> Backtest<F> {
+    pub fn new(strategy: SupportBand, fill_model: F, quote_balance: Decimal) -> Self {
+        Self {
+            strategy,
+            fill_model,
+            clock: SimulatedClock::default(),
+            portfolio: SimulatedPortfolio::default(),
+            quote_balance,
+            starting_balance: quote_balance,
+            orders: vec![],
+            entry_times: HashMap::new(),
+            fees: HashMap::new(),
+            trades: vec![],
+            equity_curve: vec![],
+            started_at: None,
+            last_bid: None,
+            exposed: 0,
+            next_order_id: 1,
+        }
+    }
+
+    // Replays recorded depth data (see `exchanges::mandala::recorder`) for the strategy's pair.
+    pub fn run_depth<P: Into<PathBuf>>(mut self, path: P, quote_currency: &str, from: NaiveDate, to: NaiveDate) -> BacktestResult {
+        let pair = format!("{}{}", self.strategy.symbol, quote_currency);
+        let mut replay = BookReplay::new(&self.strategy.symbol);
+
+        for record in records(path, &pair, from, to) {
+            replay.apply(&record);
+            self.on_book(record.received_at, replay.book());
+        }
+
+        self.finish()
+    }
+
+    // Replays candles as a book with a single level on each side, walking the likely price path of
+    // every candle. `spread` is the relative distance between bid and ask.
+    pub fn run_candles(mut self, candles: &[Candle], spread: Decimal) -> BacktestResult {
+        let mut book = OrderBook::new(&self.strategy.symbol);
+        let interval = candle_interval(candles);
+
+        for candle in candles.iter() {
+            let path = candle.price_path();
+
+            for (i, price) in path.iter().enumerate() {
+                // Spread over the candle, the close is seen just before the next candle opens.
+                let timestamp = candle.open_time + (interval - 1) * i as i64 / (path.len() as i64 - 1);
+                let ask = *price + (*price * spread);
+                book.reload(
+                    vec![Order::new(OrderSide::Buy, candle.volume, *price)],
+                    vec![Order::new(OrderSide::Sell, candle.volume, ask)],
+                );
+                book.validate();
+
+                self.on_book(timestamp, &book);
+            }
+        }
+
+        self.finish()
+    }
+
+    pub fn on_book(&mut self, timestamp: i64, book: &OrderBook) {
+        let previous = self.clock.now();
+        self.clock.advance_to(timestamp);
+        self.started_at.get_or_insert(timestamp);
+
+        if self.portfolio.has_position() && previous > 0 {
+            self.exposed += self.clock.now() - previous;
+        }
+
+        self.match_orders(book);
+
+        if !book.is_tradable() {
+            return;
+        }
+
+        if let (Some(bid), Some(ask)) = (book.highest_bid(), book.lowest_ask()) {
+            self.last_bid = Some(bid);
+
+            for intent in self.strategy.evaluate(bid, ask, &self.portfolio) {
+                self.execute(intent);
+            }
+
+            if self.equity_curve.last().map_or(true, |(at, _)| self.clock.now() - at >= EQUITY_SAMPLE_INTERVAL) {
+                let equity = self.equity(bid);
+                self.equity_curve.push((self.clock.now(), equity));
+            }
+        }
+    }
+
+    // Quote balance, plus what's reserved for open buy orders, plus held coins valued at the bid.
+    fn equity(&self, bid: Decimal) -> Decimal {
+        let reserved: Decimal = self
+            .orders
+            .iter()
+            .filter(|o| o.side == OrderSide::Buy && o.is_open())
+            .map(|o| o.remaining() * o.price)
+            .sum();
+
+        self.quote_balance + reserved + self.holdings() * bid
+    }
+
+    // Coins bought and not sold yet. Until a buy completes only its fills count, and a sell that's
+    // under way only holds what it hasn't sold.
+    fn holdings(&self) -> Decimal {
+        let executed = |transaction: &Transaction, side: OrderSide| -> Decimal {
+            self.orders
+                .iter()
+                .filter(|o| o.transaction_id == transaction.id && o.side == side)
+                .map(|o| o.executed_quantity)
+                .sum()
+        };
+
+        self.portfolio
+            .transactions
+            .iter()
+            .map(|t| match t.stage {
+                TransactionStage::BuyTransactionOpen | TransactionStage::BuyTransactionPartiallyFilled => {
+                    executed(t, OrderSide::Buy)
+                }
+                TransactionStage::Hodl => t.amount,
+                TransactionStage::SellTransactionOpen | TransactionStage::SellTransactionPartiallyFilled => {
+                    t.amount - executed(t, OrderSide::Sell)
+                }
+                TransactionStage::Finished | TransactionStage::Canceled => Decimal::new(0, 0),
+            })
+            .sum()
+    }
+
+    fn execute(&mut self, intent: TransactionIntent) {
+        let now = self.clock.now();
+
+        match intent {
+            TransactionIntent::Buy { symbol, price, .. } => {
+                let amount = match buy_amount(&symbol, price, self.quote_balance) {
+                    Some(amount) => amount,
+                    None => return,
+                };
+
+                let id = uuid::Uuid::new_v4().to_string();
+                let order_id = self.order_id();
+                self.quote_balance -= amount * price;
+
+                self.portfolio.transactions.push(Transaction {
+                    id: id.clone(),
+                    exchange_name: "backtest".to_string(),
+                    buy_exchange_id: Some(order_id.clone()),
+                    sell_exchange_id: None,
+                    amount,
+                    symbol,
+                    price,
+                    stage: TransactionStage::BuyTransactionOpen,
+                    created_at: Some(NaiveDateTime::from_timestamp(now / 1000, 0)),
+                    updated_at: Some(NaiveDateTime::from_timestamp(now / 1000, 0)),
+                });
+
+                self.place(order_id, id, OrderSide::Buy, price, amount);
+            }
+            TransactionIntent::Sell { price, amount, meta, .. } => {
+                let id = meta.existing_transaction.expect("No existing transaction");
+                let order_id = self.order_id();
+
+                if let Some(transaction) = self.portfolio.get_mut(&id) {
+                    transaction.stage = TransactionStage::SellTransactionOpen;
+                    transaction.sell_exchange_id = Some(order_id.clone());
+                }
+
+                self.place(order_id, id, OrderSide::Sell, price, amount);
+            }
+        }
+    }
+
+    fn order_id(&mut self) -> String {
+        let id = self.next_order_id;
+        self.next_order_id += 1;
+
+        id.to_string()
+    }
+
+    fn place(&mut self, id: String, transaction_id: String, side: OrderSide, price: Decimal, quantity: Decimal) {
+        self.orders.push(SimulatedOrder {
+            id,
+            transaction_id,
+            side,
+            price,
+            quantity,
+            executed_quantity: Decimal::new(0, 0),
+            status: OrderStatus::New,
+            placed_at: self.clock.now(),
+        });
+    }
+
+    fn match_orders(&mut self, book: &OrderBook) {
+        let now = self.clock.now();
+        let mut orders = std::mem::take(&mut self.orders);
+
+        for order in orders.iter_mut() {
+            let fill = match self.fill_model.fill(order, book, now) {
+                Some(fill) => fill,
+                None => continue,
+            };
+
+            order.executed_quantity += fill.quantity;
+            order.status = if order.remaining() > Decimal::new(0, 0) {
+                OrderStatus::PartiallyFilled
+            } else {
+                OrderStatus::Filled
+            };
+
+            *self.fees.entry(order.transaction_id.clone()).or_insert_with(|| Decimal::new(0, 0)) += fill.fee;
+
+            match order.side {
+                OrderSide::Buy => {
+                    // The order price was reserved up front, return what was saved by filling lower.
+                    self.quote_balance += (order.price - fill.price) * fill.quantity - fill.fee;
+                    self.on_buy_fill(order, now);
+                }
+                OrderSide::Sell => {
+                    self.quote_balance += fill.price * fill.quantity - fill.fee;
+                    self.on_sell_fill(order, fill.price, now);
+                }
+            }
+        }
+
+        for order in orders.iter().filter(|o| !o.is_open()) {
+            self.fill_model.forget(order);
+        }
+
+        orders.retain(SimulatedOrder::is_open);
+        self.orders = orders;
+    }
+
+    fn on_buy_fill(&mut self, order: &SimulatedOrder, now: i64) {
+        let transaction = match self.portfolio.get_mut(&order.transaction_id) {
+            Some(transaction) => transaction,
+            None => return,
+        };
+
+        if order.status == OrderStatus::Filled {
+            transaction.stage = TransactionStage::Hodl;
+            transaction.amount = order.executed_quantity;
+            self.entry_times.insert(order.transaction_id.clone(), now);
+        } else {
+            transaction.stage = TransactionStage::BuyTransactionPartiallyFilled;
+        }
+    }
+
+    fn on_sell_fill(&mut self, order: &SimulatedOrder, price: Decimal, now: i64) {
+        let transaction = match self.portfolio.get_mut(&order.transaction_id) {
+            Some(transaction) => transaction,
+            None => return,
+        };
+
+        if order.status != OrderStatus::Filled {
+            transaction.stage = TransactionStage::SellTransactionPartiallyFilled;
+
+            return;
+        }
+
+        transaction.stage = TransactionStage::Finished;
+
+        self.trades.push(Trade {
+            transaction_id: transaction.id.clone(),
+            symbol: transaction.symbol.clone(),
+            entry_time: self.entry_times.get(&transaction.id).cloned().unwrap_or(now),
+            exit_time: now,
+            amount: order.executed_quantity,
+            buy_price: transaction.price,
+            sell_price: price,
+            fees: self.fees.get(&transaction.id).cloned().unwrap_or_default(),
+        });
+    }
+
+    pub fn finish(self) -> BacktestResult {
+        // Whatever is still held is valued at its last known price.
+        let final_equity = self.last_bid.map(|bid| self.equity(bid));
+        let mut equity_curve = self.equity_curve;
+
+        if let Some(equity) = final_equity {
+            equity_curve.push((self.clock.now(), equity));
+        }
+
+        BacktestResult {
+            symbol: self.strategy.symbol.clone(),
+            starting_balance: self.starting_balance,
+            trades: self.trades,
+            equity_curve,
+            started_at: self.started_at.unwrap_or(0),
+            finished_at: self.clock.now(),
+            exposed: self.exposed,
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::backtest::fill::ImmediateFillModel;
+use crate::backtest::{Backtest, BacktestResult};
+use crate::bot::trading::strategy::SupportBand;
+use crate::crypto::candle::Candle;
+use anyhow::Result;
+use rand::Rng;
+use rayon::prelude::*;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+// An inclusive `start:end:step` range of values to try, e.g. `1.2:1.4:0.05`. A single value is fine too.
+#[derive(Debug, Clone)]
+pub struct ParameterRange {
+    start: Decimal,
+    end: Decimal,
+    step: Decimal,
+}
+
+impl ParameterRange {
+    pub fn values(&self) -> Vec<Decimal> {
+        let mut values = vec![];
+        let mut value = self.start;
+
+        while value <= self.end {
+            values.push(value);
+
+            if self.step.is_zero() {
+                break;
+            }
+
+            value += self.step;
+        }
+
+        values
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Decimal {
+        let values = self.values();
+
+        values[rng.gen_range(0..values.len())]
+    }
+}
+
+impl FromStr for ParameterRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split(':').map(Decimal::from_str).collect::<Result<Vec<_>, _>>()?;
+
+        match parts.as_slice() {
+            [value] => Ok(Self { start: *value, end: *value, step: Decimal::new(0, 0) }),
+            [start, end, step] if start <= end && *step > Decimal::new(0, 0) => {
+                Ok(Self { start: *start, end: *end, step: *step })
+            }
+            _ => Err(anyhow!("Invalid range {}, expected start:end:step", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parameters {
+    pub support: Decimal,
+    pub profit_wanted: Decimal,
+}
+
+pub enum Search {
+    Grid,
+    // Samples this many parameter sets from the grid.
+    Random(usize),
+}
+
+pub struct Sweep {
+    pub symbol: String,
+    pub support: ParameterRange,
+    pub profit_wanted: ParameterRange,
+    pub max_transactions: i64,
+    pub balance: Decimal,
+    pub fee: Decimal,
+    pub spread: Decimal,
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    pub parameters: Parameters,
+    pub result: BacktestResult,
+    // PnL of these parameters on every walk-forward window, in order.
+    pub window_pnl: Vec<Decimal>,
+}
+
+impl SweepResult {
+    pub fn profitable_windows(&self) -> usize {
+        self.window_pnl.iter().filter(|pnl| **pnl > Decimal::new(0, 0)).count()
+    }
+
+    // Made money over the whole period, but in less than half of the windows.
+    pub fn is_overfit(&self) -> bool {
+        self.result.pnl() > Decimal::new(0, 0) && self.profitable_windows() * 2 < self.window_pnl.len()
+    }
+}
+
+// The walk-forward outcome for a single fold: optimised on one window, tested on the next.
+#[derive(Debug, Clone)]
+pub struct Fold {
+    pub parameters: Parameters,
+    pub in_sample_pnl: Decimal,
+    pub out_of_sample_pnl: Decimal,
+}
+
+impl Sweep {
+    pub fn parameters(&self, search: &Search) -> Vec<Parameters> {
+        match search {
+            Search::Grid => {
+                let profits = self.profit_wanted.values();
+
+                self.support
+                    .values()
+                    .into_iter()
+                    .flat_map(|support| {
+                        profits.iter().map(move |profit_wanted| Parameters {
+                            support,
+                            profit_wanted: *profit_wanted,
+                        })
+                    })
+                    .collect()
+            }
+            Search::Random(count) => {
+                let mut rng = rand::thread_rng();
+                let mut parameters: Vec<Parameters> = vec![];
+
+                for _ in 0..*count {
+                    let candidate = Parameters {
+                        support: self.support.sample(&mut rng),
+                        profit_wanted: self.profit_wanted.sample(&mut rng),
+                    };
+
+                    if !parameters.contains(&candidate) {
+                        parameters.push(candidate);
+                    }
+                }
+
+                parameters
+            }
+        }
+    }
+
+    pub fn backtest(&self, parameters: &Parameters, candles: &[Candle]) -> BacktestResult {
+        let strategy = SupportBand::new(&self.symbol, parameters.support, parameters.profit_wanted, self.max_transactions);
+
+        Backtest::new(strategy, ImmediateFillModel::new(self.fee), self.balance).run_candles(candles, self.spread)
+    }
+
+    // Backtests every parameter set over the whole period and over each of `windows` consecutive
+    // windows, in parallel. Results are ranked by PnL, best first.
+    pub fn run(&self, search: &Search, candles: &[Candle], windows: usize) -> Vec<SweepResult> {
+        let chunks = split(candles, windows);
+
+        let mut results = self
+            .parameters(search)
+            .par_iter()
+            .map(|parameters| SweepResult {
+                parameters: *parameters,
+                result: self.backtest(parameters, candles),
+                window_pnl: chunks
+                    .iter()
+                    .map(|chunk| self.backtest(parameters, chunk).pnl())
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+
+        results.sort_by(|a, b| b.result.pnl().cmp(&a.result.pnl()));
+        results
+    }
+
+    // For every window but the last, picks the best parameters on that window and checks how they
+    // did on the one after it. Out of sample results far below in sample ones mean the sweep is
+    // fitting noise.
+    pub fn walk_forward(results: &[SweepResult]) -> Vec<Fold> {
+        let windows = results.first().map_or(0, |r| r.window_pnl.len());
+
+        (1..windows)
+            .filter_map(|window| {
+                let best = results
+                    .iter()
+                    .max_by(|a, b| a.window_pnl[window - 1].cmp(&b.window_pnl[window - 1]))?;
+
+                Some(Fold {
+                    parameters: best.parameters,
+                    in_sample_pnl: best.window_pnl[window - 1],
+                    out_of_sample_pnl: best.window_pnl[window],
+                })
+            })
+            .collect()
+    }
+}
+
+fn split(candles: &[Candle], windows: usize) -> Vec<&[Candle]> {
+    if windows < 2 || candles.is_empty() {
+        return vec![];
+    }
+
+    let size = (candles.len() + windows - 1) / windows;
+
+    candles.chunks(size).collect()
+}
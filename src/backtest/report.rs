@@ -0,0 +1,394 @@
+use crate::backtest::{format_time, BacktestResult, Trade};
+use crate::database::repository::TransactionRepository;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use hashbrown::HashMap;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+const CHART_WIDTH: f64 = 900.0;
+const CHART_HEIGHT: f64 = 250.0;
+
+// Upper bounds (in hours) of the holding time buckets, the last bucket takes everything longer.
+const HOLDING_BUCKETS: [(i64, &str); 6] = [
+    (1, "< 1h"),
+    (6, "1h - 6h"),
+    (24, "6h - 1d"),
+    (72, "1d - 3d"),
+    (168, "3d - 7d"),
+    (i64::MAX, "> 7d"),
+];
+
+// Everything needed to describe a trading period, whether it was simulated or live.
+#[derive(Debug)]
+pub struct Report {
+    pub title: String,
+    pub starting_balance: Decimal,
+    // (timestamp, equity in quote currency)
+    pub equity_curve: Vec<(i64, Decimal)>,
+    pub trades: Vec<Trade>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoinSummary {
+    pub symbol: String,
+    pub trades: usize,
+    pub pnl: Decimal,
+    pub win_rate: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HoldingBucket {
+    pub label: String,
+    pub trades: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub title: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub starting_balance: Decimal,
+    pub final_equity: Decimal,
+    pub pnl: Decimal,
+    pub max_drawdown: Decimal,
+    pub trades: usize,
+    pub win_rate: Decimal,
+    // In seconds.
+    pub average_holding_time: i64,
+    pub coins: Vec<CoinSummary>,
+    pub holding_times: Vec<HoldingBucket>,
+}
+
+impl Report {
+    pub fn from_backtest(result: &BacktestResult) -> Self {
+        Self {
+            title: format!("Backtest {}", result.symbol),
+            starting_balance: result.starting_balance,
+            equity_curve: result.equity_curve.clone(),
+            trades: result.trades.clone(),
+        }
+    }
+
+    // Builds a report from the transactions in `repository` finished between `from` and `to`.
+    // Live equity isn't stored, so the curve is `starting_balance` plus the realised PnL over time.
+    // Fees aren't stored either, PnL is before fees.
+    pub fn from_repository(repository: &dyn TransactionRepository, starting_balance: Decimal, from: NaiveDateTime, to: NaiveDateTime) -> Result<Self> {
+        let finished = repository.finished_between(from, to)?;
+
+        let millis = |time: Option<NaiveDateTime>| time.map_or(0, |time| time.timestamp_millis());
+
+        let trades = finished
+            .into_iter()
+            .map(|(finished, transaction)| Trade {
+                transaction_id: transaction.id,
+                symbol: transaction.symbol,
+                entry_time: millis(transaction.created_at),
+                exit_time: millis(finished.created_at),
+                amount: finished.amount_sold,
+                buy_price: finished.buy_price,
+                sell_price: finished.sell_price,
+                fees: Decimal::new(0, 0),
+            })
+            .collect::<Vec<_>>();
+
+        let mut equity = starting_balance;
+        let mut equity_curve = vec![(from.timestamp_millis(), equity)];
+
+        for trade in trades.iter() {
+            equity += trade.pnl();
+            equity_curve.push((trade.exit_time, equity));
+        }
+
+        Ok(Self {
+            title: format!("Live {} - {}", from.format("%Y-%m-%d"), to.format("%Y-%m-%d")),
+            starting_balance,
+            equity_curve,
+            trades,
+        })
+    }
+
+    pub fn final_equity(&self) -> Decimal {
+        self.equity_curve.last().map(|(_, e)| *e).unwrap_or(self.starting_balance)
+    }
+
+    // Drop from the running peak at every point of the equity curve, as a fraction of that peak.
+    pub fn drawdown_curve(&self) -> Vec<(i64, Decimal)> {
+        let mut peak = Decimal::new(0, 0);
+
+        self.equity_curve
+            .iter()
+            .map(|(at, equity)| {
+                peak = std::cmp::max(peak, *equity);
+
+                if peak.is_zero() {
+                    (*at, Decimal::new(0, 0))
+                } else {
+                    (*at, (peak - *equity) / peak)
+                }
+            })
+            .collect()
+    }
+
+    pub fn summary(&self) -> Summary {
+        let wins = self.trades.iter().filter(|t| t.pnl() > Decimal::new(0, 0)).count();
+        let holding_time: i64 = self.trades.iter().map(|t| t.exit_time - t.entry_time).sum();
+
+        Summary {
+            title: self.title.clone(),
+            started_at: self.equity_curve.first().map(|(at, _)| format_time(*at)),
+            finished_at: self.equity_curve.last().map(|(at, _)| format_time(*at)),
+            starting_balance: self.starting_balance,
+            final_equity: self.final_equity(),
+            pnl: self.final_equity() - self.starting_balance,
+            max_drawdown: self.drawdown_curve().into_iter().map(|(_, d)| d).max().unwrap_or_default(),
+            trades: self.trades.len(),
+            win_rate: ratio(wins, self.trades.len()),
+            average_holding_time: if self.trades.is_empty() { 0 } else { holding_time / self.trades.len() as i64 / 1000 },
+            coins: self.coins(),
+            holding_times: self.holding_times(),
+        }
+    }
+
+    fn coins(&self) -> Vec<CoinSummary> {
+        let mut by_symbol: HashMap<&str, Vec<&Trade>> = HashMap::new();
+
+        for trade in self.trades.iter() {
+            by_symbol.entry(trade.symbol.as_str()).or_default().push(trade);
+        }
+
+        let mut coins = by_symbol
+            .into_iter()
+            .map(|(symbol, trades)| CoinSummary {
+                symbol: symbol.to_string(),
+                trades: trades.len(),
+                pnl: trades.iter().map(|t| t.pnl()).sum(),
+                win_rate: ratio(trades.iter().filter(|t| t.pnl() > Decimal::new(0, 0)).count(), trades.len()),
+            })
+            .collect::<Vec<_>>();
+
+        coins.sort_by(|a, b| b.pnl.cmp(&a.pnl));
+        coins
+    }
+
+    fn holding_times(&self) -> Vec<HoldingBucket> {
+        let mut counts = [0; HOLDING_BUCKETS.len()];
+
+        for trade in self.trades.iter() {
+            let hours = (trade.exit_time - trade.entry_time) / 3_600_000;
+
+            if let Some(bucket) = HOLDING_BUCKETS.iter().position(|(max, _)| hours < *max) {
+                counts[bucket] += 1;
+            }
+        }
+
+        HOLDING_BUCKETS
+            .iter()
+            .zip(counts.iter())
+            .map(|((_, label), trades)| HoldingBucket { label: label.to_string(), trades: *trades })
+            .collect()
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(&self.summary())?)?;
+
+        Ok(())
+    }
+
+    // A single HTML file without any external resources, charts are inline SVG.
+    pub fn write_html<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.html()?)?;
+
+        Ok(())
+    }
+
+    // Writes `<path>.html` and `<path>.json`. The extensions are appended, so a dotted name like
+    // `report-0.5` keeps its last part.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let with = |extension: &str| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".");
+            name.push(extension);
+            PathBuf::from(name)
+        };
+
+        self.write_html(with("html"))?;
+        self.write_json(with("json"))
+    }
+
+    fn html(&self) -> Result<String> {
+        let summary = self.summary();
+        let mut html = String::new();
+
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(html, "<html><head><meta charset=\"utf-8\"><title>{}</title>", escape(&self.title))?;
+        writeln!(
+            html,
+            "<style>body{{font-family:sans-serif;margin:2em;}}table{{border-collapse:collapse;margin-bottom:2em;}}\
+             td,th{{padding:4px 10px;border-bottom:1px solid #ddd;text-align:right;}}th{{background:#f4f4f4;}}</style>"
+        )?;
+        writeln!(html, "</head><body>")?;
+        writeln!(html, "<h1>{}</h1>", escape(&self.title))?;
+
+        writeln!(html, "<table>")?;
+        let rows = vec![
+            ("Period", format!("{} - {}", summary.started_at.clone().unwrap_or_default(), summary.finished_at.clone().unwrap_or_default())),
+            ("Starting balance", summary.starting_balance.round_dp(4).to_string()),
+            ("Final equity", summary.final_equity.round_dp(4).to_string()),
+            ("PnL", summary.pnl.round_dp(4).to_string()),
+            ("Max drawdown", format!("{}%", (summary.max_drawdown * dec!(100)).round_dp(2))),
+            ("Trades", summary.trades.to_string()),
+            ("Win rate", format!("{}%", (summary.win_rate * dec!(100)).round_dp(2))),
+            ("Average holding time", format_duration(summary.average_holding_time)),
+        ];
+        for (name, value) in rows {
+            writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", name, escape(&value))?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "<h2>Equity</h2>")?;
+        writeln!(html, "{}", line_chart(&self.equity_curve, "#2a7ae2"))?;
+
+        writeln!(html, "<h2>Drawdown</h2>")?;
+        let drawdown = self
+            .drawdown_curve()
+            .into_iter()
+            .map(|(at, d)| (at, -d * dec!(100)))
+            .collect::<Vec<_>>();
+        writeln!(html, "{}", line_chart(&drawdown, "#d9534f"))?;
+
+        writeln!(html, "<h2>PnL per coin</h2>")?;
+        writeln!(html, "<table><tr><th>Coin</th><th>Trades</th><th>PnL</th><th>Win rate</th></tr>")?;
+        for coin in summary.coins.iter() {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}%</td></tr>",
+                escape(&coin.symbol),
+                coin.trades,
+                coin.pnl.round_dp(4),
+                (coin.win_rate * dec!(100)).round_dp(2)
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "<h2>Holding times</h2>")?;
+        writeln!(html, "{}", bar_chart(&summary.holding_times))?;
+
+        writeln!(html, "<h2>Trades</h2>")?;
+        writeln!(html, "<table><tr><th>Coin</th><th>Entry</th><th>Exit</th><th>Amount</th><th>Buy</th><th>Sell</th><th>Fees</th><th>PnL</th></tr>")?;
+        for trade in self.trades.iter() {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&trade.symbol),
+                format_time(trade.entry_time),
+                format_time(trade.exit_time),
+                trade.amount,
+                trade.buy_price,
+                trade.sell_price,
+                trade.fees.round_dp(4),
+                trade.pnl().round_dp(4)
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "</body></html>")?;
+
+        Ok(html)
+    }
+}
+
+fn ratio(part: usize, total: usize) -> Decimal {
+    if total == 0 {
+        return Decimal::new(0, 0);
+    }
+
+    Decimal::from(part) / Decimal::from(total)
+}
+
+fn format_duration(seconds: i64) -> String {
+    format!("{}d {}h {}m", seconds / 86_400, seconds % 86_400 / 3_600, seconds % 3_600 / 60)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn line_chart(points: &[(i64, Decimal)], colour: &str) -> String {
+    if points.len() < 2 {
+        return "<p>Not enough data.</p>".to_string();
+    }
+
+    let values = points
+        .iter()
+        .map(|(at, value)| (*at as f64, value.to_f64().unwrap_or(0.0)))
+        .collect::<Vec<_>>();
+
+    let (first, last) = (values[0].0, values[values.len() - 1].0);
+    let low = values.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min);
+    let high = values.iter().map(|(_, v)| *v).fold(f64::NEG_INFINITY, f64::max);
+    let span = if (last - first).abs() < f64::EPSILON { 1.0 } else { last - first };
+    let range = if (high - low).abs() < f64::EPSILON { 1.0 } else { high - low };
+
+    let path = values
+        .iter()
+        .map(|(at, value)| {
+            format!(
+                "{:.1},{:.1}",
+                (at - first) / span * CHART_WIDTH,
+                CHART_HEIGHT - (value - low) / range * CHART_HEIGHT
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 -10 {w} {h2}\">\
+         <polyline fill=\"none\" stroke=\"{colour}\" stroke-width=\"1.5\" points=\"{path}\"/>\
+         <text x=\"0\" y=\"0\" font-size=\"11\">{high:.4}</text>\
+         <text x=\"0\" y=\"{h}\" font-size=\"11\">{low:.4}</text>\
+         </svg>",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        h2 = CHART_HEIGHT + 20.0,
+        colour = colour,
+        path = path,
+        high = high,
+        low = low
+    )
+}
+
+fn bar_chart(buckets: &[HoldingBucket]) -> String {
+    let most = buckets.iter().map(|b| b.trades).max().unwrap_or(0).max(1) as f64;
+    let width = CHART_WIDTH / buckets.len().max(1) as f64;
+    let mut svg = format!("<svg width=\"{}\" height=\"{}\">", CHART_WIDTH, CHART_HEIGHT + 40.0);
+
+    for (i, bucket) in buckets.iter().enumerate() {
+        let height = bucket.trades as f64 / most * CHART_HEIGHT;
+        let x = i as f64 * width;
+
+        svg.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#5cb85c\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" text-anchor=\"middle\">{} ({})</text>",
+            x + 5.0,
+            CHART_HEIGHT - height + 10.0,
+            width - 10.0,
+            height,
+            x + width / 2.0,
+            CHART_HEIGHT + 30.0,
+            escape(&bucket.label),
+            bucket.trades
+        ));
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
@@ -0,0 +1,125 @@
+#[macro_use]
+extern crate log;
+
+use chrono::NaiveDate;
+use poppy::backtest::fill::{FillModel, ImmediateFillModel, QueueFillModel};
+use poppy::backtest::report::Report;
+use poppy::backtest::{Backtest, BacktestResult};
+use poppy::bot::trading::strategy::SupportBand;
+use poppy::crypto::candle;
+use poppy::CONFIG;
+use rust_decimal::Decimal;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "backtest", about = "Runs the support band strategy of a coin over historical data.")]
+struct Opt {
+    /// Base symbol of the coin, as configured in config.json (e.g. ADA)
+    #[structopt(long)]
+    symbol: String,
+
+    /// Directory with depth recordings to replay
+    #[structopt(long, parse(from_os_str), required_unless = "candles")]
+    depth: Option<PathBuf>,
+
+    /// First day of depth recordings to replay (YYYY-MM-DD)
+    #[structopt(long)]
+    from: Option<NaiveDate>,
+
+    /// Last day of depth recordings to replay (YYYY-MM-DD), defaults to --from
+    #[structopt(long)]
+    to: Option<NaiveDate>,
+
+    /// OHLCV CSV file to replay instead of depth recordings
+    #[structopt(long, parse(from_os_str), conflicts_with = "depth")]
+    candles: Option<PathBuf>,
+
+    /// Quote currency balance to start with
+    #[structopt(long, default_value = "1000")]
+    balance: Decimal,
+
+    /// Fee per fill, as a fraction of the traded value. The taker fee with --queue
+    #[structopt(long, default_value = "0.001")]
+    fee: Decimal,
+
+    /// Simulate latency, queue position and partial fills instead of filling orders as soon as they cross
+    #[structopt(long)]
+    queue: bool,
+
+    /// Milliseconds before an order reaches the exchange, with --queue
+    #[structopt(long, default_value = "100")]
+    latency: i64,
+
+    /// Fee for resting orders that get filled, with --queue. Defaults to --fee
+    #[structopt(long)]
+    maker_fee: Option<Decimal>,
+
+    /// Distance between bid and ask when replaying candles, as a fraction of the price
+    #[structopt(long, default_value = "0.001")]
+    spread: Decimal,
+
+    /// Writes an HTML report and JSON summary to this path (.html and .json are added)
+    #[structopt(long, parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    /// Overrides the configured support
+    #[structopt(long)]
+    support: Option<Decimal>,
+
+    /// Overrides the configured profit_wanted
+    #[structopt(long)]
+    profit_wanted: Option<Decimal>,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let coin = CONFIG
+        .coins
+        .iter()
+        .find(|coin| coin.symbol == opt.symbol)
+        .ok_or_else(|| anyhow::anyhow!("Couldn't find coin {} in config", opt.symbol))?;
+
+    let strategy = SupportBand::new(
+        &coin.symbol,
+        opt.support.unwrap_or(coin.support),
+        opt.profit_wanted.unwrap_or(coin.profit_wanted),
+        CONFIG.max_transaction_per_coin,
+    );
+
+    info!(
+        "Backtesting {} with support {} and profit wanted {}",
+        &strategy.symbol,
+        strategy.support,
+        strategy.profit_wanted
+    );
+
+    let result = if opt.queue {
+        let fill_model = QueueFillModel::new(opt.latency, opt.maker_fee.unwrap_or(opt.fee), opt.fee);
+        run(&opt, Backtest::new(strategy, fill_model, opt.balance))?
+    } else {
+        run(&opt, Backtest::new(strategy, ImmediateFillModel::new(opt.fee), opt.balance))?
+    };
+
+    result.print_summary();
+
+    if let Some(path) = &opt.report {
+        Report::from_backtest(&result).write(path)?;
+        info!("Wrote report to {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn run<F: FillModel>(opt: &Opt, backtest: Backtest<F>) -> anyhow::Result<BacktestResult> {
+    Ok(match (&opt.candles, &opt.depth) {
+        (Some(path), _) => backtest.run_candles(&candle::read_csv(path)?, opt.spread),
+        (None, Some(path)) => {
+            let from = opt.from.ok_or_else(|| anyhow::anyhow!("--from is required with --depth"))?;
+            backtest.run_depth(path, &CONFIG.quote_currency, from, opt.to.unwrap_or(from))
+        }
+        (None, None) => unreachable!(),
+    })
+}
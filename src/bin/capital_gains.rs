@@ -0,0 +1,55 @@
+#[macro_use]
+extern crate log;
+
+use poppy::database::lots::{self, CostMethod, LotBook};
+use poppy::{CONFIG, DATABASE};
+use rust_decimal::Decimal;
+use std::fs::File;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "capital-gains", about = "Exports the disposals of a year with their cost basis and gain, for tax filing.")]
+struct Opt {
+    /// Year the disposals were made in
+    #[structopt(long)]
+    year: i32,
+
+    /// How sales are matched to lots: fifo, lifo or average
+    #[structopt(long, default_value = "fifo")]
+    method: CostMethod,
+
+    /// CSV file to write, defaults to stdout
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    // Lots bought in earlier years can be sold in this one, so the whole history gets replayed.
+    let events = lots::load_events(&DATABASE.get_connection(), &CONFIG.quote_currency)?;
+    let book = LotBook::from_events(opt.method, events);
+    let disposals = book.disposals_in(opt.year);
+
+    match &opt.output {
+        Some(path) => lots::write_csv(&disposals, File::create(path)?)?,
+        None => lots::write_csv(&disposals, std::io::stdout())?,
+    }
+
+    let proceeds: Decimal = disposals.iter().map(|d| d.proceeds).sum();
+    let gains: Decimal = disposals.iter().map(|d| d.gain()).sum();
+
+    info!(
+        "{} disposals in {}, proceeds {} {}, gains {} {}",
+        disposals.len(),
+        opt.year,
+        proceeds.round_dp(2),
+        CONFIG.quote_currency,
+        gains.round_dp(2),
+        CONFIG.quote_currency
+    );
+
+    Ok(())
+}
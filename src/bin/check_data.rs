@@ -0,0 +1,45 @@
+use poppy::database::check;
+use poppy::DATABASE;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "check-data", about = "Flags transactions corrupted by the old double(8,2) monetary columns.")]
+struct Opt {
+    /// Overwrite flagged transaction amounts and prices with what their fills add up to
+    #[structopt(long)]
+    repair: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let connection = DATABASE.get_connection();
+    let mut problems = check::check_transactions(&connection)?;
+    problems.extend(check::check_finished_transactions(&connection)?);
+
+    for problem in problems.iter() {
+        println!(
+            "{:<22} {:<36} {:<14} {:>24}  {}{}",
+            problem.table,
+            problem.id,
+            problem.column,
+            problem.value,
+            problem.reason,
+            problem.repair.map(|value| format!(" (repairable: {})", value)).unwrap_or_default()
+        );
+    }
+
+    println!("{} problems found", problems.len());
+
+    if opt.repair {
+        let repaired = check::repair(&connection, &problems)?;
+        println!("Repaired {} values from fills", repaired);
+    }
+
+    if !problems.is_empty() && !opt.repair {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
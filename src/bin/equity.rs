@@ -0,0 +1,69 @@
+use chrono::{NaiveDateTime, Utc};
+use poppy::database::equity;
+use poppy::DATABASE;
+use rust_decimal::Decimal;
+use structopt::StructOpt;
+
+fn parse_time(s: &str) -> anyhow::Result<NaiveDateTime> {
+    Ok(NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")?)
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "equity", about = "Shows what the portfolio was worth, from the equity snapshots.")]
+struct Opt {
+    /// Exchange the snapshots were taken on
+    #[structopt(long, default_value = "mandala")]
+    exchange: String,
+
+    /// Show the balances of the last snapshot before this time ("YYYY-MM-DD HH:MM:SS", UTC), defaults to now
+    #[structopt(long, parse(try_from_str = parse_time))]
+    at: Option<NaiveDateTime>,
+
+    /// Show the total value of every snapshot since this time instead ("YYYY-MM-DD HH:MM:SS", UTC)
+    #[structopt(long, parse(try_from_str = parse_time), conflicts_with = "at")]
+    from: Option<NaiveDateTime>,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let connection = DATABASE.get_connection();
+    let now = Utc::now().naive_utc();
+
+    if let Some(from) = opt.from {
+        for (taken_at, value) in equity::equity_curve(&connection, &opt.exchange, from, now)? {
+            println!("{}  {:>16}", taken_at.format("%Y-%m-%d %H:%M:%S"), value.round_dp(4));
+        }
+
+        return Ok(());
+    }
+
+    let snapshot = equity::snapshot_at(&connection, &opt.exchange, opt.at.unwrap_or(now))?;
+
+    let taken_at = match snapshot.first() {
+        Some(row) => row.taken_at,
+        None => {
+            println!("No snapshots of {} before then.", &opt.exchange);
+            return Ok(());
+        }
+    };
+
+    println!("Snapshot of {} taken at {}", &opt.exchange, taken_at.format("%Y-%m-%d %H:%M:%S"));
+    println!("{:<8} {:>18} {:>18} {:>16} {:>16}", "Asset", "Free", "Locked", "Price", "Value");
+
+    for row in snapshot.iter() {
+        println!(
+            "{:<8} {:>18} {:>18} {:>16} {:>16}",
+            row.asset,
+            row.free,
+            row.locked,
+            row.price.map_or("-".to_string(), |price| price.to_string()),
+            row.value.map_or("-".to_string(), |value| value.round_dp(4).to_string())
+        );
+    }
+
+    let total: Decimal = snapshot.iter().filter_map(|row| row.value).sum();
+    println!("Total: {}", total.round_dp(4));
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use poppy::crypto::candle::{self, Interval};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "import-candles", about = "Imports OHLCV candles into the candles table.")]
+struct Opt {
+    /// Exchange the candles come from
+    #[structopt(long, default_value = "binance")]
+    exchange: String,
+
+    /// Pair the candles are for, e.g. ADAUSDT
+    #[structopt(long)]
+    symbol: String,
+
+    /// Candle interval, e.g. 1m, 1h, 1d, 1M
+    #[structopt(long)]
+    interval: String,
+
+    /// Files are `open_time,open,high,low,close,volume` CSVs with a header instead of Binance kline archives
+    #[structopt(long)]
+    csv: bool,
+
+    /// Files to import (Binance archives may be .zip or the .csv inside)
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let interval: Interval = opt.interval.parse()?;
+
+    let mut candles = vec![];
+    for file in opt.files.iter() {
+        let mut read = if opt.csv {
+            candle::read_csv(file)?
+        } else {
+            candle::read_binance_klines(file)?
+        };
+
+        println!("Read {} candles from {}", read.len(), file.display());
+        candles.append(&mut read);
+    }
+
+    let read = candles.len();
+    candle::deduplicate(&mut candles);
+    if read != candles.len() {
+        println!("Dropped {} duplicate candles", read - candles.len());
+    }
+
+    let (first, last) = match (candles.first(), candles.last()) {
+        (Some(first), Some(last)) => (first.open_time, last.open_time),
+        _ => {
+            println!("Nothing to import.");
+            return Ok(());
+        }
+    };
+
+    candle::store_candles(&opt.exchange, &opt.symbol, interval, &candles)?;
+    println!("Stored {} {} candles for {} on {}", candles.len(), interval, &opt.symbol, &opt.exchange);
+
+    // Check the whole stored range, so gaps between this and earlier imports show up too.
+    let stored = candle::load_candles(&opt.exchange, &opt.symbol, interval, first, last)?;
+    let gaps = candle::find_gaps(&stored, interval);
+
+    for (from, to) in gaps.iter() {
+        let missing = interval.count(*from, *to);
+        println!(
+            "Gap: {} candles missing from {} to {}",
+            missing,
+            poppy::backtest::format_time(*from),
+            poppy::backtest::format_time(*to)
+        );
+    }
+
+    if gaps.is_empty() {
+        println!("No gaps between {} and {}", poppy::backtest::format_time(first), poppy::backtest::format_time(last));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,165 @@
+#[macro_use]
+extern crate log;
+
+use poppy::backtest::format_time;
+use poppy::backtest::optimise::{ParameterRange, Search, Sweep};
+use poppy::crypto::candle::{self, Interval};
+use poppy::CONFIG;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "optimise", about = "Sweeps support and profit_wanted settings of the support band strategy over historical candles.")]
+struct Opt {
+    /// Base symbol of the coin (e.g. ADA)
+    #[structopt(long)]
+    symbol: String,
+
+    /// OHLCV CSV file to replay
+    #[structopt(long, parse(from_os_str), required_unless = "stored")]
+    candles: Option<PathBuf>,
+
+    /// Replay candles of this interval from the database instead (e.g. 1m)
+    #[structopt(long, conflicts_with = "candles")]
+    stored: Option<Interval>,
+
+    /// Exchange the stored candles were imported for
+    #[structopt(long, default_value = "binance")]
+    exchange: String,
+
+    /// First stored candle to replay, in milliseconds since the epoch
+    #[structopt(long, default_value = "0")]
+    from: i64,
+
+    /// Last stored candle to replay, in milliseconds since the epoch
+    #[structopt(long, default_value = "9223372036854775807")]
+    to: i64,
+
+    /// Supports to try, as start:end:step
+    #[structopt(long)]
+    support: ParameterRange,
+
+    /// Profits wanted to try, as start:end:step
+    #[structopt(long)]
+    profit_wanted: ParameterRange,
+
+    /// Try this many random settings from the ranges instead of all of them
+    #[structopt(long)]
+    random: Option<usize>,
+
+    /// Number of consecutive windows for walk-forward validation
+    #[structopt(long, default_value = "4")]
+    windows: usize,
+
+    /// Number of settings to show
+    #[structopt(long, default_value = "20")]
+    top: usize,
+
+    /// Quote currency balance to start with
+    #[structopt(long, default_value = "1000")]
+    balance: Decimal,
+
+    /// Fee per fill, as a fraction of the traded value
+    #[structopt(long, default_value = "0.001")]
+    fee: Decimal,
+
+    /// Distance between bid and ask when replaying candles, as a fraction of the price
+    #[structopt(long, default_value = "0.001")]
+    spread: Decimal,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let candles = match (&opt.candles, &opt.stored) {
+        (Some(path), _) => candle::read_csv(path)?,
+        (None, Some(interval)) => candle::load_candles(&opt.exchange, &format!("{}{}", opt.symbol, CONFIG.quote_currency), *interval, opt.from, opt.to)?,
+        (None, None) => unreachable!(),
+    };
+
+    if candles.is_empty() {
+        return Err(anyhow::anyhow!("No candles to replay"));
+    }
+
+    let search = match opt.random {
+        Some(count) => Search::Random(count),
+        None => Search::Grid,
+    };
+
+    let sweep = Sweep {
+        symbol: opt.symbol.clone(),
+        support: opt.support.clone(),
+        profit_wanted: opt.profit_wanted.clone(),
+        max_transactions: CONFIG.max_transaction_per_coin,
+        balance: opt.balance,
+        fee: opt.fee,
+        spread: opt.spread,
+    };
+
+    info!(
+        "Sweeping {} settings over {} candles ({} - {})",
+        sweep.parameters(&search).len(),
+        candles.len(),
+        format_time(candles[0].open_time),
+        format_time(candles[candles.len() - 1].open_time)
+    );
+
+    let results = sweep.run(&search, &candles, opt.windows);
+
+    println!(
+        "{:>10} {:>14} {:>14} {:>14} {:>8} {:>10} {:>10}",
+        "Support", "Profit wanted", "PnL", "Max drawdown", "Trades", "Win rate", "Windows"
+    );
+
+    for result in results.iter().take(opt.top) {
+        println!(
+            "{:>10} {:>14} {:>14} {:>13}% {:>8} {:>9}% {:>10} {}",
+            result.parameters.support,
+            result.parameters.profit_wanted,
+            result.result.pnl().round_dp(4),
+            (result.result.max_drawdown() * dec!(100)).round_dp(2),
+            result.result.trades.len(),
+            (result.result.win_rate() * dec!(100)).round_dp(2),
+            format!("{}/{}", result.profitable_windows(), result.window_pnl.len()),
+            if result.is_overfit() { "OVERFIT" } else { "" }
+        );
+    }
+
+    let folds = Sweep::walk_forward(&results);
+
+    if folds.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("Walk-forward:");
+    println!("{:>6} {:>10} {:>14} {:>14} {:>14}", "Window", "Support", "Profit wanted", "In sample", "Out of sample");
+
+    for (i, fold) in folds.iter().enumerate() {
+        println!(
+            "{:>6} {:>10} {:>14} {:>14} {:>14}",
+            i + 2,
+            fold.parameters.support,
+            fold.parameters.profit_wanted,
+            fold.in_sample_pnl.round_dp(4),
+            fold.out_of_sample_pnl.round_dp(4)
+        );
+    }
+
+    let in_sample: Decimal = folds.iter().map(|fold| fold.in_sample_pnl).sum();
+    let out_of_sample: Decimal = folds.iter().map(|fold| fold.out_of_sample_pnl).sum();
+
+    println!();
+    println!("In sample PnL:      {}", in_sample.round_dp(4));
+    println!("Out of sample PnL:  {}", out_of_sample.round_dp(4));
+
+    // Settings picked on past data should keep at least half their edge on the data that follows.
+    if in_sample > Decimal::new(0, 0) && out_of_sample * dec!(2) < in_sample {
+        println!("Out of sample results fall well short of in sample ones, the best settings are likely overfit.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,212 @@
+#[macro_use]
+extern crate log;
+
+use chrono::{NaiveDate, Utc};
+use hashbrown::HashMap;
+use poppy::database::pnl::{Period, PnlReport};
+use poppy::exchanges::mandala::Mandala;
+use poppy::{CONFIG, DATABASE};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(anyhow::anyhow!("Unknown format {}, expected table, json or csv", s)),
+        }
+    }
+}
+
+// What held coins are valued at.
+enum Mark {
+    // Middle of the best bid and ask of the order book.
+    Mid,
+    // Price of the last trade.
+    Last,
+    None,
+}
+
+impl FromStr for Mark {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "mid" => Ok(Mark::Mid),
+            "last" => Ok(Mark::Last),
+            "none" => Ok(Mark::None),
+            _ => Err(anyhow::anyhow!("Unknown mark {}, expected mid, last or none", s)),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "pnl", about = "Reports realised PnL of finished transactions and unrealised PnL of held coins.")]
+struct Opt {
+    /// First day to report on (YYYY-MM-DD), defaults to the first transaction
+    #[structopt(long)]
+    from: Option<NaiveDate>,
+
+    /// Last day to report on (YYYY-MM-DD), defaults to today
+    #[structopt(long)]
+    to: Option<NaiveDate>,
+
+    /// Group realised PnL by day, week or month
+    #[structopt(long, default_value = "day")]
+    period: Period,
+
+    /// Output as table, json or csv
+    #[structopt(long, default_value = "table")]
+    format: Format,
+
+    /// Which rows to write as csv: trades, coins, periods or positions
+    #[structopt(long, default_value = "periods")]
+    rows: String,
+
+    /// Value held coins at the order book mid, the last traded price or not at all (mid, last, none)
+    #[structopt(long, default_value = "mid")]
+    mark: Mark,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let from = opt.from.unwrap_or_else(|| NaiveDate::from_ymd(1970, 1, 1)).and_hms(0, 0, 0);
+    let to = opt.to.unwrap_or_else(|| Utc::today().naive_utc()).and_hms(23, 59, 59);
+
+    let mut report = PnlReport::load(&DATABASE.get_connection(), from, to, CONFIG.quote_currency.clone())?;
+    let mut prices = HashMap::new();
+
+    for symbol in report.held_symbols() {
+        let price = match opt.mark {
+            Mark::Mid => Mandala::fetch_mid_price(&symbol).await,
+            Mark::Last => Mandala::fetch_last_price(&symbol).await.map(Some),
+            Mark::None => break,
+        };
+
+        match price {
+            Ok(Some(price)) => {
+                prices.insert(symbol, price);
+            }
+            Ok(None) => warn!("No price for {}, its positions are left unmarked", &symbol),
+            Err(error) => warn!("Error fetching the price of {}: {:?}", &symbol, error),
+        }
+    }
+
+    report.mark(&prices);
+
+    match opt.format {
+        Format::Table => print_table(&report, opt.period),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report.summary(opt.period))?),
+        Format::Csv => match opt.rows.as_str() {
+            "trades" => write_csv(&report.trade_rows())?,
+            "coins" => write_csv(&report.coins())?,
+            "periods" => write_csv(&report.periods(opt.period))?,
+            "positions" => write_csv(&report.position_rows())?,
+            rows => return Err(anyhow::anyhow!("Unknown rows {}, expected trades, coins, periods or positions", rows)),
+        },
+    }
+
+    Ok(())
+}
+
+fn write_csv<R: Serialize>(rows: &[R]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+
+    for row in rows.iter() {
+        writer.serialize(row)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn optional(value: Option<Decimal>) -> String {
+    value.map_or("-".to_string(), |value| value.round_dp(4).to_string())
+}
+
+fn print_table(report: &PnlReport, period: Period) {
+    let summary = report.summary(period);
+
+    println!("PnL from {} to {} in {}", summary.from, summary.to, summary.quote_currency);
+    println!();
+    println!("Trades:             {}", summary.trades);
+    println!("Realised PnL:       {}", summary.realised_pnl.round_dp(4));
+    println!("Unrealised PnL:     {}", optional(summary.unrealised_pnl));
+    println!("Fees:               {}", summary.fees.round_dp(4));
+    println!("Win rate:           {}%", (summary.win_rate * dec!(100)).round_dp(2));
+    println!("Average hold time:  {:.1}h", summary.average_hold_seconds as f64 / 3600.0);
+
+    println!();
+    println!(
+        "{:<8} {:>8} {:>14} {:>12} {:>10} {:>10} {:>14} {:>14}",
+        "Coin", "Trades", "Realised", "Fees", "Win rate", "Hold (h)", "Held", "Unrealised"
+    );
+
+    for coin in summary.coins.iter() {
+        println!(
+            "{:<8} {:>8} {:>14} {:>12} {:>9}% {:>10.1} {:>14} {:>14}",
+            coin.symbol,
+            coin.trades,
+            coin.realised_pnl.round_dp(4),
+            coin.fees.round_dp(4),
+            (coin.win_rate * dec!(100)).round_dp(2),
+            coin.average_hold_seconds as f64 / 3600.0,
+            coin.held,
+            optional(coin.unrealised_pnl)
+        );
+    }
+
+    println!();
+    println!("{:<12} {:>8} {:>14} {:>12} {:>10}", "Period", "Trades", "Realised", "Fees", "Win rate");
+
+    for row in summary.periods.iter() {
+        println!(
+            "{:<12} {:>8} {:>14} {:>12} {:>9}%",
+            row.period,
+            row.trades,
+            row.realised_pnl.round_dp(4),
+            row.fees.round_dp(4),
+            (row.win_rate * dec!(100)).round_dp(2)
+        );
+    }
+
+    if summary.positions.is_empty() {
+        return;
+    }
+
+    println!();
+    println!(
+        "{:<36} {:<8} {:<20} {:>14} {:>12} {:>12} {:>14}",
+        "Transaction", "Coin", "Held since", "Amount", "Buy price", "Mark", "Unrealised"
+    );
+
+    for position in summary.positions.iter() {
+        println!(
+            "{:<36} {:<8} {:<20} {:>14} {:>12} {:>12} {:>14}",
+            position.transaction_id,
+            position.symbol,
+            position.held_since.clone().unwrap_or_default(),
+            position.amount,
+            position.buy_price,
+            optional(position.mark_price),
+            optional(position.unrealised_pnl)
+        );
+    }
+}
@@ -0,0 +1,42 @@
+#[macro_use]
+extern crate log;
+
+use chrono::{NaiveDate, Utc};
+use poppy::backtest::report::Report;
+use poppy::database::repository::DieselTransactionRepository;
+use rust_decimal::Decimal;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "report", about = "Writes an HTML report and JSON summary of live trading from the database.")]
+struct Opt {
+    /// First day to report on (YYYY-MM-DD)
+    #[structopt(long)]
+    from: NaiveDate,
+
+    /// Last day to report on (YYYY-MM-DD), defaults to today
+    #[structopt(long)]
+    to: Option<NaiveDate>,
+
+    /// Quote currency balance at the start of the period, the equity curve builds on it
+    #[structopt(long, default_value = "0")]
+    balance: Decimal,
+
+    /// Where to write the report (.html and .json are added)
+    #[structopt(long, parse(from_os_str), default_value = "report")]
+    output: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let to = opt.to.unwrap_or_else(|| Utc::today().naive_utc());
+    let report = Report::from_repository(&DieselTransactionRepository, opt.balance, opt.from.and_hms(0, 0, 0), to.and_hms(23, 59, 59))?;
+
+    report.write(&opt.output)?;
+    info!("Wrote report on {} trades to {}", report.trades.len(), opt.output.display());
+
+    Ok(())
+}
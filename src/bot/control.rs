@@ -0,0 +1,407 @@
+use crate::bot::trading::strategy::SupportBand;
+use crate::bot::Poppy;
+use crate::crypto::orderbook::OrderBook;
+use crate::crypto::treasury::{ExecutableTransaction, TransactionIntent, TransactionMeta};
+use crate::database::pnl::PnlReport;
+use crate::database::repository::TransactionRepository;
+use crate::database::{Transaction, TransactionStage};
+use crate::exchanges::Exchange;
+use crate::notify::{Notification, Notifier};
+use crate::CONFIG;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use hashbrown::{HashMap, HashSet};
+use parking_lot::{Mutex as SyncMutex, RwLock};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+// Switches the intent handler checks before acting on what the brokers want to do.
+#[derive(Debug, Default)]
+pub struct Controls {
+    paused: RwLock<HashSet<String>>,
+    buying_paused: AtomicBool,
+    // What the bot stopped doing by itself, like trading on a book out of sync. Remembered so each
+    // halt is announced once when it starts and once when it ends.
+    halts: RwLock<HashSet<String>>,
+}
+
+impl Controls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A paused coin neither buys nor sells.
+    pub fn pause_coin<T: Into<String>>(&self, symbol: T) {
+        self.paused.write().insert(symbol.into());
+    }
+
+    pub fn resume_coin(&self, symbol: &str) {
+        self.paused.write().remove(symbol);
+    }
+
+    pub fn is_paused(&self, symbol: &str) -> bool {
+        self.paused.read().contains(symbol)
+    }
+
+    pub fn paused_coins(&self) -> Vec<String> {
+        let mut paused = self.paused.read().iter().cloned().collect::<Vec<_>>();
+
+        paused.sort();
+        paused
+    }
+
+    // Stops every coin from buying, held coins still get sold.
+    pub fn pause_buying(&self) {
+        self.buying_paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume_buying(&self) {
+        self.buying_paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_buying_paused(&self) -> bool {
+        self.buying_paused.load(Ordering::SeqCst)
+    }
+
+    // Records whether `halt` holds now. True when that changed, so it's worth telling someone.
+    pub fn set_halted<T: Into<String>>(&self, halt: T, halted: bool) -> bool {
+        let halt = halt.into();
+
+        if halted {
+            self.halts.write().insert(halt)
+        } else {
+            self.halts.write().remove(&halt)
+        }
+    }
+
+    pub fn allows(&self, intent: &TransactionIntent) -> bool {
+        match intent {
+            TransactionIntent::Buy { symbol, .. } => !self.is_buying_paused() && !self.is_paused(symbol),
+            TransactionIntent::Sell { symbol, .. } => !self.is_paused(symbol),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookView {
+    pub exchange: String,
+    pub symbol: String,
+    pub tradable: bool,
+    // (price, quantity), best first.
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceView {
+    pub exchange: String,
+    pub asset: String,
+    pub available: Decimal,
+    pub locked: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BrokerView {
+    pub symbol: String,
+    pub support: Decimal,
+    pub profit_wanted: Decimal,
+    pub lower: Decimal,
+    pub upper: Decimal,
+    pub max_transactions: i64,
+    pub paused: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookHealth {
+    pub exchange: String,
+    pub symbol: String,
+    pub valid: bool,
+    pub stale: bool,
+    pub seconds_since_update: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub uptime_seconds: u64,
+    pub buying_paused: bool,
+    pub paused_coins: Vec<String>,
+    pub books: Vec<BookHealth>,
+}
+
+// Everything that can be asked of or done to a running bot, for the admin API and the chat bots.
+#[derive(Clone)]
+pub struct Control {
+    controls: Arc<Controls>,
+    repository: Arc<dyn TransactionRepository>,
+    notifier: Notifier,
+    exchanges: HashMap<String, Arc<Mutex<Box<dyn Exchange + Sync + Send>>>>,
+    started_at: Instant,
+}
+
+impl Control {
+    pub fn new(
+        controls: Arc<Controls>,
+        repository: Arc<dyn TransactionRepository>,
+        notifier: Notifier,
+        exchanges: HashMap<String, Arc<Mutex<Box<dyn Exchange + Sync + Send>>>>,
+        started_at: Instant,
+    ) -> Self {
+        Self {
+            controls,
+            repository,
+            notifier,
+            exchanges,
+            started_at,
+        }
+    }
+
+    pub fn controls(&self) -> &Controls {
+        &self.controls
+    }
+
+    fn sorted_books(exchange: &(dyn Exchange + Sync + Send)) -> Vec<(String, Arc<SyncMutex<OrderBook>>)> {
+        let mut books = exchange.books().into_iter().collect::<Vec<_>>();
+
+        books.sort_by(|a, b| a.0.cmp(&b.0));
+        books
+    }
+
+    // The `depth` best levels on both sides of every book.
+    pub async fn books(&self, depth: usize) -> Vec<BookView> {
+        let mut views = vec![];
+
+        for (identifier, exchange) in self.exchanges.iter() {
+            let exchange = exchange.lock().await;
+
+            for (symbol, book) in Self::sorted_books(&**exchange) {
+                let book = book.lock();
+
+                views.push(BookView {
+                    exchange: identifier.clone(),
+                    symbol,
+                    tradable: book.is_tradable(),
+                    bids: book.bids.top(depth).map(|(price, quantity)| (*price, *quantity)).collect(),
+                    asks: book.asks.top(depth).map(|(price, quantity)| (*price, *quantity)).collect(),
+                });
+            }
+        }
+
+        views
+    }
+
+    pub async fn balances(&self) -> Vec<BalanceView> {
+        let mut views = vec![];
+
+        for (identifier, exchange) in self.exchanges.iter() {
+            let exchange = exchange.lock().await;
+
+            let mut balances = exchange
+                .balances()
+                .iter()
+                .filter(|balance| !(balance.available + balance.locked).is_zero())
+                .map(|balance| BalanceView {
+                    exchange: identifier.clone(),
+                    asset: balance.symbol.clone(),
+                    available: balance.available,
+                    locked: balance.locked,
+                })
+                .collect::<Vec<_>>();
+
+            balances.sort_by(|a, b| a.asset.cmp(&b.asset));
+            views.append(&mut balances);
+        }
+
+        views
+    }
+
+    pub fn open_transactions(&self) -> Result<Vec<Transaction>> {
+        let mut open = vec![];
+
+        for identifier in self.exchanges.keys() {
+            open.append(&mut self.repository.open_for_exchange(identifier)?);
+        }
+
+        Ok(open)
+    }
+
+    // Realised PnL between `from` and `to`, with the positions held marked at the mid of their live book.
+    pub async fn pnl(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<PnlReport> {
+        let mut report = self.repository.pnl_report(from, to, &CONFIG.quote_currency)?;
+        let mut prices = HashMap::new();
+
+        for exchange in self.exchanges.values() {
+            let exchange = exchange.lock().await;
+
+            for (symbol, book) in exchange.books() {
+                let book = book.lock();
+
+                if let (true, Some(mid)) = (book.is_tradable(), book.mid()) {
+                    prices.insert(symbol, mid);
+                }
+            }
+        }
+
+        report.mark(&prices);
+
+        Ok(report)
+    }
+
+    pub fn brokers(&self) -> Vec<BrokerView> {
+        CONFIG
+            .coins
+            .iter()
+            .map(|coin| {
+                let strategy = SupportBand::from_coin(coin, CONFIG.max_transaction_per_coin);
+
+                BrokerView {
+                    symbol: coin.symbol.clone(),
+                    support: strategy.support,
+                    profit_wanted: strategy.profit_wanted,
+                    lower: strategy.lower(),
+                    upper: strategy.upper(),
+                    max_transactions: strategy.max_transactions,
+                    paused: self.controls.is_paused(&coin.symbol),
+                }
+            })
+            .collect()
+    }
+
+    pub async fn health(&self) -> Health {
+        let mut books = vec![];
+
+        for (identifier, exchange) in self.exchanges.iter() {
+            let exchange = exchange.lock().await;
+
+            for (symbol, book) in Self::sorted_books(&**exchange) {
+                let book = book.lock();
+
+                books.push(BookHealth {
+                    exchange: identifier.clone(),
+                    symbol,
+                    valid: book.is_valid(),
+                    stale: book.is_stale(),
+                    seconds_since_update: book.updated_at().map(|at| at.elapsed().as_secs()),
+                });
+            }
+        }
+
+        Health {
+            uptime_seconds: self.started_at.elapsed().as_secs(),
+            buying_paused: self.controls.is_buying_paused(),
+            paused_coins: self.controls.paused_coins(),
+            books,
+        }
+    }
+
+    pub fn pause_coin(&self, symbol: &str) -> Result<()> {
+        self.known_coin(symbol)?;
+        self.controls.pause_coin(symbol);
+        info!("Paused trading {}", symbol);
+        self.notifier.notify(Notification::Halted { what: format!("trading {}", symbol) });
+
+        Ok(())
+    }
+
+    pub fn resume_coin(&self, symbol: &str) -> Result<()> {
+        self.known_coin(symbol)?;
+        self.controls.resume_coin(symbol);
+        info!("Resumed trading {}", symbol);
+        self.notifier.notify(Notification::Resumed { what: format!("trading {}", symbol) });
+
+        Ok(())
+    }
+
+    pub fn pause_buying(&self) {
+        self.controls.pause_buying();
+        info!("Paused buying");
+        self.notifier.notify(Notification::Halted { what: "buying".to_string() });
+    }
+
+    pub fn resume_buying(&self) {
+        self.controls.resume_buying();
+        info!("Resumed buying");
+        self.notifier.notify(Notification::Resumed { what: "buying".to_string() });
+    }
+
+    fn known_coin(&self, symbol: &str) -> Result<()> {
+        if !CONFIG.coins.iter().any(|coin| coin.symbol == symbol) {
+            return Err(anyhow!("{} isn't a configured coin", symbol));
+        }
+
+        Ok(())
+    }
+
+    // Sells a held transaction at `price`, or at the best bid of its live book. Returns the order id.
+    pub async fn force_sell(&self, transaction_id: &str, price: Option<Decimal>) -> Result<String> {
+        let transaction = self
+            .repository
+            .find(transaction_id)?
+            .ok_or_else(|| anyhow!("Transaction {} doesn't exist", transaction_id))?;
+
+        let exchange = self
+            .exchanges
+            .get(&transaction.exchange_name)
+            .ok_or_else(|| anyhow!("Exchange {} isn't running", &transaction.exchange_name))?;
+        let mut exchange = exchange.lock().await;
+
+        // The strategy may have sold it while we waited for the exchange, so look again under the lock.
+        let transaction = self
+            .repository
+            .find(transaction_id)?
+            .ok_or_else(|| anyhow!("Transaction {} doesn't exist", transaction_id))?;
+
+        if transaction.stage != TransactionStage::Hodl {
+            return Err(anyhow!("Only held transactions can be sold, {} is {}", &transaction.id, transaction.stage));
+        }
+
+        let price = match price {
+            Some(price) => price,
+            None => {
+                let book = exchange
+                    .books()
+                    .get(&transaction.symbol)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("No book for {}", &transaction.symbol))?;
+                let book = book.lock();
+
+                if !book.is_tradable() {
+                    return Err(anyhow!("The book for {} is out of sync, give a price", &transaction.symbol));
+                }
+
+                book.highest_bid()
+                    .ok_or_else(|| anyhow!("Nobody is bidding on {}", &transaction.symbol))?
+            }
+        };
+
+        force_sell(&mut **exchange, &*self.repository, &self.notifier, &transaction, price)
+    }
+}
+
+// Places a sell for everything `transaction` holds at `price`, booked like a sell the strategy made.
+pub fn force_sell(exchange: &mut (dyn Exchange + Sync + Send), repository: &dyn TransactionRepository, notifier: &Notifier, transaction: &Transaction, price: Decimal) -> Result<String> {
+    if transaction.stage != TransactionStage::Hodl {
+        return Err(anyhow!("Only held transactions can be sold, {} is {}", &transaction.id, transaction.stage));
+    }
+
+    if transaction.exchange_name != exchange.get_identifier() {
+        return Err(anyhow!("Transaction {} was made on {}", &transaction.id, &transaction.exchange_name));
+    }
+
+    let order_id = exchange.execute_transaction(&ExecutableTransaction::Sell {
+        symbol: transaction.symbol.clone(),
+        price,
+        amount: transaction.amount,
+        meta: TransactionMeta {
+            existing_transaction: Some(transaction.id.clone()),
+        },
+    })?;
+
+    info!("Force-selling {} {} of transaction {} at {}", &transaction.amount, &transaction.symbol, &transaction.id, price);
+    Poppy::update_transaction_for_sale(repository, notifier, transaction.id.clone(), order_id.clone(), transaction.amount, price);
+
+    Ok(order_id)
+}
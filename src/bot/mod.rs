@@ -8,23 +8,72 @@ use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use crate::CONFIG as Config;
 use crate::crypto::balances::Balance;
 use anyhow::Error;
-use crate::database::{Transaction, TransactionStage, UpdateTransactionStageForm, FinishedTransaction};
-use crate::schema::transactions::columns::{symbol, id};
+use crate::database::{Transaction, TransactionStage, UpdateTransactionStageForm};
+use crate::database::repository::TransactionRepository;
 use chrono::Utc;
-use diesel::{RunQueryDsl, QueryDsl, ExpressionMethods};
-use crate::schema::transactions::dsl::transactions;
-use uuid::Uuid;
-use round::round_down;
-
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal_macros::dec;
+use crate::bot::control::{Control, Controls};
+use crate::metrics;
+use crate::notify::{Notification, Notifier};
+use chrono::NaiveDate;
+
+pub mod control;
 pub mod trading;
 
+// How much of `symbol` to buy at `price`, spending at most `max_trade_size` of the `available` quote
+// balance. None when we can't afford the `min_trade_size`.
+pub fn buy_amount(symbol: &str, price: Decimal, available: Decimal) -> Option<Decimal> {
+    let min_quote_order_size = Config.min_trade_size;
+    let mut quote_order_size = Config.max_trade_size;
+
+    while quote_order_size > available && quote_order_size >= min_quote_order_size {
+        quote_order_size -= (quote_order_size * dec!(0.01));
+    }
+
+    if quote_order_size < min_quote_order_size {
+        return None;
+    }
+
+    let mut amount = (quote_order_size / price)
+        .round_dp_with_strategy(2, RoundingStrategy::RoundDown);
+
+    if symbol == "STMX" {
+        // TODO: Why the fuck does binance do this and can we find a list of coins for which this is active?????
+        amount = amount.floor();
+    }
+
+    Some(amount)
+}
+
 pub struct Poppy {
     exchanges: HashMap<String, Arc<Mutex<Box<dyn Exchange + Sync + Send>>>>,
+    repository: Arc<dyn TransactionRepository>,
+    controls: Arc<Controls>,
+    notifier: Notifier,
+    started_at: std::time::Instant,
 }
 
 impl Poppy {
-    pub fn new() -> Self {
-        Self { exchanges: HashMap::new() }
+    pub fn new(repository: Arc<dyn TransactionRepository>, notifier: Notifier) -> Self {
+        Self {
+            exchanges: HashMap::new(),
+            repository,
+            controls: Arc::new(Controls::new()),
+            notifier,
+            started_at: std::time::Instant::now(),
+        }
+    }
+
+    // A handle to control the bot with while it runs. Only sees the exchanges registered so far.
+    pub fn control(&self) -> Control {
+        Control::new(
+            Arc::clone(&self.controls),
+            Arc::clone(&self.repository),
+            self.notifier.clone(),
+            self.exchanges.clone(),
+            self.started_at,
+        )
     }
 
     pub async fn register_exchange(&mut self, mut exchange: Box<dyn Exchange + Send + Sync>)
@@ -50,9 +99,24 @@ impl Poppy {
 
     fn spawn_intent_handler(&self, exchange: &Arc<Mutex<Box<dyn Exchange + Sync + Send>>>, mut intent_receiver: UnboundedReceiver<TransactionIntent>) {
         let exchange = Arc::clone(exchange);
+        let repository = Arc::clone(&self.repository);
+        let controls = Arc::clone(&self.controls);
+        let notifier = self.notifier.clone();
 
         tokio::spawn(async move {
            while let Some(intent) = intent_receiver.recv().await {
+                let labels = [intent.symbol().to_string(), intent.side().to_string()];
+                let reject = |reason: &str| {
+                    metrics::INTENTS_REJECTED
+                        .with_label_values(&[&labels[0], &labels[1], reason])
+                        .inc();
+                };
+
+                if !controls.allows(&intent) {
+                    reject("paused");
+                    continue;
+                }
+
                 let mut exchange = exchange.lock().await;
 
                 let executable = match intent {
@@ -62,32 +126,26 @@ impl Poppy {
                         meta
                     } => {
 
-                        let min_quote_order_size = Config.min_trade_size;
-                        let mut quote_order_size = Config.max_trade_size;
-
-                        match exchange
+                        let available = match exchange
                             .balances()
                             .get_balance_for_symbol(&Config.quote_currency) {
                             None => {
                                 panic!("Invalid quote currency.");
                             }
-                            Some(balance) => {
-                                while quote_order_size > balance.available {
-                                    quote_order_size -= (quote_order_size * 0.01);
-                                }
-                            }
-                        }
-
-                        if quote_order_size < min_quote_order_size {
-                            continue;
-                        }
+                            Some(balance) => balance.available,
+                        };
 
-                        let mut amount = round_down(&quote_order_size / price, 2);
+                        let funds_halt = format!("buying on {} (not enough {})", exchange.get_identifier(), &Config.quote_currency);
+                        let amount = buy_amount(&tx_symbol, price, available);
+                        Self::announce_halt(&controls, &notifier, funds_halt, amount.is_none());
 
-                        if tx_symbol == "STMX" {
-                            // TODO: Why the fuck does binance do this and can we find a list of coins for which this is active?????
-                            amount = amount.floor();
-                        }
+                        let amount = match amount {
+                            Some(amount) => amount,
+                            None => {
+                                reject("funds");
+                                continue;
+                            }
+                        };
 
                         info!("[{}]: Found buy opportunity on {}. Price: {}", &exchange.get_identifier(), &tx_symbol, &price);
                         info!("[{}]: Buying {} of {} at {}", &exchange.get_identifier(), &amount, &tx_symbol, &price);
@@ -107,6 +165,22 @@ impl Poppy {
                         amount,
                         meta
                     } => {
+                        // A forced sell or an earlier intent may have sold it since the broker looked, a
+                        // second order would sell coins of another transaction.
+                        let held = match meta.existing_transaction.as_deref().map(|id| repository.find(id)) {
+                            Some(Ok(Some(transaction))) => transaction.stage == TransactionStage::Hodl,
+                            Some(Err(error)) => {
+                                error!("[{}]: Error loading transaction to sell: {:?}", exchange.get_identifier(), error);
+                                false
+                            }
+                            _ => false,
+                        };
+
+                        if !held {
+                            reject("stale");
+                            continue;
+                        }
+
                         ExecutableTransaction::Sell {
                             symbol: tx_symbol,
                             price,
@@ -121,6 +195,10 @@ impl Poppy {
                let exchange_id = exchange.get_identifier().clone();
                 match exchange.execute_transaction(&executable) {
                     Ok(tx_id) => {
+                        metrics::INTENTS_EXECUTED
+                            .with_label_values(&[&labels[0], &labels[1]])
+                            .inc();
+
                         match executable {
                             ExecutableTransaction::Buy {
                                 symbol: tx_symbol,
@@ -129,12 +207,21 @@ impl Poppy {
                                 ..
                             } => {
                                 Self::record_transaction_to_database(
+                                    &*repository,
                                     tx_symbol.clone(),
                                     exchange_id.clone(),
                                     tx_id.clone(),
                                     amount,
                                     price
                                 );
+
+                                notifier.notify(Notification::Bought {
+                                    exchange: exchange_id.clone(),
+                                    symbol: tx_symbol,
+                                    amount,
+                                    price,
+                                    order_id: tx_id,
+                                });
                             }
                             ExecutableTransaction::Sell {
                                 meta,
@@ -143,6 +230,8 @@ impl Poppy {
                                 ..
                             } => {
                                 Self::update_transaction_for_sale(
+                                    &*repository,
+                                    &notifier,
                                     meta.existing_transaction.expect("No existing transaction"),
                                     tx_id.clone(),
                                     amount,
@@ -152,7 +241,15 @@ impl Poppy {
                         }
                     }
                     Err(error) => {
+                        reject("exchange");
                         error!("[{}]: Error while executing transaction: {:?}", exchange.get_identifier(), error);
+
+                        notifier.notify(Notification::OrderFailed {
+                            exchange: exchange_id,
+                            symbol: labels[0].clone(),
+                            side: labels[1].clone(),
+                            error: format!("{:#}", error),
+                        });
                     }
                 }
            }
@@ -165,6 +262,7 @@ impl Poppy {
         let tick_time = Duration::from_millis(1000); // Tick 1 times per 2 seconds.
         let mut next_tick = Instant::now();
         let mut cycles: i32 = 0;
+        let mut summary_day = Utc::today().naive_utc();
 
         loop {
             let dbg = cycles % 6 == 0;
@@ -172,6 +270,20 @@ impl Poppy {
 
             self.tick_exchanges(dbg, actionable);
 
+            if dbg {
+                self.watch_books();
+            }
+
+            if actionable {
+                self.record_metrics();
+
+                let today = Utc::today().naive_utc();
+                if today != summary_day {
+                    self.send_daily_summary(summary_day);
+                    summary_day = today;
+                }
+            }
+
             while next_tick < Instant::now() {
                 next_tick += tick_time;
             }
@@ -195,10 +307,127 @@ impl Poppy {
         }
     }
 
-    fn record_transaction_to_database<T: Into<String>>(tx_symbol: T, exchange_id: T, buy_id: T, amount: f64, price: f64) {
-        use crate::schema::transactions;
+    // Announces the books that can't be traded on, out of sync or stale, and the ones that recovered.
+    // Books that never loaded are still booting and left out.
+    fn watch_books(&self) {
+        for (identifier, exchange) in self.exchanges.iter() {
+            let exchange = Arc::clone(exchange);
+            let identifier = identifier.clone();
+            let controls = Arc::clone(&self.controls);
+            let notifier = self.notifier.clone();
+
+            tokio::spawn(async move {
+                let books = exchange.lock().await.books();
+
+                for (symbol, book) in books.iter() {
+                    let (loaded, tradable) = {
+                        let book = book.lock();
+
+                        (book.updated_at().is_some(), book.is_tradable())
+                    };
+
+                    if loaded {
+                        let halt = format!("trading {} on {} (book out of sync or stale)", symbol, &identifier);
+                        Self::announce_halt(&controls, &notifier, halt, !tradable);
+                    }
+                }
+            });
+        }
+    }
+
+    // Sends Halted when `halt` starts to hold and Resumed when it stops, nothing while it stays the same.
+    pub(crate) fn announce_halt(controls: &Controls, notifier: &Notifier, halt: String, halted: bool) {
+        if !controls.set_halted(halt.clone(), halted) {
+            return;
+        }
+
+        if halted {
+            warn!("Halted {}", &halt);
+            notifier.notify(Notification::Halted { what: halt });
+        } else {
+            info!("Resumed {}", &halt);
+            notifier.notify(Notification::Resumed { what: halt });
+        }
+    }
+
+    // Updates the gauges that are read from the exchanges and the database rather than counted.
+    fn record_metrics(&self) {
+        match self.repository.realised_pnl() {
+            Ok(pnl) => metrics::REALISED_PNL.set(metrics::as_f64(pnl)),
+            Err(error) => error!("Error calculating realised PnL: {:?}", error),
+        }
+
+        for (identifier, exchange) in self.exchanges.iter() {
+            let exchange = Arc::clone(exchange);
+            let identifier = identifier.clone();
+            let repository = Arc::clone(&self.repository);
+
+            tokio::spawn(async move {
+                if let Some(balance) = exchange.lock().await.balances().get_balance_for_symbol(&Config.quote_currency) {
+                    metrics::QUOTE_BALANCE
+                        .with_label_values(&[&identifier, "available"])
+                        .set(metrics::as_f64(balance.available));
+                    metrics::QUOTE_BALANCE
+                        .with_label_values(&[&identifier, "locked"])
+                        .set(metrics::as_f64(balance.locked));
+                }
+
+                let open = match repository.open_for_exchange(&identifier) {
+                    Ok(open) => open,
+                    Err(error) => {
+                        error!("[{}]: Error loading open transactions: {:?}", &identifier, error);
+                        return;
+                    }
+                };
+
+                for coin in Config.coins.iter() {
+                    let count = open.iter().filter(|t| t.symbol == coin.symbol).count();
+
+                    metrics::OPEN_POSITIONS
+                        .with_label_values(&[&identifier, &coin.symbol])
+                        .set(count as i64);
+                }
+            });
+        }
+    }
+
+    // Posts what happened on `day` (UTC) to the notification sinks.
+    fn send_daily_summary(&self, day: NaiveDate) {
+        let exchanges = self.exchanges.clone();
+        let notifier = self.notifier.clone();
+        let repository = Arc::clone(&self.repository);
+
+        tokio::spawn(async move {
+            let from = day.and_hms(0, 0, 0);
+            let to = day.and_hms(23, 59, 59);
+
+            let report = match repository.pnl_report(from, to, &Config.quote_currency) {
+                Ok(report) => report,
+                Err(error) => {
+                    error!("Error loading the PnL of {}: {:?}", day, error);
+                    return;
+                }
+            };
+
+            let mut quote_balance = dec!(0);
+            for exchange in exchanges.values() {
+                if let Some(balance) = exchange.lock().await.balances().get_balance_for_symbol(&Config.quote_currency) {
+                    quote_balance += balance.available + balance.locked;
+                }
+            }
 
-        let connection = crate::DATABASE.get_connection();
+            notifier.notify(Notification::DailySummary {
+                day,
+                trades: report.trades.len(),
+                realised_pnl: report.realised_pnl(),
+                fees: report.fees(),
+                open_positions: report.positions.len(),
+                quote_balance,
+            });
+        });
+    }
+
+    fn record_transaction_to_database<T: Into<String>>(repository: &dyn TransactionRepository, tx_symbol: T, exchange_id: T, buy_id: T, amount: Decimal, price: Decimal) {
         let transaction = Transaction {
             id: uuid::Uuid::new_v4().to_string(),
             exchange_name: exchange_id.into(),
@@ -207,71 +436,67 @@ impl Poppy {
             amount,
             symbol: tx_symbol.into(),
             price,
-            stage: TransactionStage::BuyTransactionOpen.to_string(),
+            stage: TransactionStage::BuyTransactionOpen,
             created_at: Some(Utc::now().naive_utc()),
             updated_at: Some(Utc::now().naive_utc()),
         };
 
-        diesel::insert_into(transactions::table)
-            .values(&transaction)
-            .execute(&connection)
-            .expect("Error saving transaction");
+        if let Err(error) = repository.insert(&transaction) {
+            error!("Error saving transaction {}: {:?}", &transaction.id, error);
+            return;
+        }
 
         info!("Inserted transaction with id {} into database", &transaction.id);
     }
 
-    fn update_transaction_for_sale<T: Into<String>>(transaction_id: T, sell_id: T, amount: f64, price: f64) {
-        use crate::schema::transactions::dsl;
-
-        let connection = crate::DATABASE.get_connection();
+    pub fn update_transaction_for_sale<T: Into<String>>(repository: &dyn TransactionRepository, notifier: &Notifier, transaction_id: T, sell_id: T, amount: Decimal, price: Decimal) {
         let transaction_id = transaction_id.into();
         let sell_id = sell_id.into();
-        let transaction: Transaction = transactions
-            .find(&transaction_id)
-            .first(&connection)
-            .expect("Could not find transaction");
-
-        let change_set = UpdateTransactionStageForm {
-            stage: TransactionStage::SellTransactionOpen.to_string(),
-            sell_exchange_id: Some(sell_id.clone()),
-            updated_at: Some(Utc::now().naive_utc()),
-            amount: transaction.amount
+        let transaction = match repository.find(&transaction_id) {
+            Ok(Some(transaction)) => transaction,
+            Ok(None) => {
+                error!("Could not find transaction {}", &transaction_id);
+                return;
+            }
+            Err(error) => {
+                error!("Error loading transaction {}: {:?}", &transaction_id, error);
+                return;
+            }
         };
 
-        diesel::update(&transaction)
-            .set(change_set)
-            .execute(&connection);
-
-        let finished = FinishedTransaction {
-            id: Uuid::new_v4().to_string(),
-            transaction_id: sell_id,
-            amount_bought: transaction.amount.clone(),
-            buy_price: transaction.price.clone(),
-            amount_sold: amount,
-            sell_price: price,
-            created_at: Some(Utc::now().naive_utc()),
+        let change_set = UpdateTransactionStageForm {
+            stage: TransactionStage::SellTransactionOpen,
+            sell_exchange_id: Some(Some(sell_id.clone())),
             updated_at: Some(Utc::now().naive_utc()),
+            amount: transaction.amount,
+            price: None,
         };
 
-        {
-            use crate::schema::finished_transactions;
-
-
-            diesel::insert_into(finished_transactions::table)
-                .values(&finished)
-                .execute(&connection)
-                .expect("Error saving transaction");
+        // The sale itself gets booked once the order fills, see `Mandala::apply_order_state`.
+        if let Err(error) = repository.transition(&transaction, change_set) {
+            error!("Error updating transaction {} for sale: {:?}", &transaction.id, error);
+            return;
         }
 
-        info!("[Mandala]: Success!! Bought {} {} at {}. Sold {} {} at {}. making a profit of {:.2} {}.",
-              &transaction.amount,
+        let profit = (amount * price) - (transaction.amount * transaction.price);
+
+        info!("[Mandala]: Selling {} {} bought at {} for {} with order {}, making a profit of {:.2} {} once it fills.",
+              &amount,
               &transaction.symbol,
               &transaction.price,
-              &finished.amount_sold,
-              &transaction.symbol,
-              &finished.sell_price,
-              (finished.amount_sold * finished.sell_price) - (transaction.amount * transaction.price),
+              &price,
+              &sell_id,
+              profit.round_dp(2),
               Config.quote_currency.clone()
         );
+
+        notifier.notify(Notification::Sold {
+            exchange: transaction.exchange_name.clone(),
+            symbol: transaction.symbol.clone(),
+            amount,
+            price,
+            order_id: sell_id,
+            profit,
+        });
     }
 }
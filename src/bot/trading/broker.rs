@@ -3,8 +3,6 @@ use std::sync::Arc;
 use tokio::sync::{Notify};
 use crate::crypto::orderbook::{OrderBook, OrderSide, OrderType};
 use crate::CONFIG as Config;
-use crate::utils::{count_transactions_for_pair, get_transactions_for_pair};
-use crate::database::TransactionStage;
 use parking_lot::Mutex;
 use tokio::sync::watch::Receiver;
 use std::borrow::BorrowMut;
@@ -15,22 +13,27 @@ use diesel::result::Error;
 use crate::crypto::treasury::TransactionMeta;
 use crate::crypto::treasury::IntentMeta;
 use tokio::sync::mpsc::error::SendError;
+use crate::bot::trading::strategy::SupportBand;
+use crate::database::repository::TransactionRepository;
+use crate::metrics;
 
 #[derive(Debug)]
 pub struct Broker {
     symbol: String,
     book: Arc<Mutex<OrderBook>>,
     receiver: Receiver<Tick>,
-    intent_sender: UnboundedSender<TransactionIntent>
+    intent_sender: UnboundedSender<TransactionIntent>,
+    repository: Arc<dyn TransactionRepository>,
 }
 
 impl Broker {
-    pub fn new<T: Into<String>>(symbol: T, book: Arc<Mutex<OrderBook>>, receiver: Receiver<Tick>, intent_sender: UnboundedSender<TransactionIntent>) -> Self {
+    pub fn new<T: Into<String>>(symbol: T, book: Arc<Mutex<OrderBook>>, receiver: Receiver<Tick>, intent_sender: UnboundedSender<TransactionIntent>, repository: Arc<dyn TransactionRepository>) -> Self {
         Self {
             symbol: symbol.into(),
             book,
             receiver,
-            intent_sender
+            intent_sender,
+            repository,
         }
     }
 
@@ -43,86 +46,44 @@ impl Broker {
         let max_transactions = Config.max_transaction_per_coin;
         let coins = Config.coins.clone();
         let coin = coins.into_iter().find(|coin| &coin.symbol == &self.symbol).expect("Couldn't find coin in config");
-        let support = coin.support;
-        let increment = coin.profit_wanted;
-        let lower = support - (support*(increment/2.0));
-        let upper = support + (support*(increment/2.0));
+        let strategy = SupportBand::from_coin(&coin, max_transactions);
         let symbol = self.symbol.clone();
 
         let mut receiver = self.receiver.clone();
         let intent_sender = self.intent_sender.clone();
+        let repository = Arc::clone(&self.repository);
         tokio::spawn(async move {
             loop {
                 receiver.changed().await;
                 let tick = *receiver.borrow();
                 let book = book.lock();
-                if let (Some(bid), Some(ask)) = (book.highest_bid(), book.lowest_ask()) {
 
+                // The bookie is resyncing this book or it stopped receiving updates, its prices can't be trusted.
+                if !book.is_tradable() {
                     if tick == Tick::Output {
-                        info!("[Mandala]: Support for {} is configured at {}. Looking for a profit of {}%. (B: {:.3}, S: {:.3}) Current: (B: {:.4}, A: {:.4})", &symbol, &support, &increment, &lower, &upper, &bid, &ask);
+                        warn!("[Mandala]: Book for {} is out of sync or stale, not trading.", &symbol);
                     }
 
-                    if ask.as_ref() <= &lower {
-
-                        let count = count_transactions_for_pair(
-                            &symbol,
-                            TransactionStage::open()
-                        ).unwrap_or(0);
+                    continue;
+                }
 
-                        if count >= max_transactions {
-                            continue;
-                        }
+                if let (Some(bid), Some(ask)) = (book.highest_bid(), book.lowest_ask()) {
 
-                        match intent_sender.send(TransactionIntent::Buy {
-                            symbol: symbol.clone(),
-                            price: ask.as_ref().clone(),
-                            meta: IntentMeta { existing_transaction: None }
-                        }) {
-                            Ok(_) => {}
-                            Err(error) => {
-                                info!("Error while sending intent: {:?}", error);
-                            }
-                        }
+                    if tick == Tick::Output {
+                        info!("[Mandala]: Support for {} is configured at {}. Looking for a profit of {}%. (B: {:.3}, S: {:.3}) Current: (B: {:.4}, A: {:.4})", &symbol, &strategy.support, &strategy.profit_wanted, &strategy.lower(), &strategy.upper(), &bid, &ask);
                     }
 
-                    if bid >= upper {
-                        match get_transactions_for_pair(&symbol, vec![TransactionStage::Hodl]) {
-                            Ok(transactions) => {
-                                // dbg!(&transactions);
-                                if transactions.is_empty() {
-                                    continue;
-                                }
-
-                                for transaction in transactions.iter() {
-                                    if bid < transaction.price + (transaction.price * coin.profit_wanted) {
-                                        // To prevent selling multiple transactions of one coin at a single price point.
-                                        continue;
-                                    }
-
-                                    info!("[Mandala]: Found potential sell opportunity on {}. Price: {}", &symbol, &bid);
-                                    info!("[Mandala]: THIS IS PROFIT.");
-                                    info!("[Mandala]: selling {} of {} at {}", &transaction.amount, &symbol, &bid);
+                    for intent in strategy.evaluate(bid, ask, &*repository) {
+                        metrics::INTENTS_EMITTED
+                            .with_label_values(&[intent.symbol(), intent.side()])
+                            .inc();
 
-
-
-                                    if let Some(error) = intent_sender.send(TransactionIntent::Sell {
-                                        symbol: symbol.clone(),
-                                        price: bid.as_ref().clone(),
-                                        amount: transaction.amount,
-                                        meta: IntentMeta { existing_transaction: Some(transaction.id.clone()) }
-                                    }).err() {
-                                        dbg!(&error);
-                                        panic!();
-                                    }
-                                }
-                            }
-                            Err(error) => {
-                                error!("[Mandala]: Error getting transactions for {}: {:?}", symbol, error);
-                            }
+                        if let Err(error) = intent_sender.send(intent) {
+                            error!("[Mandala]: Error while sending intent: {:?}", error);
                         }
                     }
                 }
             }
         });
     }
-}
\ No newline at end of file
+}
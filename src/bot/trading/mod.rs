@@ -8,20 +8,24 @@ use parking_lot::Mutex;
 use tokio::sync::watch::Receiver;
 use tokio::sync::mpsc::UnboundedSender;
 use crate::crypto::treasury::TransactionIntent;
+use crate::database::repository::TransactionRepository;
 
 pub mod broker;
+pub mod strategy;
 
 #[derive(Debug)]
 pub struct Trader {
     brokers: HashMap<String, Broker>,
-    receiver: Receiver<Tick>
+    receiver: Receiver<Tick>,
+    repository: Arc<dyn TransactionRepository>,
 }
 
 impl Trader {
-    pub fn new(receiver: Receiver<Tick>) -> Self {
+    pub fn new(receiver: Receiver<Tick>, repository: Arc<dyn TransactionRepository>) -> Self {
         Self {
             brokers: HashMap::new(),
-            receiver
+            receiver,
+            repository,
         }
     }
 
@@ -33,7 +37,8 @@ impl Trader {
             symbol.clone(),
             book,
             self.receiver.clone(),
-                intent_sender
+            intent_sender,
+            Arc::clone(&self.repository),
         ));
     }
 
@@ -0,0 +1,195 @@
+use crate::crypto::treasury::{IntentMeta, TransactionIntent};
+use crate::database::repository::TransactionRepository;
+use crate::database::{Transaction, TransactionStage};
+use crate::utils::config::Coin;
+use anyhow::Result;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+// Where the strategy learns about the positions it has open. Live these come from the transaction
+// repository, in a backtest from the simulated portfolio.
+pub trait Positions {
+    // Transactions for `symbol` that haven't finished yet, in any stage.
+    fn count_open(&self, symbol: &str) -> Result<i64>;
+    // Transactions for `symbol` that have been bought and are waiting to be sold.
+    fn held(&self, symbol: &str) -> Result<Vec<Transaction>>;
+}
+
+impl<R: TransactionRepository + ?Sized> Positions for R {
+    fn count_open(&self, symbol: &str) -> Result<i64> {
+        self.count_for_pair(symbol, &TransactionStage::open())
+    }
+
+    fn held(&self, symbol: &str) -> Result<Vec<Transaction>> {
+        self.for_pair(symbol, &[TransactionStage::Hodl])
+    }
+}
+
+// Buys when the ask drops into the band below the support level, and sells held coins once the bid is
+// above the band and far enough above their buy price to make the wanted profit.
+#[derive(Debug, Clone)]
+pub struct SupportBand {
+    pub symbol: String,
+    pub support: Decimal,
+    pub profit_wanted: Decimal,
+    pub max_transactions: i64,
+    lower: Decimal,
+    upper: Decimal,
+}
+
+impl SupportBand {
+    pub fn new<T: Into<String>>(symbol: T, support: Decimal, profit_wanted: Decimal, max_transactions: i64) -> Self {
+        Self {
+            symbol: symbol.into(),
+            support,
+            profit_wanted,
+            max_transactions,
+            lower: support - (support * (profit_wanted / dec!(2))),
+            upper: support + (support * (profit_wanted / dec!(2))),
+        }
+    }
+
+    pub fn from_coin(coin: &Coin, max_transactions: i64) -> Self {
+        Self::new(&coin.symbol, coin.support, coin.profit_wanted, max_transactions)
+    }
+
+    #[inline]
+    pub fn lower(&self) -> Decimal {
+        self.lower
+    }
+
+    #[inline]
+    pub fn upper(&self) -> Decimal {
+        self.upper
+    }
+
+    pub fn evaluate<P: Positions + ?Sized>(&self, bid: Decimal, ask: Decimal, positions: &P) -> Vec<TransactionIntent> {
+        let mut intents = vec![];
+
+        if ask <= self.lower {
+            let count = positions.count_open(&self.symbol).unwrap_or(0);
+
+            if count >= self.max_transactions {
+                return intents;
+            }
+
+            intents.push(TransactionIntent::Buy {
+                symbol: self.symbol.clone(),
+                price: ask,
+                meta: IntentMeta { existing_transaction: None },
+            });
+        }
+
+        if bid >= self.upper {
+            match positions.held(&self.symbol) {
+                Ok(transactions) => {
+                    for transaction in transactions.iter() {
+                        if bid < transaction.price + (transaction.price * self.profit_wanted) {
+                            // To prevent selling multiple transactions of one coin at a single price point.
+                            continue;
+                        }
+
+                        info!("[Mandala]: Found potential sell opportunity on {}. Price: {}", &self.symbol, &bid);
+                        info!("[Mandala]: THIS IS PROFIT.");
+                        info!("[Mandala]: selling {} of {} at {}", &transaction.amount, &self.symbol, &bid);
+
+                        intents.push(TransactionIntent::Sell {
+                            symbol: self.symbol.clone(),
+                            price: bid,
+                            amount: transaction.amount,
+                            meta: IntentMeta { existing_transaction: Some(transaction.id.clone()) },
+                        });
+                    }
+                }
+                Err(error) => {
+                    error!("[Mandala]: Error getting transactions for {}: {:?}", self.symbol, error);
+                }
+            }
+        }
+
+        intents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repository::InMemoryTransactionRepository;
+    use chrono::Utc;
+
+    fn transaction(id: &str, stage: TransactionStage, price: Decimal) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            exchange_name: "mandala".to_string(),
+            buy_exchange_id: Some(format!("buy-{}", id)),
+            sell_exchange_id: None,
+            amount: dec!(100),
+            symbol: "ADA".to_string(),
+            price,
+            stage,
+            created_at: Some(Utc::now().naive_utc()),
+            updated_at: Some(Utc::now().naive_utc()),
+        }
+    }
+
+    // Support at 1 with 10% profit wanted puts the band at 0.95 - 1.05.
+    fn strategy(max_transactions: i64) -> SupportBand {
+        SupportBand::new("ADA", dec!(1), dec!(0.1), max_transactions)
+    }
+
+    #[test]
+    fn buys_below_the_band() {
+        let repository = InMemoryTransactionRepository::new();
+        let intents = strategy(2).evaluate(dec!(0.93), dec!(0.94), &repository);
+
+        assert_eq!(intents.len(), 1);
+        assert!(matches!(&intents[0], TransactionIntent::Buy { price, .. } if *price == dec!(0.94)));
+    }
+
+    #[test]
+    fn does_nothing_inside_the_band() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction("held", TransactionStage::Hodl, dec!(0.9))).unwrap();
+
+        assert!(strategy(2).evaluate(dec!(1.0), dec!(1.01), &repository).is_empty());
+    }
+
+    #[test]
+    fn stops_buying_at_max_transactions() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction("buying", TransactionStage::BuyTransactionOpen, dec!(0.94))).unwrap();
+        repository.insert(&transaction("held", TransactionStage::Hodl, dec!(0.94))).unwrap();
+
+        assert!(strategy(2).evaluate(dec!(0.93), dec!(0.94), &repository).is_empty());
+        assert_eq!(strategy(3).evaluate(dec!(0.93), dec!(0.94), &repository).len(), 1);
+    }
+
+    #[test]
+    fn finished_transactions_dont_count_towards_the_maximum() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction("done", TransactionStage::Finished, dec!(0.94))).unwrap();
+
+        assert_eq!(strategy(1).evaluate(dec!(0.93), dec!(0.94), &repository).len(), 1);
+    }
+
+    #[test]
+    fn sells_held_coins_above_the_band_once_profitable() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction("cheap", TransactionStage::Hodl, dec!(0.9))).unwrap();
+        repository.insert(&transaction("expensive", TransactionStage::Hodl, dec!(1.0))).unwrap();
+        repository.insert(&transaction("selling", TransactionStage::SellTransactionOpen, dec!(0.9))).unwrap();
+
+        // 1.06 is 10% above 0.9 but not above 1.0, and the open sell isn't held anymore.
+        let intents = strategy(2).evaluate(dec!(1.06), dec!(1.07), &repository);
+
+        assert_eq!(intents.len(), 1);
+        match &intents[0] {
+            TransactionIntent::Sell { price, amount, meta, .. } => {
+                assert_eq!(*price, dec!(1.06));
+                assert_eq!(*amount, dec!(100));
+                assert_eq!(meta.existing_transaction.as_deref(), Some("cheap"));
+            }
+            other => panic!("Expected a sell, got {:?}", other),
+        }
+    }
+}
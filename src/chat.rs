@@ -0,0 +1,284 @@
+use crate::bot::control::Control;
+use crate::database::pnl::Period;
+use crate::notify::discord::fit_message;
+use crate::CONFIG;
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serenity::async_trait;
+use serenity::client::{Client, Context, EventHandler};
+use serenity::model::channel::Message;
+use serenity::model::gateway::Ready;
+use std::str::FromStr;
+
+const PREFIX: &str = "!";
+const HELP: &str = "```
+!status                 uptime, pauses, books and quote balance
+!positions              open transactions
+!pnl [today|yesterday|week|month|all]
+!pause [COIN]           stop trading a coin, or all buying without one
+!resume [COIN]          undo a pause
+!sell <id> [price]      sell a held transaction, at the best bid without a price
+```";
+
+// A command typed in a chat channel, run against the same `Control` as the admin API.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommand {
+    Status,
+    Positions,
+    Pnl(PnlRange),
+    // Without a coin these pause or resume all buying.
+    Pause(Option<String>),
+    Resume(Option<String>),
+    Sell {
+        transaction: String,
+        price: Option<Decimal>,
+    },
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PnlRange {
+    // Since midnight UTC.
+    Today,
+    Yesterday,
+    // The last 7 days.
+    Week,
+    // The last 30 days.
+    Month,
+    All,
+}
+
+impl PnlRange {
+    fn bounds(&self, now: NaiveDateTime) -> (NaiveDateTime, NaiveDateTime) {
+        let midnight = now.date().and_hms(0, 0, 0);
+
+        match self {
+            PnlRange::Today => (midnight, now),
+            PnlRange::Yesterday => (midnight - Duration::days(1), midnight - Duration::seconds(1)),
+            PnlRange::Week => (now - Duration::days(7), now),
+            PnlRange::Month => (now - Duration::days(30), now),
+            PnlRange::All => (NaiveDateTime::from_timestamp(0, 0), now),
+        }
+    }
+}
+
+impl FromStr for PnlRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "today" => Ok(PnlRange::Today),
+            "yesterday" => Ok(PnlRange::Yesterday),
+            "week" => Ok(PnlRange::Week),
+            "month" => Ok(PnlRange::Month),
+            "all" => Ok(PnlRange::All),
+            _ => Err(anyhow!("Unknown range {}, expected today, yesterday, week, month or all", s)),
+        }
+    }
+}
+
+impl FromStr for ChatCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.trim().trim_start_matches(PREFIX).split_whitespace();
+        let name = words.next().unwrap_or_default().to_lowercase();
+        let args = words.collect::<Vec<_>>();
+
+        match (name.as_str(), args.as_slice()) {
+            ("status", []) => Ok(ChatCommand::Status),
+            ("positions", []) => Ok(ChatCommand::Positions),
+            ("pnl", []) => Ok(ChatCommand::Pnl(PnlRange::Today)),
+            ("pnl", [range]) => Ok(ChatCommand::Pnl(range.parse()?)),
+            ("pause", []) => Ok(ChatCommand::Pause(None)),
+            ("pause", [symbol]) => Ok(ChatCommand::Pause(Some(symbol.to_uppercase()))),
+            ("resume", []) => Ok(ChatCommand::Resume(None)),
+            ("resume", [symbol]) => Ok(ChatCommand::Resume(Some(symbol.to_uppercase()))),
+            ("sell", [transaction]) => Ok(ChatCommand::Sell {
+                transaction: transaction.to_string(),
+                price: None,
+            }),
+            ("sell", [transaction, price]) => Ok(ChatCommand::Sell {
+                transaction: transaction.to_string(),
+                price: Some(Decimal::from_str(price).map_err(|_| anyhow!("{} isn't a price", price))?),
+            }),
+            ("help", []) => Ok(ChatCommand::Help),
+            _ => Err(anyhow!("Unknown command, try {}help", PREFIX)),
+        }
+    }
+}
+
+// Runs `command` and returns the reply.
+pub async fn execute(control: &Control, command: ChatCommand) -> Result<String> {
+    match command {
+        ChatCommand::Status => Ok(status(control).await),
+        ChatCommand::Positions => positions(control),
+        ChatCommand::Pnl(range) => pnl(control, range).await,
+        ChatCommand::Pause(None) => {
+            control.pause_buying();
+            Ok("Paused buying, held coins still sell".to_string())
+        }
+        ChatCommand::Pause(Some(symbol)) => {
+            control.pause_coin(&symbol)?;
+            Ok(format!("Paused {}", symbol))
+        }
+        ChatCommand::Resume(None) => {
+            control.resume_buying();
+            Ok("Resumed buying".to_string())
+        }
+        ChatCommand::Resume(Some(symbol)) => {
+            control.resume_coin(&symbol)?;
+            Ok(format!("Resumed {}", symbol))
+        }
+        ChatCommand::Sell { transaction, price } => {
+            let order_id = control.force_sell(&transaction, price).await?;
+            Ok(format!("Placed sell order {}", order_id))
+        }
+        ChatCommand::Help => Ok(HELP.to_string()),
+    }
+}
+
+async fn status(control: &Control) -> String {
+    let health = control.health().await;
+    let quote = &CONFIG.quote_currency;
+    let out_of_sync = health
+        .books
+        .iter()
+        .filter(|book| !book.valid || book.stale)
+        .map(|book| book.symbol.clone())
+        .collect::<Vec<_>>();
+
+    let mut lines = vec![
+        format!(
+            "Up {}h {}m, buying {}",
+            health.uptime_seconds / 3600,
+            health.uptime_seconds % 3600 / 60,
+            if health.buying_paused { "paused" } else { "active" }
+        ),
+        format!(
+            "Paused coins: {}",
+            if health.paused_coins.is_empty() { "none".to_string() } else { health.paused_coins.join(", ") }
+        ),
+        format!(
+            "Books: {} in sync, out of sync: {}",
+            health.books.len() - out_of_sync.len(),
+            if out_of_sync.is_empty() { "none".to_string() } else { out_of_sync.join(", ") }
+        ),
+    ];
+
+    for balance in control.balances().await.iter().filter(|balance| &balance.asset == quote) {
+        lines.push(format!(
+            "{} on {}: {} available, {} locked",
+            quote, balance.exchange, balance.available, balance.locked
+        ));
+    }
+
+    lines.join("\n")
+}
+
+fn positions(control: &Control) -> Result<String> {
+    let open = control.open_transactions()?;
+
+    if open.is_empty() {
+        return Ok("No open positions".to_string());
+    }
+
+    let lines = open
+        .iter()
+        .map(|transaction| {
+            format!(
+                "{} {:<6} {:<28} {} @ {}",
+                transaction.id,
+                transaction.symbol,
+                transaction.stage.to_string(),
+                transaction.amount,
+                transaction.price
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok(format!("```\n{}\n```", lines.join("\n")))
+}
+
+async fn pnl(control: &Control, range: PnlRange) -> Result<String> {
+    let (from, to) = range.bounds(Utc::now().naive_utc());
+    let summary = control.pnl(from, to).await?.summary(Period::Day);
+    let quote = &CONFIG.quote_currency;
+
+    let unrealised = match summary.unrealised_pnl {
+        Some(pnl) => format!("{:.2} {}", pnl.round_dp(2), quote),
+        None => "unknown, not every book is in sync".to_string(),
+    };
+
+    Ok(format!(
+        "PnL {} to {}: {} trades, realised {:.2} {} after {:.2} {} in fees, {}% won.\nUnrealised on {} positions: {}",
+        summary.from,
+        summary.to,
+        summary.trades,
+        summary.realised_pnl.round_dp(2),
+        quote,
+        summary.fees.round_dp(2),
+        quote,
+        (summary.win_rate * dec!(100)).round_dp(2),
+        summary.positions.len(),
+        unrealised
+    ))
+}
+
+struct Handler {
+    control: Control,
+    allowed_users: Vec<u64>,
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, context: Context, message: Message) {
+        if message.author.bot || !message.content.starts_with(PREFIX) {
+            return;
+        }
+
+        // Anyone else gets ignored, not even told the bot is listening.
+        if !self.allowed_users.contains(&message.author.id.0) {
+            return;
+        }
+
+        info!("[Chat]: {} sent {}", &message.author.name, &message.content);
+
+        let reply = match message.content.parse::<ChatCommand>() {
+            Ok(command) => execute(&self.control, command).await,
+            Err(error) => Err(error),
+        };
+
+        let reply = reply.unwrap_or_else(|error| error.to_string());
+
+        if let Err(error) = message.channel_id.say(&context.http, fit_message(reply)).await {
+            error!("[Chat]: Couldn't reply to {}: {:?}", &message.author.name, error);
+        }
+    }
+
+    async fn ready(&self, _: Context, ready: Ready) {
+        info!("[Chat]: Connected to Discord as {}", ready.user.name);
+    }
+}
+
+// Listens for commands on Discord as the bot user `token` belongs to, until the connection fails for
+// good. Only `allowed_users` are answered.
+pub async fn serve_discord(control: Control, token: String, allowed_users: Vec<u64>) {
+    let client = Client::builder(&token)
+        .event_handler(Handler { control, allowed_users })
+        .await;
+
+    let mut client = match client {
+        Ok(client) => client,
+        Err(error) => {
+            error!("[Chat]: Couldn't create the Discord client: {:?}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = client.start().await {
+        error!("[Chat]: Discord client stopped: {:?}", error);
+    }
+}
@@ -1,4 +1,5 @@
 use hashbrown::HashMap;
+use rust_decimal::Decimal;
 
 pub struct BalanceMap {
     inner: HashMap<String, Balance>,
@@ -15,6 +16,10 @@ impl BalanceMap {
         self.inner = inner;
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &Balance> {
+        self.inner.values()
+    }
+
     pub fn get_balance_for_symbol<T: Into<String>>(&self, symbol: T) -> Option<&Balance> {
         self.inner.get(&symbol.into())
     }
@@ -23,12 +28,12 @@ impl BalanceMap {
 #[derive(Debug, Clone)]
 pub struct Balance {
     pub symbol: String,
-    pub available: f64,
-    pub locked: f64,
+    pub available: Decimal,
+    pub locked: Decimal,
 }
 
 impl Balance {
-    pub fn new<T: Into<String>>(symbol: T, available: f64, locked: f64) -> Self {
+    pub fn new<T: Into<String>>(symbol: T, available: Decimal, locked: Decimal) -> Self {
         Self {
             symbol: symbol.into(),
             available,
@@ -0,0 +1,310 @@
+use crate::database::StoredCandle;
+use crate::with_connection;
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Candle {
+    // Milliseconds since the epoch.
+    pub open_time: i64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+}
+
+impl Candle {
+    // The order in which prices were most likely visited within the candle: a rising candle probably
+    // dipped before it went up, a falling one peaked first.
+    pub fn price_path(&self) -> [Decimal; 4] {
+        if self.close >= self.open {
+            [self.open, self.low, self.high, self.close]
+        } else {
+            [self.open, self.high, self.low, self.close]
+        }
+    }
+}
+
+impl From<StoredCandle> for Candle {
+    fn from(candle: StoredCandle) -> Self {
+        Self {
+            open_time: candle.open_time,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+        }
+    }
+}
+
+const MINUTE: i64 = 60_000;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+
+// Candle intervals as Binance names them ("1m", "15m", "4h", "1d", "1w", "1M").
+//
+// Months aren't a fixed number of milliseconds, so they're kept apart. The `Display` form is the
+// normalised name ("60m" shows as "1h"), which is what candles are stored under.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interval {
+    Millis(i64),
+    Months(u32),
+}
+
+impl Interval {
+    // The length of the interval, months count as 30 days.
+    pub fn millis(&self) -> i64 {
+        match self {
+            Interval::Millis(millis) => *millis,
+            Interval::Months(months) => *months as i64 * 30 * DAY,
+        }
+    }
+
+    // Open time of the candle after the one opening at `open_time`.
+    pub fn next(&self, open_time: i64) -> i64 {
+        match self {
+            Interval::Millis(millis) => open_time + millis,
+            Interval::Months(months) => shift_months(open_time, *months as i32),
+        }
+    }
+
+    // Open time of the candle before the one opening at `open_time`.
+    pub fn previous(&self, open_time: i64) -> i64 {
+        match self {
+            Interval::Millis(millis) => open_time - millis,
+            Interval::Months(months) => shift_months(open_time, -(*months as i32)),
+        }
+    }
+
+    // How many candles open in `from..=to`, both being open times.
+    pub fn count(&self, from: i64, to: i64) -> i64 {
+        match self {
+            Interval::Millis(millis) => (to - from) / millis + 1,
+            Interval::Months(months) => {
+                let (from, to) = (month_index(from), month_index(to));
+                (to - from) / *months as i64 + 1
+            }
+        }
+    }
+}
+
+fn month_index(timestamp: i64) -> i64 {
+    let date = NaiveDateTime::from_timestamp(timestamp / 1000, 0).date();
+    date.year() as i64 * 12 + date.month0() as i64
+}
+
+// Monthly candles open at midnight on the first of the month, so that's all this has to handle.
+fn shift_months(timestamp: i64, months: i32) -> i64 {
+    let index = month_index(timestamp) + months as i64;
+    let date = NaiveDate::from_ymd((index / 12) as i32, (index % 12) as u32 + 1, 1);
+
+    date.and_hms(0, 0, 0).timestamp() * 1000
+}
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (amount, unit) = s.split_at(s.len().saturating_sub(1));
+        let amount: i64 = amount.parse().map_err(|_| anyhow!("Invalid interval {}", s))?;
+
+        if amount <= 0 {
+            return Err(anyhow!("Invalid interval {}", s));
+        }
+
+        let unit = match unit {
+            // Binance tells minutes and months apart by case.
+            "M" => return Ok(Interval::Months(amount as u32)),
+            "m" => MINUTE,
+            "h" => HOUR,
+            "d" => DAY,
+            "w" => WEEK,
+            _ => return Err(anyhow!("Invalid interval {}", s)),
+        };
+
+        Ok(Interval::Millis(amount * unit))
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interval::Months(months) => write!(f, "{}M", months),
+            Interval::Millis(millis) => {
+                let (amount, unit) = [(WEEK, "w"), (DAY, "d"), (HOUR, "h")]
+                    .iter()
+                    .find(|(length, _)| millis % length == 0)
+                    .map(|(length, unit)| (millis / length, *unit))
+                    .unwrap_or((millis / MINUTE, "m"));
+
+                write!(f, "{}{}", amount, unit)
+            }
+        }
+    }
+}
+
+// Reads candles from a CSV file with a `open_time,open,high,low,close,volume` header.
+pub fn read_csv<P: AsRef<Path>>(path: P) -> Result<Vec<Candle>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut candles = vec![];
+
+    for candle in reader.deserialize() {
+        candles.push(candle?);
+    }
+
+    Ok(candles)
+}
+
+// Reads a file from Binance's public kline archive (data.binance.vision), either the downloaded
+// .zip or the CSV inside it. Those have no header (newer ones do) and the columns
+// open_time, open, high, low, close, volume, close_time, quote_volume, trades, taker_base, taker_quote, ignore.
+pub fn read_binance_klines<P: AsRef<Path>>(path: P) -> Result<Vec<Candle>> {
+    let path = path.as_ref();
+    let mut contents = String::new();
+
+    if path.extension().map_or(false, |ext| ext == "zip") {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+        for i in 0..archive.len() {
+            archive.by_index(i)?.read_to_string(&mut contents)?;
+        }
+    } else {
+        File::open(path)?.read_to_string(&mut contents)?;
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(contents.as_bytes());
+    let mut candles = vec![];
+
+    for record in reader.records() {
+        let record = record?;
+
+        let open_time: i64 = match record.get(0).map(str::parse) {
+            Some(Ok(open_time)) => open_time,
+            // A header line.
+            _ => continue,
+        };
+
+        let column = |i: usize| -> Result<Decimal> {
+            Ok(Decimal::from_str(record.get(i).ok_or_else(|| anyhow!("Missing column {}", i))?)?)
+        };
+
+        candles.push(Candle {
+            // Archives from 2025 onwards are in microseconds.
+            open_time: if open_time > 100_000_000_000_000 { open_time / 1000 } else { open_time },
+            open: column(1)?,
+            high: column(2)?,
+            low: column(3)?,
+            close: column(4)?,
+            volume: column(5)?,
+        });
+    }
+
+    Ok(candles)
+}
+
+// Sorts candles by time and drops duplicates (e.g. from overlapping files), keeping the first.
+pub fn deduplicate(candles: &mut Vec<Candle>) {
+    candles.sort_by_key(|candle| candle.open_time);
+    candles.dedup_by_key(|candle| candle.open_time);
+}
+
+// Ranges of missing candles, as (first missing open time, last missing open time).
+// Expects the candles to be sorted.
+pub fn find_gaps(candles: &[Candle], interval: Interval) -> Vec<(i64, i64)> {
+    candles
+        .windows(2)
+        .filter(|pair| pair[1].open_time > interval.next(pair[0].open_time))
+        .map(|pair| (interval.next(pair[0].open_time), interval.previous(pair[1].open_time)))
+        .collect()
+}
+
+// Stores candles, replacing any that were stored before for the same exchange, symbol, interval and time.
+pub fn store_candles(exchange: &str, symbol: &str, interval: Interval, candles: &[Candle]) -> Result<usize> {
+    use crate::schema::candles;
+
+    let connection = crate::DATABASE.get_connection();
+    let rows = candles
+        .iter()
+        .map(|candle| StoredCandle {
+            exchange: exchange.to_string(),
+            symbol: symbol.to_string(),
+            timeframe: interval.to_string(),
+            open_time: candle.open_time,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+        })
+        .collect::<Vec<_>>();
+
+    let mut stored = 0;
+
+    // Keeps the statements a reasonable size for big archives.
+    for chunk in rows.chunks(1000) {
+        stored += with_connection!(connection, |connection| {
+            diesel::replace_into(candles::table)
+                .values(chunk)
+                .execute(connection)
+        })?;
+    }
+
+    Ok(stored)
+}
+
+// Candles with an open time in `from..=to` (milliseconds since the epoch), oldest first.
+pub fn load_candles(exchange: &str, symbol: &str, interval: Interval, from: i64, to: i64) -> Result<Vec<Candle>> {
+    use crate::schema::candles::dsl;
+
+    let connection = crate::DATABASE.get_connection();
+    let stored = with_connection!(connection, |connection| {
+        dsl::candles
+            .filter(dsl::exchange.eq(exchange))
+            .filter(dsl::symbol.eq(symbol))
+            .filter(dsl::timeframe.eq(interval.to_string()))
+            .filter(dsl::open_time.between(from, to))
+            .order(dsl::open_time.asc())
+            .load::<StoredCandle>(connection)
+    })?;
+
+    Ok(stored
+        .into_iter()
+        .map(Candle::from)
+        .collect())
+}
+
+// The most recent `count` candles, oldest first. Handy for warming up indicators.
+pub fn latest_candles(exchange: &str, symbol: &str, interval: Interval, count: i64) -> Result<Vec<Candle>> {
+    use crate::schema::candles::dsl;
+
+    let connection = crate::DATABASE.get_connection();
+    let mut stored = with_connection!(connection, |connection| {
+        dsl::candles
+            .filter(dsl::exchange.eq(exchange))
+            .filter(dsl::symbol.eq(symbol))
+            .filter(dsl::timeframe.eq(interval.to_string()))
+            .order(dsl::open_time.desc())
+            .limit(count)
+            .load::<StoredCandle>(connection)
+    })?;
+
+    stored.reverse();
+
+    Ok(stored
+        .into_iter()
+        .map(Candle::from)
+        .collect())
+}
@@ -1,4 +1,5 @@
 pub mod balances;
+pub mod candle;
 pub mod coin;
 pub mod orderbook;
 pub mod orderbook_old;
@@ -6,8 +7,9 @@ pub mod treasury;
 
 use crate::crypto::orderbook::OrderSide;
 use crate::exchanges::mandala::utils::OrderType;
+use rust_decimal::Decimal;
 
 pub struct Fees {
-    taker: f64,
-    maker: f64,
+    taker: Decimal,
+    maker: Decimal,
 }
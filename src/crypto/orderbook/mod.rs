@@ -1,21 +1,25 @@
-use decorum::Finite;
-use hashbrown::HashMap;
-use itertools::Itertools;
+use itertools::Either;
+use rust_decimal::Decimal;
 use std::{
     cmp::Ordering,
+    collections::{btree_map, BTreeMap},
     fmt::{Display, Formatter},
+    iter::{Rev, Take},
+    time::Instant,
 };
 use uuid::Uuid;
 
-// Disallows NaN and Infinite
-// Implements Ord
-type Fin64 = Finite<f64>;
-
 #[derive(Debug)]
 pub struct OrderBook {
     symbol: String,
     pub asks: Ledger,
     pub bids: Ledger,
+    // Whether the book is known to mirror the exchange. Cleared whenever the feed keeping it up to
+    // date loses track (missed updates, crossed book), brokers must not trade on an invalid book.
+    valid: bool,
+    // Set when no updates came in for a while (e.g. the websocket died), cleared by the next update.
+    stale: bool,
+    updated_at: Option<Instant>,
 }
 
 impl OrderBook {
@@ -24,22 +28,74 @@ impl OrderBook {
             symbol: symbol.into(),
             asks: Ledger::new(TailOrdering::Lowest),
             bids: Ledger::new(TailOrdering::Highest),
+            valid: false,
+            stale: false,
+            updated_at: None,
         }
     }
 
+    // Only a book that is in sync and receiving updates reflects the market.
+    #[inline]
+    pub fn is_tradable(&self) -> bool {
+        self.valid && !self.stale
+    }
+
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    pub fn validate(&mut self) {
+        self.valid = true;
+    }
+
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
     #[inline]
-    pub fn lowest_ask(&self) -> Option<Fin64> {
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    #[inline]
+    pub fn updated_at(&self) -> Option<Instant> {
+        self.updated_at
+    }
+
+    // A bid at or above the lowest ask can't exist on the exchange, so seeing one means we missed something.
+    pub fn is_crossed(&self) -> bool {
+        match (self.highest_bid(), self.lowest_ask()) {
+            (Some(bid), Some(ask)) => ask <= bid,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub fn lowest_ask(&self) -> Option<Decimal> {
         self.asks.tail()
     }
 
     #[inline]
-    pub fn highest_bid(&self) -> Option<Fin64> {
+    pub fn highest_bid(&self) -> Option<Decimal> {
         self.bids.tail()
     }
 
-    pub fn spread(&self) -> Option<f64> {
+    pub fn mid(&self) -> Option<Decimal> {
+        if let (Some(bid), Some(ask)) = (self.highest_bid(), self.lowest_ask()) {
+            return Some((bid + ask) / Decimal::new(2, 0));
+        }
+
+        None
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
         if let (Some(bid), Some(ask)) = (self.highest_bid(), self.lowest_ask()) {
-            return Some((bid - ask).into());
+            return Some(bid - ask);
         }
 
         None
@@ -52,8 +108,8 @@ impl OrderBook {
             OrderSide::Sell => &mut self.asks,
         }
         .put(
-            order.price.into(),
-            if order.quantity == 0.0 {
+            order.price,
+            if order.quantity.is_zero() {
                 None
             } else {
                 Some(order.quantity)
@@ -69,6 +125,9 @@ impl OrderBook {
     }
 
     pub fn update(&mut self, bids: Vec<Order>, asks: Vec<Order>) {
+        self.stale = false;
+        self.updated_at = Some(Instant::now());
+
         bids.iter().for_each(|order| {
             self.execute(order);
         });
@@ -89,9 +148,6 @@ impl Display for OrderBook {
             return write!(f, "OB: empty");
         }
 
-        let size = 10;
-        let round_lambda = |x: f64| (x * 10000.0).round() / 10000.0;
-
         let bids = "bids";
         let asks = "asks";
 
@@ -128,21 +184,27 @@ impl TailOrdering {
 
 #[derive(Debug)]
 pub enum TailUpdate {
-    Set(Fin64),
-    Remove(Fin64),
+    Set(Decimal),
+    Remove(Decimal),
 }
 
+/// Iterates a ledger best level first: ascending for asks, descending for bids.
+pub type LedgerIter<'a> = Either<btree_map::Iter<'a, Decimal, Decimal>, Rev<btree_map::Iter<'a, Decimal, Decimal>>>;
+
 #[derive(Debug)]
 pub struct Ledger {
-    map: HashMap<Fin64, f64>,
-    tail: Option<Fin64>,
+    // Kept sorted by price, so updates are O(log n) and walking the book from the tail outwards
+    // doesn't require sorting the whole side first.
+    map: BTreeMap<Decimal, Decimal>,
+    // Cached best level, so reading it (which brokers do on every tick) is O(1).
+    tail: Option<Decimal>,
     ordering: TailOrdering,
 }
 
 impl Ledger {
     pub fn new(ordering: TailOrdering) -> Ledger {
         Ledger {
-            map: HashMap::new(),
+            map: BTreeMap::new(),
             tail: None,
             ordering,
         }
@@ -151,7 +213,7 @@ impl Ledger {
     // quantity -> Some: updates/inserts
     // quantity -> None: removes
     // note: 0 for quantity will *insert*, not remove
-    pub fn put(&mut self, price: Fin64, quantity: Option<f64>) {
+    pub fn put(&mut self, price: Decimal, quantity: Option<Decimal>) {
         match quantity {
             Some(q) => {
                 self.set(price, q);
@@ -184,46 +246,25 @@ impl Ledger {
             }
             // A value has been removed
             TailUpdate::Remove(at) => {
-                // We only care if the current tail has a value in it.
-                // (Not that it is expected a "value remove" happens when the ledger is empty, but still)
-                if let Some(old) = self.tail {
-                    // How does the new value compare to the old tail?
-                    match self.ordering.cmp(&old, &at) {
-                        // If the tail is the same value of the value being removed, we must clear it and find a new value.
-                        Ordering::Equal => {
-                            // Replace the tail, find_new_tail() will be None if no value exists in the map anymore,
-                            // which is fine, because then the tail doesn't exist.
-                            self.tail = self.find_new_tail()
-                        }
-
-                        // If the current tail is somehow(???) "less" than the removed value,
-                        // we must panic, because this *should never happen*.
-                        //
-                        // *If* this ever happens, it denotes a tail not being calculated correctly somewhere,
-                        // because this path (TailUpdate::Remove) is the only one that CAN cut off the tail to "lesser"
-                        // value, and because EVERY removal is handled by ::Equal, this path should *never* trigger,
-                        // because that means that an ::Equal call was missed, the hashmap was edited without methods of
-                        // Ledger, or something else is fucking up.
-                        Ordering::Less => {
-                            panic!("Found Ordering::Less for TailOrdering {:?} for values (a) {} and (b) {}", self.ordering, old, at)
-                        }
-                        _ => {}
-                    }
+                // Only removing the tail itself moves it. The map is ordered, so the next best level
+                // is simply the first key on the tail's end of the map.
+                if self.tail == Some(at) {
+                    self.tail = self.find_new_tail()
                 }
             }
         }
     }
 
-    fn find_new_tail(&self) -> Option<Fin64> {
-        let keys = self.map.keys();
+    fn find_new_tail(&self) -> Option<Decimal> {
+        let mut keys = self.map.keys();
         match self.ordering {
             TailOrdering::Lowest => {
                 // We want the lowest value for the tail
-                keys.min()
+                keys.next()
             }
             TailOrdering::Highest => {
                 // We want the highest value for the tail
-                keys.max()
+                keys.next_back()
             }
         }
         .cloned()
@@ -231,17 +272,17 @@ impl Ledger {
 
     // doesn't allow mut
     #[inline]
-    pub fn tail(&self) -> Option<Fin64> {
+    pub fn tail(&self) -> Option<Decimal> {
         self.tail
     }
 
     #[inline]
-    fn set<F: Into<Fin64>>(&mut self, at: F, with: f64) {
+    fn set<F: Into<Decimal>>(&mut self, at: F, with: Decimal) {
         self.map.insert(at.into(), with);
     }
 
     #[inline]
-    fn remove<F: Into<Fin64>>(&mut self, at: F) -> bool {
+    fn remove<F: Into<Decimal>>(&mut self, at: F) -> bool {
         self.map.remove(&at.into()).is_some()
     }
 
@@ -250,10 +291,32 @@ impl Ledger {
         self.tail = None;
     }
 
-    pub fn iter(&self) -> std::vec::IntoIter<(&Fin64, &f64)> {
-        self.map
-            .iter()
-            .sorted_by(|a, b| self.ordering.cmp(&b.0, &a.0))
+    // Quantity at exactly `price`, if there is a level there.
+    #[inline]
+    pub fn get(&self, price: &Decimal) -> Option<Decimal> {
+        self.map.get(price).cloned()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> LedgerIter<'_> {
+        match self.ordering {
+            TailOrdering::Lowest => Either::Left(self.map.iter()),
+            TailOrdering::Highest => Either::Right(self.map.iter().rev()),
+        }
+    }
+
+    // The `n` best levels, best first.
+    pub fn top(&self, n: usize) -> Take<LedgerIter<'_>> {
+        self.iter().take(n)
     }
 }
 
@@ -264,12 +327,12 @@ pub struct Order {
     // to our local book instead of just relying on the data sent by an exchange. That could
     // potentially make trading faster and smarter?
     side: OrderSide,
-    quantity: f64,
-    price: f64,
+    quantity: Decimal,
+    price: Decimal,
 }
 
 impl Order {
-    pub fn new(side: OrderSide, quantity: f64, price: f64) -> Self {
+    pub fn new(side: OrderSide, quantity: Decimal, price: Decimal) -> Self {
         Self {
             side,
             quantity,
@@ -278,7 +341,7 @@ impl Order {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderSide {
     Buy,
     Sell,
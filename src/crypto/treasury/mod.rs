@@ -7,6 +7,7 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 use crate::crypto::orderbook::{OrderSide, OrderType};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use rust_decimal::Decimal;
 
 pub struct Treasury {
     treasurers: HashMap<String, Arc<Treasurer>>
@@ -58,18 +59,34 @@ impl Treasurer {
 pub enum TransactionIntent {
     Buy {
         symbol: String,
-        price: f64,
+        price: Decimal,
         meta: IntentMeta,
     },
 
     Sell {
         symbol: String,
-        price: f64,
-        amount: f64,
+        price: Decimal,
+        amount: Decimal,
         meta: IntentMeta,
     }
 }
 
+impl TransactionIntent {
+    pub fn symbol(&self) -> &str {
+        match self {
+            TransactionIntent::Buy { symbol, .. } => symbol,
+            TransactionIntent::Sell { symbol, .. } => symbol,
+        }
+    }
+
+    pub fn side(&self) -> &'static str {
+        match self {
+            TransactionIntent::Buy { .. } => "buy",
+            TransactionIntent::Sell { .. } => "sell",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IntentMeta {
     pub existing_transaction: Option<String>
@@ -80,15 +97,15 @@ pub struct IntentMeta {
 pub enum ExecutableTransaction {
     Buy {
         symbol: String,
-        price: f64,
-        amount: f64,
+        price: Decimal,
+        amount: Decimal,
         meta: TransactionMeta
     },
 
     Sell {
         symbol: String,
-        price: f64,
-        amount: f64,
+        price: Decimal,
+        amount: Decimal,
         meta: TransactionMeta
     }
 }
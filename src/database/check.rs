@@ -0,0 +1,242 @@
+use crate::database::{DatabaseConnection, Fill, FillSide, FillTotals, FinishedTransaction, Transaction};
+use crate::with_connection;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use hashbrown::HashMap;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+// Rows are read this many at a time, so big tables don't have to fit in memory.
+const BATCH_SIZE: i64 = 500;
+// The migration that converted the monetary columns to DECIMAL, see `migrated_at`.
+const DECIMAL_MIGRATION: &str = "20210321120000";
+
+// Diesel's own bookkeeping, only read to find out when the decimal migration ran.
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub table: &'static str,
+    pub id: String,
+    pub column: &'static str,
+    pub value: Decimal,
+    pub reason: String,
+    // What the value should be, when the fills tell us.
+    pub repair: Option<Decimal>,
+}
+
+// Monetary columns used to be double(8,2): anything below 0.005 was stored as 0.00 and anything
+// above 999999.99 was clamped to it.
+fn check_value(problems: &mut Vec<Problem>, table: &'static str, id: &str, column: &'static str, value: Decimal) {
+    let reason = if value.is_zero() {
+        "rounded down to zero"
+    } else if value >= dec!(999999.99) {
+        "at the maximum of the old double(8,2) column, likely overflowed"
+    } else {
+        return;
+    };
+
+    problems.push(Problem {
+        table,
+        id: id.to_string(),
+        column,
+        value,
+        reason: reason.to_string(),
+        repair: None,
+    });
+}
+
+// Prices below 1 lost everything past the second decimal (0.0567 became 0.06). A price that was
+// stored before the migration and has no more than two decimals can't be told apart from one that
+// got truncated, so it's flagged as well.
+fn check_truncated_price(problems: &mut Vec<Problem>, table: &'static str, id: &str, column: &'static str, value: Decimal, created_at: Option<NaiveDateTime>, migrated_at: Option<NaiveDateTime>) {
+    let before_migration = match (created_at, migrated_at) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(created_at), Some(migrated_at)) => created_at < migrated_at,
+    };
+
+    if !before_migration || value.is_zero() || value >= dec!(1) || value != value.round_dp(2) {
+        return;
+    }
+
+    problems.push(Problem {
+        table,
+        id: id.to_string(),
+        column,
+        value,
+        reason: "stored with two decimals before the decimal migration, likely truncated".to_string(),
+        repair: None,
+    });
+}
+
+// When the monetary columns were converted, None if they never were double(8,2) (SQLite databases,
+// or MySQL ones that haven't been migrated yet).
+fn migrated_at(connection: &DatabaseConnection) -> QueryResult<Option<NaiveDateTime>> {
+    use self::__diesel_schema_migrations::dsl;
+
+    with_connection!(connection, |connection| {
+        dsl::__diesel_schema_migrations
+            .filter(dsl::version.eq(DECIMAL_MIGRATION))
+            .select(dsl::run_on)
+            .first(connection)
+            .optional()
+    })
+}
+
+// Transactions whose amount or price were mangled by the old precision, or disagree with their buy fills.
+pub fn check_transactions(connection: &DatabaseConnection) -> QueryResult<Vec<Problem>> {
+    use crate::schema::{fills, transactions};
+
+    let migrated_at = migrated_at(connection)?;
+    let mut problems = vec![];
+    let mut last_id = String::new();
+
+    loop {
+        let batch = with_connection!(connection, |connection| {
+            transactions::table
+                .filter(transactions::id.gt(&last_id))
+                .order(transactions::id.asc())
+                .limit(BATCH_SIZE)
+                .load::<Transaction>(connection)?
+        });
+
+        let last = match batch.last() {
+            Some(last) => last.id.clone(),
+            None => break,
+        };
+
+        let ids = batch.iter().map(|t| t.id.clone()).collect::<Vec<_>>();
+        let batch_fills = with_connection!(connection, |connection| {
+            fills::table
+                .filter(fills::transaction_id.eq_any(ids))
+                .load::<Fill>(connection)?
+        });
+
+        let mut fills_by_transaction: HashMap<String, Vec<Fill>> = HashMap::new();
+
+        for fill in batch_fills {
+            fills_by_transaction.entry(fill.transaction_id.clone()).or_default().push(fill);
+        }
+
+        for transaction in batch.iter() {
+            check_transaction(&mut problems, transaction, fills_by_transaction.get(&transaction.id), migrated_at);
+        }
+
+        last_id = last;
+    }
+
+    Ok(problems)
+}
+
+fn check_transaction(problems: &mut Vec<Problem>, transaction: &Transaction, fills: Option<&Vec<Fill>>, migrated_at: Option<NaiveDateTime>) {
+    let totals = fills.and_then(|fills| FillTotals::from_fills(fills, FillSide::Buy, &transaction.symbol));
+
+    let before = problems.len();
+    check_value(problems, "transactions", &transaction.id, "amount", transaction.amount);
+    check_value(problems, "transactions", &transaction.id, "price", transaction.price);
+    check_truncated_price(problems, "transactions", &transaction.id, "price", transaction.price, transaction.created_at, migrated_at);
+
+    let totals = match totals {
+        Some(totals) => totals,
+        None => return,
+    };
+
+    for problem in problems[before..].iter_mut() {
+        problem.repair = Some(if problem.column == "amount" { totals.net_quantity() } else { totals.price });
+    }
+
+    let flagged = |problems: &[Problem], column: &str| problems[before..].iter().any(|p| p.column == column);
+
+    if !flagged(problems, "amount") && transaction.amount != totals.net_quantity() {
+        problems.push(Problem {
+            table: "transactions",
+            id: transaction.id.clone(),
+            column: "amount",
+            value: transaction.amount,
+            reason: format!("differs from the {} bought according to its fills", totals.net_quantity()),
+            repair: Some(totals.net_quantity()),
+        });
+    }
+
+    // Prices more than 0.1% off weren't just rounded differently.
+    if !flagged(problems, "price") && (transaction.price - totals.price).abs() > totals.price * dec!(0.001) {
+        problems.push(Problem {
+            table: "transactions",
+            id: transaction.id.clone(),
+            column: "price",
+            value: transaction.price,
+            reason: format!("differs from the average fill price {}", totals.price),
+            repair: Some(totals.price),
+        });
+    }
+}
+
+pub fn check_finished_transactions(connection: &DatabaseConnection) -> QueryResult<Vec<Problem>> {
+    use crate::schema::finished_transactions;
+
+    let migrated_at = migrated_at(connection)?;
+    let mut problems = vec![];
+    let mut last_id = String::new();
+
+    loop {
+        let batch = with_connection!(connection, |connection| {
+            finished_transactions::table
+                .filter(finished_transactions::id.gt(&last_id))
+                .order(finished_transactions::id.asc())
+                .limit(BATCH_SIZE)
+                .load::<FinishedTransaction>(connection)?
+        });
+
+        let last = match batch.last() {
+            Some(last) => last.id.clone(),
+            None => break,
+        };
+
+        for finished in batch {
+            let id = &finished.id;
+
+            check_value(&mut problems, "finished_transactions", id, "amount_bought", finished.amount_bought);
+            check_value(&mut problems, "finished_transactions", id, "buy_price", finished.buy_price);
+            check_value(&mut problems, "finished_transactions", id, "amount_sold", finished.amount_sold);
+            check_value(&mut problems, "finished_transactions", id, "sell_price", finished.sell_price);
+            check_truncated_price(&mut problems, "finished_transactions", id, "buy_price", finished.buy_price, finished.created_at, migrated_at);
+            check_truncated_price(&mut problems, "finished_transactions", id, "sell_price", finished.sell_price, finished.created_at, migrated_at);
+        }
+
+        last_id = last;
+    }
+
+    Ok(problems)
+}
+
+// Writes the values derived from fills back to transactions. Returns how many problems got repaired.
+pub fn repair(connection: &DatabaseConnection, problems: &[Problem]) -> QueryResult<usize> {
+    use crate::schema::transactions::dsl;
+
+    let mut repaired = 0;
+
+    for problem in problems.iter().filter(|p| p.table == "transactions") {
+        let value = match problem.repair {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let target = dsl::transactions.find(&problem.id);
+
+        repaired += with_connection!(connection, |connection| {
+            match problem.column {
+                "amount" => diesel::update(target).set(dsl::amount.eq(value)).execute(connection)?,
+                "price" => diesel::update(target).set(dsl::price.eq(value)).execute(connection)?,
+                _ => 0,
+            }
+        });
+    }
+
+    Ok(repaired)
+}
@@ -0,0 +1,155 @@
+use crate::database::DatabaseConnection;
+use crate::schema::equity_snapshots;
+use crate::with_connection;
+use chrono::{Duration, NaiveDateTime, Timelike};
+use diesel::prelude::*;
+use hashbrown::{HashMap, HashSet};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+// One asset's balance at the moment a snapshot was taken. A snapshot is every row of an exchange that
+// shares a `taken_at`.
+#[derive(Queryable, Serialize, Debug, Clone)]
+pub struct EquitySnapshot {
+    pub id: i64,
+    pub exchange_name: String,
+    pub taken_at: NaiveDateTime,
+    pub asset: String,
+    pub free: Decimal,
+    pub locked: Decimal,
+    // Quote currency per unit, None when there was no book to value the asset with.
+    pub price: Option<Decimal>,
+    pub value: Option<Decimal>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[table_name = "equity_snapshots"]
+pub struct NewEquitySnapshot {
+    pub exchange_name: String,
+    pub taken_at: NaiveDateTime,
+    pub asset: String,
+    pub free: Decimal,
+    pub locked: Decimal,
+    pub price: Option<Decimal>,
+    pub value: Option<Decimal>,
+}
+
+pub fn record_snapshot(connection: &DatabaseConnection, rows: &[NewEquitySnapshot]) -> QueryResult<usize> {
+    with_connection!(connection, |connection| {
+        diesel::insert_into(equity_snapshots::table)
+            .values(rows)
+            .execute(connection)
+    })
+}
+
+// The last snapshot of `exchange` taken at or before `at`, empty when there is none.
+pub fn snapshot_at(connection: &DatabaseConnection, exchange: &str, at: NaiveDateTime) -> QueryResult<Vec<EquitySnapshot>> {
+    use crate::schema::equity_snapshots::dsl;
+
+    with_connection!(connection, |connection| {
+        let taken_at = dsl::equity_snapshots
+            .filter(dsl::exchange_name.eq(exchange))
+            .filter(dsl::taken_at.le(at))
+            .select(dsl::taken_at)
+            .order(dsl::taken_at.desc())
+            .first::<NaiveDateTime>(connection)
+            .optional()?;
+
+        match taken_at {
+            Some(taken_at) => dsl::equity_snapshots
+                .filter(dsl::exchange_name.eq(exchange))
+                .filter(dsl::taken_at.eq(taken_at))
+                .order(dsl::asset.asc())
+                .load::<EquitySnapshot>(connection),
+            None => Ok(vec![]),
+        }
+    })
+}
+
+// Total value of every snapshot of `exchange` between `from` and `to`, oldest first. Assets that couldn't
+// be valued count as nothing.
+pub fn equity_curve(connection: &DatabaseConnection, exchange: &str, from: NaiveDateTime, to: NaiveDateTime) -> QueryResult<Vec<(NaiveDateTime, Decimal)>> {
+    use crate::schema::equity_snapshots::dsl;
+
+    let rows = with_connection!(connection, |connection| {
+        dsl::equity_snapshots
+            .filter(dsl::exchange_name.eq(exchange))
+            .filter(dsl::taken_at.between(from, to))
+            .order(dsl::taken_at.asc())
+            .load::<EquitySnapshot>(connection)?
+    });
+
+    let mut curve: Vec<(NaiveDateTime, Decimal)> = vec![];
+
+    for row in rows.iter() {
+        let value = row.value.unwrap_or_default();
+
+        match curve.last_mut() {
+            Some((taken_at, total)) if *taken_at == row.taken_at => *total += value,
+            _ => curve.push((row.taken_at, value)),
+        }
+    }
+
+    Ok(curve)
+}
+
+// Thins out old snapshots: everything younger than `keep_all` stays, up to `keep_hourly` the first
+// snapshot of every hour stays and past that the first of every day. Returns how many rows went.
+pub fn downsample(connection: &DatabaseConnection, now: NaiveDateTime, keep_all: Duration, keep_hourly: Duration) -> QueryResult<usize> {
+    use crate::schema::equity_snapshots::dsl;
+
+    let hourly_from = now - keep_all;
+
+    let times = with_connection!(connection, |connection| {
+        dsl::equity_snapshots
+            .filter(dsl::taken_at.lt(hourly_from))
+            .select((dsl::exchange_name, dsl::taken_at))
+            .distinct()
+            .load::<(String, NaiveDateTime)>(connection)?
+    });
+
+    let mut deleted = 0;
+
+    for (exchange, times) in to_downsample(times, now, keep_all, keep_hourly) {
+        // Chunked, so the IN list stays a sensible size.
+        for chunk in times.chunks(500) {
+            deleted += with_connection!(connection, |connection| {
+                diesel::delete(
+                    dsl::equity_snapshots
+                        .filter(dsl::exchange_name.eq(&exchange))
+                        .filter(dsl::taken_at.eq_any(chunk.to_vec())),
+                )
+                .execute(connection)?
+            });
+        }
+    }
+
+    Ok(deleted)
+}
+
+// The snapshot times `downsample` drops, per exchange, out of every (exchange, taken_at) there is.
+pub fn to_downsample(mut times: Vec<(String, NaiveDateTime)>, now: NaiveDateTime, keep_all: Duration, keep_hourly: Duration) -> HashMap<String, Vec<NaiveDateTime>> {
+    let hourly_from = now - keep_all;
+    let daily_from = now - keep_hourly;
+
+    times.retain(|(_, taken_at)| *taken_at < hourly_from);
+    times.sort();
+    times.dedup();
+
+    let mut kept = HashSet::new();
+    let mut dropped: HashMap<String, Vec<NaiveDateTime>> = HashMap::new();
+
+    for (exchange, taken_at) in times {
+        let bucket = if taken_at < daily_from {
+            taken_at.date().and_hms(0, 0, 0)
+        } else {
+            taken_at.date().and_hms(taken_at.hour(), 0, 0)
+        };
+
+        if !kept.insert((exchange.clone(), bucket)) {
+            dropped.entry(exchange).or_default().push(taken_at);
+        }
+    }
+
+    dropped
+}
@@ -0,0 +1,486 @@
+use crate::database::{DatabaseConnection, Fill, FillSide, FinishedTransaction, Transaction, TransactionStage};
+use crate::with_connection;
+use chrono::{Datelike, NaiveDateTime};
+use diesel::prelude::*;
+use hashbrown::HashMap;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Which coins a sale is taken to dispose of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CostMethod {
+    // The ones held longest.
+    Fifo,
+    // The ones bought most recently.
+    Lifo,
+    // All coins held share the average cost.
+    Average,
+}
+
+impl FromStr for CostMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "fifo" => Ok(CostMethod::Fifo),
+            "lifo" => Ok(CostMethod::Lifo),
+            "average" => Ok(CostMethod::Average),
+            _ => Err(anyhow!("Unknown cost method {}, expected fifo, lifo or average", s)),
+        }
+    }
+}
+
+// A buy or a sell of a coin, with its fees in the quote currency.
+#[derive(Debug, Clone)]
+pub struct LotEvent {
+    pub symbol: String,
+    pub side: FillSide,
+    pub at: NaiveDateTime,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub fee: Decimal,
+}
+
+impl LotEvent {
+    // Fees taken from the coin bought never reach the account, so they shrink the lot instead of
+    // adding to its cost. Fees in other assets than the quote currency or the coin (e.g. BNB) have no
+    // price here and are left out.
+    fn from_fill(fill: &Fill, symbol: &str, quote_currency: &str) -> Self {
+        let mut quantity = fill.quantity;
+        let mut fee = Decimal::new(0, 0);
+
+        if fill.fee_asset == quote_currency {
+            fee = fill.fee;
+        } else if fill.fee_asset == symbol {
+            match fill.side {
+                FillSide::Buy => quantity -= fill.fee,
+                FillSide::Sell => fee = fill.fee * fill.price,
+            }
+        }
+
+        Self {
+            symbol: symbol.to_string(),
+            side: fill.side,
+            at: fill.executed_at,
+            quantity,
+            price: fill.price,
+            fee,
+        }
+    }
+}
+
+// Coins acquired together that haven't all been disposed of yet.
+#[derive(Debug, Clone)]
+pub struct Lot {
+    pub symbol: String,
+    pub acquired_at: NaiveDateTime,
+    pub quantity: Decimal,
+    // What the remaining quantity cost, buy fees included.
+    pub cost: Decimal,
+}
+
+// The part of a sale matched against a single lot.
+#[derive(Debug, Clone)]
+pub struct Disposal {
+    pub symbol: String,
+    // None when the coins sold were never bought here, their cost basis is unknown and taken as zero.
+    pub acquired_at: Option<NaiveDateTime>,
+    pub sold_at: NaiveDateTime,
+    pub quantity: Decimal,
+    // After sell fees.
+    pub proceeds: Decimal,
+    pub cost_basis: Decimal,
+}
+
+impl Disposal {
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.cost_basis
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DisposalRow {
+    symbol: String,
+    quantity: Decimal,
+    date_acquired: String,
+    date_sold: String,
+    proceeds: Decimal,
+    cost_basis: Decimal,
+    gain: Decimal,
+}
+
+#[derive(Debug)]
+pub struct LotBook {
+    method: CostMethod,
+    lots: HashMap<String, Vec<Lot>>,
+    disposals: Vec<Disposal>,
+}
+
+impl LotBook {
+    pub fn new(method: CostMethod) -> Self {
+        Self {
+            method,
+            lots: HashMap::new(),
+            disposals: vec![],
+        }
+    }
+
+    // Runs `events` through a new book in the order they happened. Buys go first when a buy and a sell
+    // share a timestamp.
+    pub fn from_events(method: CostMethod, mut events: Vec<LotEvent>) -> Self {
+        events.sort_by_key(|event| (event.at, event.side == FillSide::Sell));
+
+        let mut book = Self::new(method);
+
+        for event in events.iter() {
+            match event.side {
+                FillSide::Buy => book.acquire(event),
+                FillSide::Sell => book.dispose(event),
+            }
+        }
+
+        book
+    }
+
+    pub fn acquire(&mut self, event: &LotEvent) {
+        if event.quantity <= Decimal::new(0, 0) {
+            return;
+        }
+
+        self.lots.entry(event.symbol.clone()).or_default().push(Lot {
+            symbol: event.symbol.clone(),
+            acquired_at: event.at,
+            quantity: event.quantity,
+            cost: event.quantity * event.price + event.fee,
+        });
+    }
+
+    pub fn dispose(&mut self, event: &LotEvent) {
+        if event.quantity <= Decimal::new(0, 0) {
+            return;
+        }
+
+        let method = self.method;
+        let lots = self.lots.entry(event.symbol.clone()).or_default();
+        let proceeds = event.quantity * event.price - event.fee;
+        let mut remaining = event.quantity;
+
+        // Average cost per coin over everything held, before this sale.
+        let held: Decimal = lots.iter().map(|lot| lot.quantity).sum();
+        let average = if held.is_zero() {
+            Decimal::new(0, 0)
+        } else {
+            lots.iter().map(|lot| lot.cost).sum::<Decimal>() / held
+        };
+
+        while remaining > Decimal::new(0, 0) && !lots.is_empty() {
+            // Average cost still needs dates for the disposals, it takes them oldest first.
+            let index = match method {
+                CostMethod::Fifo | CostMethod::Average => 0,
+                CostMethod::Lifo => lots.len() - 1,
+            };
+
+            let lot = &mut lots[index];
+            let quantity = std::cmp::min(remaining, lot.quantity);
+            let cost_basis = match method {
+                CostMethod::Average => average * quantity,
+                _ => lot.cost * quantity / lot.quantity,
+            };
+
+            self.disposals.push(Disposal {
+                symbol: event.symbol.clone(),
+                acquired_at: Some(lot.acquired_at),
+                sold_at: event.at,
+                quantity,
+                proceeds: proceeds * quantity / event.quantity,
+                cost_basis,
+            });
+
+            lot.cost -= lot.cost * quantity / lot.quantity;
+            lot.quantity -= quantity;
+            remaining -= quantity;
+
+            if lot.quantity.is_zero() {
+                lots.remove(index);
+            }
+        }
+
+        if method == CostMethod::Average {
+            for lot in lots.iter_mut() {
+                lot.cost = average * lot.quantity;
+            }
+        }
+
+        if remaining > Decimal::new(0, 0) {
+            warn!(
+                "Sold {} {} more than was bought on {}, taking a cost basis of zero for it",
+                remaining,
+                &event.symbol,
+                event.at.format(DATE_FORMAT)
+            );
+
+            self.disposals.push(Disposal {
+                symbol: event.symbol.clone(),
+                acquired_at: None,
+                sold_at: event.at,
+                quantity: remaining,
+                proceeds: proceeds * remaining / event.quantity,
+                cost_basis: Decimal::new(0, 0),
+            });
+        }
+    }
+
+    pub fn disposals(&self) -> &[Disposal] {
+        &self.disposals
+    }
+
+    pub fn disposals_in(&self, year: i32) -> Vec<&Disposal> {
+        self.disposals.iter().filter(|d| d.sold_at.year() == year).collect()
+    }
+
+    // Lots still held, oldest first.
+    pub fn open_lots(&self) -> Vec<&Lot> {
+        let mut lots = self.lots.values().flatten().collect::<Vec<_>>();
+
+        lots.sort_by_key(|lot| lot.acquired_at);
+        lots
+    }
+}
+
+// Every buy and sell from the transaction history. Fills are used where they were recorded, older
+// transactions fall back to the amounts and prices stored on them, without fees.
+pub fn load_events(connection: &DatabaseConnection, quote_currency: &str) -> QueryResult<Vec<LotEvent>> {
+    use crate::schema::{fills, finished_transactions, transactions};
+
+    let (all, finished, all_fills) = with_connection!(connection, |connection| {
+        (
+            transactions::table.load::<Transaction>(connection)?,
+            finished_transactions::table.load::<FinishedTransaction>(connection)?,
+            fills::table.load::<Fill>(connection)?,
+        )
+    });
+
+    let mut fills_by_transaction: HashMap<String, Vec<Fill>> = HashMap::new();
+
+    for fill in all_fills {
+        fills_by_transaction.entry(fill.transaction_id.clone()).or_default().push(fill);
+    }
+
+    let finished_by_transaction = finished
+        .into_iter()
+        .map(|finished| (finished.transaction_id.clone(), finished))
+        .collect::<HashMap<_, _>>();
+
+    let mut events = vec![];
+
+    for transaction in all.iter() {
+        let fills = fills_by_transaction.get(&transaction.id).map(Vec::as_slice).unwrap_or(&[]);
+        let has_fills = |side: FillSide| fills.iter().any(|f| f.side == side);
+
+        events.extend(fills.iter().map(|fill| LotEvent::from_fill(fill, &transaction.symbol, quote_currency)));
+
+        // A partially filled buy without fills has bought some unknown part of its amount, so it's left
+        // out until it fills.
+        let bought = match transaction.stage {
+            TransactionStage::BuyTransactionOpen | TransactionStage::BuyTransactionPartiallyFilled | TransactionStage::Canceled => false,
+            _ => true,
+        };
+
+        if bought && !has_fills(FillSide::Buy) {
+            if let Some(at) = transaction.created_at {
+                events.push(LotEvent {
+                    symbol: transaction.symbol.clone(),
+                    side: FillSide::Buy,
+                    at,
+                    quantity: transaction.amount,
+                    price: transaction.price,
+                    fee: Decimal::new(0, 0),
+                });
+            }
+        }
+
+        // Finished rows were written when sells got placed too, only a finished transaction sold for sure.
+        if has_fills(FillSide::Sell) || transaction.stage != TransactionStage::Finished {
+            continue;
+        }
+
+        if let Some(finished) = finished_by_transaction.get(&transaction.id) {
+            if let Some(at) = finished.created_at {
+                events.push(LotEvent {
+                    symbol: transaction.symbol.clone(),
+                    side: FillSide::Sell,
+                    at,
+                    quantity: finished.amount_sold,
+                    price: finished.sell_price,
+                    fee: Decimal::new(0, 0),
+                });
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+// Writes `disposals` as CSV, amounts in the quote currency.
+pub fn write_csv<W: Write>(disposals: &[&Disposal], writer: W) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for disposal in disposals.iter() {
+        writer.serialize(DisposalRow {
+            symbol: disposal.symbol.clone(),
+            quantity: disposal.quantity,
+            date_acquired: disposal.acquired_at.map(|at| at.format(DATE_FORMAT).to_string()).unwrap_or_default(),
+            date_sold: disposal.sold_at.format(DATE_FORMAT).to_string(),
+            proceeds: disposal.proceeds.round_dp(8),
+            cost_basis: disposal.cost_basis.round_dp(8),
+            gain: disposal.gain().round_dp(8),
+        })?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    fn fill(side: FillSide, day: u32, quantity: Decimal, price: Decimal, fee: Decimal, fee_asset: &str) -> Fill {
+        Fill {
+            id: day as i64,
+            transaction_id: "transaction".to_string(),
+            exchange_name: "mandala".to_string(),
+            order_id: format!("order-{}", day),
+            trade_id: format!("trade-{}", day),
+            side,
+            quantity,
+            price,
+            fee,
+            fee_asset: fee_asset.to_string(),
+            executed_at: NaiveDate::from_ymd(2021, 3, day).and_hms(12, 0, 0),
+        }
+    }
+
+    // 100 ADA at 1 with 0.1 USDT in fees, 100 ADA at 2 after 0.2 ADA in fees, then 150 sold at 3 with
+    // 0.45 USDT in fees, so 449.55 in proceeds.
+    fn buy_buy_sell() -> Vec<LotEvent> {
+        vec![
+            fill(FillSide::Buy, 1, dec!(100), dec!(1), dec!(0.1), "USDT"),
+            fill(FillSide::Buy, 2, dec!(100.2), dec!(2), dec!(0.2), "ADA"),
+            fill(FillSide::Sell, 3, dec!(150), dec!(3), dec!(0.45), "USDT"),
+        ]
+        .iter()
+        .map(|fill| LotEvent::from_fill(fill, "ADA", "USDT"))
+        .collect()
+    }
+
+    fn amounts(book: &LotBook) -> Vec<(Decimal, Decimal, Decimal, Decimal)> {
+        book.disposals()
+            .iter()
+            .map(|d| (d.quantity, d.proceeds, d.cost_basis, d.gain()))
+            .collect()
+    }
+
+    #[test]
+    fn fees_in_the_quote_currency_add_to_the_cost() {
+        let event = LotEvent::from_fill(&fill(FillSide::Buy, 1, dec!(100), dec!(1), dec!(0.1), "USDT"), "ADA", "USDT");
+
+        assert_eq!(event.quantity, dec!(100));
+        assert_eq!(event.fee, dec!(0.1));
+    }
+
+    #[test]
+    fn fees_in_the_coin_shrink_a_buy_and_are_priced_on_a_sell() {
+        let buy = LotEvent::from_fill(&fill(FillSide::Buy, 1, dec!(100.2), dec!(2), dec!(0.2), "ADA"), "ADA", "USDT");
+        let sell = LotEvent::from_fill(&fill(FillSide::Sell, 2, dec!(10), dec!(3), dec!(0.01), "ADA"), "ADA", "USDT");
+        let other = LotEvent::from_fill(&fill(FillSide::Buy, 3, dec!(10), dec!(3), dec!(0.001), "BNB"), "ADA", "USDT");
+
+        assert_eq!((buy.quantity, buy.fee), (dec!(100), dec!(0)));
+        assert_eq!((sell.quantity, sell.fee), (dec!(10), dec!(0.03)));
+        assert_eq!((other.quantity, other.fee), (dec!(10), dec!(0)));
+    }
+
+    #[test]
+    fn fifo_disposes_of_the_oldest_lot_first() {
+        let book = LotBook::from_events(CostMethod::Fifo, buy_buy_sell());
+
+        assert_eq!(amounts(&book), vec![
+            (dec!(100), dec!(299.70), dec!(100.1), dec!(199.60)),
+            (dec!(50), dec!(149.85), dec!(100), dec!(49.85)),
+        ]);
+
+        let open = book.open_lots();
+        assert_eq!(open.len(), 1);
+        assert_eq!((open[0].quantity, open[0].cost), (dec!(50), dec!(100)));
+    }
+
+    #[test]
+    fn lifo_disposes_of_the_newest_lot_first() {
+        let book = LotBook::from_events(CostMethod::Lifo, buy_buy_sell());
+
+        assert_eq!(amounts(&book), vec![
+            (dec!(100), dec!(299.70), dec!(200), dec!(99.70)),
+            (dec!(50), dec!(149.85), dec!(50.05), dec!(99.80)),
+        ]);
+
+        let open = book.open_lots();
+        assert_eq!(open.len(), 1);
+        assert_eq!((open[0].quantity, open[0].cost), (dec!(50), dec!(50.05)));
+    }
+
+    #[test]
+    fn average_cost_spreads_the_cost_over_everything_held() {
+        let book = LotBook::from_events(CostMethod::Average, buy_buy_sell());
+
+        // 300.1 for 200 coins is 1.5005 a coin.
+        assert_eq!(amounts(&book), vec![
+            (dec!(100), dec!(299.70), dec!(150.05), dec!(149.65)),
+            (dec!(50), dec!(149.85), dec!(75.025), dec!(74.825)),
+        ]);
+
+        let open = book.open_lots();
+        assert_eq!(open.len(), 1);
+        assert_eq!((open[0].quantity, open[0].cost), (dec!(50), dec!(75.025)));
+    }
+
+    #[test]
+    fn average_cost_starts_from_what_is_left_after_a_sale() {
+        let mut events = buy_buy_sell();
+        events.push(LotEvent::from_fill(&fill(FillSide::Buy, 4, dec!(50), dec!(1), dec!(0), "USDT"), "ADA", "USDT"));
+        events.push(LotEvent::from_fill(&fill(FillSide::Sell, 5, dec!(100), dec!(2), dec!(0), "USDT"), "ADA", "USDT"));
+
+        let book = LotBook::from_events(CostMethod::Average, events);
+        let later = book.disposals().iter().filter(|d| d.sold_at.day() == 5).collect::<Vec<_>>();
+
+        // 75.025 left plus 50 bought, for 100 coins.
+        assert_eq!(later.iter().map(|d| d.cost_basis).sum::<Decimal>(), dec!(125.025));
+        assert_eq!(later.iter().map(|d| d.proceeds).sum::<Decimal>(), dec!(200));
+        assert!(book.open_lots().is_empty());
+    }
+
+    #[test]
+    fn selling_more_than_was_bought_takes_a_zero_cost_basis() {
+        let events = vec![
+            fill(FillSide::Buy, 1, dec!(10), dec!(1), dec!(0), "USDT"),
+            fill(FillSide::Sell, 2, dec!(15), dec!(2), dec!(0.3), "USDT"),
+        ]
+        .iter()
+        .map(|fill| LotEvent::from_fill(fill, "ADA", "USDT"))
+        .collect();
+
+        let book = LotBook::from_events(CostMethod::Fifo, events);
+        let disposals = book.disposals();
+
+        assert_eq!(disposals.len(), 2);
+        assert_eq!((disposals[0].proceeds, disposals[0].cost_basis), (dec!(19.8), dec!(10)));
+        assert_eq!(disposals[1].acquired_at, None);
+        assert_eq!((disposals[1].quantity, disposals[1].proceeds, disposals[1].cost_basis), (dec!(5), dec!(9.9), dec!(0)));
+    }
+}
@@ -0,0 +1,439 @@
+use crate::database::{DatabaseConnection, Fill, FinishedTransaction, Transaction, TransactionStage};
+use crate::with_connection;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use hashbrown::HashMap;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::str::FromStr;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    fn key(&self, at: NaiveDateTime) -> String {
+        match self {
+            Period::Day => at.format("%Y-%m-%d").to_string(),
+            Period::Week => at.format("%G-W%V").to_string(),
+            Period::Month => at.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            _ => Err(anyhow!("Unknown period {}, expected day, week or month", s)),
+        }
+    }
+}
+
+// A transaction that was bought and sold again.
+#[derive(Debug, Clone)]
+pub struct RealisedTrade {
+    pub transaction_id: String,
+    pub symbol: String,
+    pub bought_at: NaiveDateTime,
+    pub sold_at: NaiveDateTime,
+    pub cost: Decimal,
+    pub proceeds: Decimal,
+    pub fees: Decimal,
+}
+
+impl RealisedTrade {
+    pub fn pnl(&self) -> Decimal {
+        self.proceeds - self.cost - self.fees
+    }
+
+    pub fn hold_seconds(&self) -> i64 {
+        (self.sold_at - self.bought_at).num_seconds()
+    }
+}
+
+// Coins bought that haven't been sold yet.
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub transaction_id: String,
+    pub symbol: String,
+    pub stage: TransactionStage,
+    pub amount: Decimal,
+    pub price: Decimal,
+    pub bought_at: Option<NaiveDateTime>,
+    pub fees: Decimal,
+    // What the coins are worth now, per coin. Unknown until marked.
+    pub mark: Option<Decimal>,
+}
+
+impl Position {
+    pub fn cost(&self) -> Decimal {
+        self.amount * self.price
+    }
+
+    pub fn unrealised_pnl(&self) -> Option<Decimal> {
+        self.mark.map(|mark| self.amount * mark - self.cost() - self.fees)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TradeRow {
+    pub transaction_id: String,
+    pub symbol: String,
+    pub bought_at: String,
+    pub sold_at: String,
+    pub cost: Decimal,
+    pub proceeds: Decimal,
+    pub fees: Decimal,
+    pub pnl: Decimal,
+    pub hold_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoinRow {
+    pub symbol: String,
+    pub trades: usize,
+    pub realised_pnl: Decimal,
+    pub fees: Decimal,
+    pub win_rate: Decimal,
+    pub average_hold_seconds: i64,
+    pub held: Decimal,
+    pub unrealised_pnl: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeriodRow {
+    pub period: String,
+    pub trades: usize,
+    pub realised_pnl: Decimal,
+    pub fees: Decimal,
+    pub win_rate: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PositionRow {
+    pub transaction_id: String,
+    pub symbol: String,
+    pub stage: String,
+    pub held_since: Option<String>,
+    pub amount: Decimal,
+    pub buy_price: Decimal,
+    pub mark_price: Option<Decimal>,
+    pub cost: Decimal,
+    pub unrealised_pnl: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PnlSummary {
+    pub from: String,
+    pub to: String,
+    pub quote_currency: String,
+    pub trades: usize,
+    pub realised_pnl: Decimal,
+    // None when any held position couldn't be marked.
+    pub unrealised_pnl: Option<Decimal>,
+    pub fees: Decimal,
+    pub win_rate: Decimal,
+    pub average_hold_seconds: i64,
+    pub coins: Vec<CoinRow>,
+    pub periods: Vec<PeriodRow>,
+    pub positions: Vec<PositionRow>,
+}
+
+// Realised PnL of the transactions finished between `from` and `to`, and the positions held right now.
+// Only finished rows of transactions that did finish count as realised, rows older versions wrote for
+// sells that were still open, or got canceled after, are left out. A transaction is either a trade or
+// a position, never both.
+// Fees come from the fills: the ones paid in the quote currency or the traded coin are counted, fees
+// paid in any other asset (e.g. BNB) have no price here and are left out.
+#[derive(Debug)]
+pub struct PnlReport {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub quote_currency: String,
+    pub trades: Vec<RealisedTrade>,
+    pub positions: Vec<Position>,
+}
+
+impl PnlReport {
+    pub fn load<T: Into<String>>(connection: &DatabaseConnection, from: NaiveDateTime, to: NaiveDateTime, quote_currency: T) -> QueryResult<Self> {
+        use crate::schema::{fills, finished_transactions, transactions};
+
+        let held_stages = PnlReport::held_stages();
+
+        let (finished, held) = with_connection!(connection, |connection| {
+            (
+                finished_transactions::table
+                    .inner_join(transactions::table)
+                    .filter(transactions::stage.eq(TransactionStage::Finished))
+                    .filter(finished_transactions::created_at.between(from, to))
+                    .order(finished_transactions::created_at.asc())
+                    .load::<(FinishedTransaction, Transaction)>(connection)?,
+                transactions::table
+                    .filter(transactions::stage.eq_any(held_stages))
+                    .order(transactions::created_at.asc())
+                    .load::<Transaction>(connection)?,
+            )
+        });
+
+        let ids = finished
+            .iter()
+            .map(|(_, transaction)| transaction.id.clone())
+            .chain(held.iter().map(|transaction| transaction.id.clone()))
+            .collect::<Vec<_>>();
+
+        let all_fills = with_connection!(connection, |connection| {
+            fills::table
+                .filter(fills::transaction_id.eq_any(ids))
+                .load::<Fill>(connection)?
+        });
+
+        Ok(Self::new(from, to, quote_currency, finished, held, all_fills))
+    }
+
+    // Stages in which a transaction holds coins.
+    pub fn held_stages() -> Vec<TransactionStage> {
+        vec![
+            TransactionStage::Hodl,
+            TransactionStage::SellTransactionOpen,
+            TransactionStage::SellTransactionPartiallyFilled,
+        ]
+    }
+
+    // Builds the report from the rows `load` reads: the finished rows in the period with their
+    // transactions, the held transactions and the fills of both.
+    pub fn new<T: Into<String>>(from: NaiveDateTime, to: NaiveDateTime, quote_currency: T, finished: Vec<(FinishedTransaction, Transaction)>, held: Vec<Transaction>, all_fills: Vec<Fill>) -> Self {
+        let quote_currency = quote_currency.into();
+        let mut fills_by_transaction: HashMap<String, Vec<Fill>> = HashMap::new();
+
+        for fill in all_fills {
+            fills_by_transaction.entry(fill.transaction_id.clone()).or_default().push(fill);
+        }
+
+        let fees = |transaction: &Transaction| {
+            fills_by_transaction
+                .get(&transaction.id)
+                .map_or(Decimal::new(0, 0), |fills| quote_fees(fills, &transaction.symbol, &quote_currency))
+        };
+
+        let trades = finished
+            .iter()
+            .map(|(finished, transaction)| {
+                let sold_at = finished.created_at.unwrap_or(to);
+
+                RealisedTrade {
+                    transaction_id: transaction.id.clone(),
+                    symbol: transaction.symbol.clone(),
+                    bought_at: transaction.created_at.unwrap_or(sold_at),
+                    sold_at,
+                    cost: finished.amount_bought * finished.buy_price,
+                    proceeds: finished.amount_sold * finished.sell_price,
+                    fees: fees(transaction),
+                }
+            })
+            .collect();
+
+        let positions = held
+            .iter()
+            .map(|transaction| Position {
+                transaction_id: transaction.id.clone(),
+                symbol: transaction.symbol.clone(),
+                stage: transaction.stage,
+                amount: transaction.amount,
+                price: transaction.price,
+                bought_at: transaction.created_at,
+                fees: fees(transaction),
+                mark: None,
+            })
+            .collect();
+
+        Self {
+            from,
+            to,
+            quote_currency,
+            trades,
+            positions,
+        }
+    }
+
+    // Every symbol a position is held in, to fetch marking prices for.
+    pub fn held_symbols(&self) -> Vec<String> {
+        let mut symbols = self.positions.iter().map(|p| p.symbol.clone()).collect::<Vec<_>>();
+
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    pub fn mark(&mut self, prices: &HashMap<String, Decimal>) {
+        for position in self.positions.iter_mut() {
+            position.mark = prices.get(&position.symbol).copied();
+        }
+    }
+
+    pub fn realised_pnl(&self) -> Decimal {
+        self.trades.iter().map(|t| t.pnl()).sum()
+    }
+
+    pub fn unrealised_pnl(&self) -> Option<Decimal> {
+        self.positions.iter().map(|p| p.unrealised_pnl()).sum()
+    }
+
+    pub fn fees(&self) -> Decimal {
+        self.trades.iter().map(|t| t.fees).sum()
+    }
+
+    pub fn coins(&self) -> Vec<CoinRow> {
+        let mut trades_by_symbol: HashMap<&str, Vec<&RealisedTrade>> = HashMap::new();
+        let mut positions_by_symbol: HashMap<&str, Vec<&Position>> = HashMap::new();
+
+        for trade in self.trades.iter() {
+            trades_by_symbol.entry(trade.symbol.as_str()).or_default().push(trade);
+        }
+
+        for position in self.positions.iter() {
+            positions_by_symbol.entry(position.symbol.as_str()).or_default().push(position);
+        }
+
+        let mut symbols = trades_by_symbol.keys().chain(positions_by_symbol.keys()).copied().collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup();
+
+        symbols
+            .into_iter()
+            .map(|symbol| {
+                let trades = trades_by_symbol.get(symbol).cloned().unwrap_or_default();
+                let positions = positions_by_symbol.get(symbol).cloned().unwrap_or_default();
+
+                CoinRow {
+                    symbol: symbol.to_string(),
+                    trades: trades.len(),
+                    realised_pnl: trades.iter().map(|t| t.pnl()).sum(),
+                    fees: trades.iter().map(|t| t.fees).sum(),
+                    win_rate: win_rate(&trades),
+                    average_hold_seconds: average_hold_seconds(&trades),
+                    held: positions.iter().map(|p| p.amount).sum(),
+                    unrealised_pnl: positions.iter().map(|p| p.unrealised_pnl()).sum(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn periods(&self, period: Period) -> Vec<PeriodRow> {
+        let mut by_period: HashMap<String, Vec<&RealisedTrade>> = HashMap::new();
+
+        for trade in self.trades.iter() {
+            by_period.entry(period.key(trade.sold_at)).or_default().push(trade);
+        }
+
+        let mut periods = by_period
+            .into_iter()
+            .map(|(period, trades)| PeriodRow {
+                period,
+                trades: trades.len(),
+                realised_pnl: trades.iter().map(|t| t.pnl()).sum(),
+                fees: trades.iter().map(|t| t.fees).sum(),
+                win_rate: win_rate(&trades),
+            })
+            .collect::<Vec<_>>();
+
+        periods.sort_by(|a, b| a.period.cmp(&b.period));
+        periods
+    }
+
+    pub fn trade_rows(&self) -> Vec<TradeRow> {
+        self.trades
+            .iter()
+            .map(|trade| TradeRow {
+                transaction_id: trade.transaction_id.clone(),
+                symbol: trade.symbol.clone(),
+                bought_at: trade.bought_at.format(TIME_FORMAT).to_string(),
+                sold_at: trade.sold_at.format(TIME_FORMAT).to_string(),
+                cost: trade.cost.round_dp(8),
+                proceeds: trade.proceeds.round_dp(8),
+                fees: trade.fees.round_dp(8),
+                pnl: trade.pnl().round_dp(8),
+                hold_seconds: trade.hold_seconds(),
+            })
+            .collect()
+    }
+
+    pub fn position_rows(&self) -> Vec<PositionRow> {
+        self.positions
+            .iter()
+            .map(|position| PositionRow {
+                transaction_id: position.transaction_id.clone(),
+                symbol: position.symbol.clone(),
+                stage: position.stage.to_string(),
+                held_since: position.bought_at.map(|at| at.format(TIME_FORMAT).to_string()),
+                amount: position.amount,
+                buy_price: position.price,
+                mark_price: position.mark,
+                cost: position.cost().round_dp(8),
+                unrealised_pnl: position.unrealised_pnl().map(|pnl| pnl.round_dp(8)),
+            })
+            .collect()
+    }
+
+    pub fn summary(&self, period: Period) -> PnlSummary {
+        let trades = self.trades.iter().collect::<Vec<_>>();
+
+        PnlSummary {
+            from: self.from.format(TIME_FORMAT).to_string(),
+            to: self.to.format(TIME_FORMAT).to_string(),
+            quote_currency: self.quote_currency.clone(),
+            trades: self.trades.len(),
+            realised_pnl: self.realised_pnl().round_dp(8),
+            unrealised_pnl: self.unrealised_pnl().map(|pnl| pnl.round_dp(8)),
+            fees: self.fees().round_dp(8),
+            win_rate: win_rate(&trades),
+            average_hold_seconds: average_hold_seconds(&trades),
+            coins: self.coins(),
+            periods: self.periods(period),
+            positions: self.position_rows(),
+        }
+    }
+}
+
+// Fees of `fills` in the quote currency. Fees taken from the coin itself are valued at the fill price.
+fn quote_fees(fills: &[Fill], symbol: &str, quote_currency: &str) -> Decimal {
+    fills
+        .iter()
+        .map(|fill| {
+            if fill.fee_asset == quote_currency {
+                fill.fee
+            } else if fill.fee_asset == symbol {
+                fill.fee * fill.price
+            } else {
+                Decimal::new(0, 0)
+            }
+        })
+        .sum()
+}
+
+fn win_rate(trades: &[&RealisedTrade]) -> Decimal {
+    if trades.is_empty() {
+        return Decimal::new(0, 0);
+    }
+
+    let wins = trades.iter().filter(|t| t.pnl() > Decimal::new(0, 0)).count();
+
+    Decimal::from(wins as u64) / Decimal::from(trades.len() as u64)
+}
+
+fn average_hold_seconds(trades: &[&RealisedTrade]) -> i64 {
+    if trades.is_empty() {
+        return 0;
+    }
+
+    trades.iter().map(|t| t.hold_seconds()).sum::<i64>() / trades.len() as i64
+}
@@ -0,0 +1,706 @@
+use crate::database::equity::{self, NewEquitySnapshot};
+use crate::database::pnl::PnlReport;
+use crate::database::{self, Fill, FinishedTransaction, NewFill, StageTransition, Transaction, TransactionStage, UpdateTransactionStageForm};
+use crate::with_connection;
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::dsl::count;
+use diesel::prelude::*;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+
+// Everything the bot stores about its transactions, and the account they're made from. Live this is
+// the database, tests and simulations can keep it in memory.
+pub trait TransactionRepository: std::fmt::Debug + Send + Sync {
+    fn count_for_pair(&self, symbol: &str, stages: &[TransactionStage]) -> Result<i64>;
+    fn for_pair(&self, symbol: &str, stages: &[TransactionStage]) -> Result<Vec<Transaction>>;
+    // Transactions on `exchange` in any open stage.
+    fn open_for_exchange(&self, exchange: &str) -> Result<Vec<Transaction>>;
+    fn find(&self, id: &str) -> Result<Option<Transaction>>;
+    // The transaction that placed `order_id` on `exchange`, as its buy or its sell.
+    fn find_by_order(&self, exchange: &str, order_id: &str) -> Result<Option<Transaction>>;
+    // Stores a new transaction, along with the stage it starts in.
+    fn insert(&self, transaction: &Transaction) -> Result<()>;
+    // Moves a transaction to another stage, see `database::transition`.
+    fn transition(&self, transaction: &Transaction, changes: UpdateTransactionStageForm) -> Result<()>;
+    // Moves a selling transaction to Finished and books the sale, both or neither. A held transaction
+    // whose coins were sold outside the bot passes through SellTransactionOpen (with the finished sell
+    // order) on the way.
+    fn finish(&self, transaction: &Transaction, changes: UpdateTransactionStageForm, finished: &FinishedTransaction) -> Result<()>;
+    fn stage_history(&self, transaction_id: &str) -> Result<Vec<StageTransition>>;
+    // Stores fills, skipping ones that were stored before. Returns how many were new.
+    fn record_fills(&self, fills: &[NewFill]) -> Result<usize>;
+    fn fills_for(&self, transaction_id: &str) -> Result<Vec<Fill>>;
+    // Proceeds minus cost of every finished transaction, before fees. Like `PnlReport`, only counts
+    // transactions that are Finished.
+    fn realised_pnl(&self) -> Result<Decimal>;
+    // Finished rows of Finished transactions booked between `from` and `to`, with their transaction,
+    // oldest first.
+    fn finished_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<Vec<(FinishedTransaction, Transaction)>>;
+    // See `PnlReport`, positions come without a mark.
+    fn pnl_report(&self, from: NaiveDateTime, to: NaiveDateTime, quote_currency: &str) -> Result<PnlReport>;
+    fn record_equity(&self, snapshot: &[NewEquitySnapshot]) -> Result<usize>;
+    // Thins out old equity snapshots, see `equity::downsample`. Returns how many rows went.
+    fn prune_equity(&self, now: NaiveDateTime, keep_all: Duration, keep_hourly: Duration) -> Result<usize>;
+}
+
+#[derive(Debug, Default)]
+pub struct DieselTransactionRepository;
+
+impl TransactionRepository for DieselTransactionRepository {
+    fn count_for_pair(&self, symbol: &str, stages: &[TransactionStage]) -> Result<i64> {
+        use crate::schema::transactions::dsl;
+
+        let connection = crate::DATABASE.get_connection();
+
+        Ok(with_connection!(connection, |connection| {
+            dsl::transactions
+                .filter(dsl::stage.eq_any(stages.to_vec()))
+                .filter(dsl::symbol.eq(symbol))
+                .select(count(dsl::id))
+                .first(connection)
+        })?)
+    }
+
+    fn for_pair(&self, symbol: &str, stages: &[TransactionStage]) -> Result<Vec<Transaction>> {
+        use crate::schema::transactions::dsl;
+
+        let connection = crate::DATABASE.get_connection();
+
+        Ok(with_connection!(connection, |connection| {
+            dsl::transactions
+                .filter(dsl::stage.eq_any(stages.to_vec()))
+                .filter(dsl::symbol.eq(symbol))
+                .load::<Transaction>(connection)
+        })?)
+    }
+
+    fn open_for_exchange(&self, exchange: &str) -> Result<Vec<Transaction>> {
+        use crate::schema::transactions::dsl;
+
+        let connection = crate::DATABASE.get_connection();
+
+        Ok(with_connection!(connection, |connection| {
+            dsl::transactions
+                .filter(dsl::exchange_name.eq(exchange))
+                .filter(dsl::stage.eq_any(TransactionStage::open()))
+                .load::<Transaction>(connection)
+        })?)
+    }
+
+    fn find(&self, id: &str) -> Result<Option<Transaction>> {
+        use crate::schema::transactions::dsl;
+
+        let connection = crate::DATABASE.get_connection();
+
+        Ok(with_connection!(connection, |connection| {
+            dsl::transactions.find(id).first::<Transaction>(connection).optional()
+        })?)
+    }
+
+    fn find_by_order(&self, exchange: &str, order_id: &str) -> Result<Option<Transaction>> {
+        use crate::schema::transactions::dsl;
+
+        let connection = crate::DATABASE.get_connection();
+
+        Ok(with_connection!(connection, |connection| {
+            dsl::transactions
+                .filter(dsl::exchange_name.eq(exchange))
+                .filter(dsl::buy_exchange_id.eq(order_id).or(dsl::sell_exchange_id.eq(order_id)))
+                .first::<Transaction>(connection)
+                .optional()
+        })?)
+    }
+
+    fn insert(&self, transaction: &Transaction) -> Result<()> {
+        use crate::schema::transactions;
+
+        let pooled = crate::DATABASE.get_connection();
+
+        with_connection!(pooled, |connection| {
+            connection.transaction::<_, diesel::result::Error, _>(|| {
+                diesel::insert_into(transactions::table)
+                    .values(transaction)
+                    .execute(connection)?;
+                database::record_stage(&pooled, transaction)?;
+
+                Ok(())
+            })
+        })?;
+
+        Ok(())
+    }
+
+    fn transition(&self, transaction: &Transaction, changes: UpdateTransactionStageForm) -> Result<()> {
+        database::transition(&crate::DATABASE.get_connection(), transaction, changes)
+    }
+
+    fn finish(&self, transaction: &Transaction, changes: UpdateTransactionStageForm, finished: &FinishedTransaction) -> Result<()> {
+        use crate::schema::finished_transactions;
+
+        if changes.stage != TransactionStage::Finished {
+            return Err(anyhow!("Can't finish transaction {} by moving it to {}", &transaction.id, changes.stage));
+        }
+
+        let pooled = crate::DATABASE.get_connection();
+
+        with_connection!(pooled, |connection| {
+            // The transitions run on the same connection, nested in this transaction.
+            connection.transaction::<_, anyhow::Error, _>(|| {
+                let selling = match sold_outside(transaction, finished) {
+                    Some((selling, changes)) => {
+                        database::transition(&pooled, transaction, changes)?;
+                        selling
+                    }
+                    None => transaction.clone(),
+                };
+
+                database::transition(&pooled, &selling, changes)?;
+                diesel::insert_into(finished_transactions::table)
+                    .values(finished)
+                    .execute(connection)?;
+
+                Ok(())
+            })
+        })
+    }
+
+    fn stage_history(&self, transaction_id: &str) -> Result<Vec<StageTransition>> {
+        Ok(database::stage_history(&crate::DATABASE.get_connection(), transaction_id)?)
+    }
+
+    fn record_fills(&self, fills: &[NewFill]) -> Result<usize> {
+        Ok(database::record_fills(&crate::DATABASE.get_connection(), fills)?)
+    }
+
+    fn fills_for(&self, transaction_id: &str) -> Result<Vec<Fill>> {
+        Ok(database::fills_for(&crate::DATABASE.get_connection(), transaction_id)?)
+    }
+
+    fn realised_pnl(&self) -> Result<Decimal> {
+        use crate::schema::{finished_transactions, transactions};
+
+        let connection = crate::DATABASE.get_connection();
+
+        let trades = with_connection!(connection, |connection| {
+            finished_transactions::table
+                .inner_join(transactions::table)
+                .filter(transactions::stage.eq(TransactionStage::Finished))
+                .select((
+                    finished_transactions::amount_bought,
+                    finished_transactions::buy_price,
+                    finished_transactions::amount_sold,
+                    finished_transactions::sell_price,
+                ))
+                .load::<(Decimal, Decimal, Decimal, Decimal)>(connection)
+        })?;
+
+        Ok(trades
+            .iter()
+            .map(|(amount_bought, buy_price, amount_sold, sell_price)| amount_sold * sell_price - amount_bought * buy_price)
+            .sum())
+    }
+
+    fn finished_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<Vec<(FinishedTransaction, Transaction)>> {
+        use crate::schema::{finished_transactions, transactions};
+
+        let connection = crate::DATABASE.get_connection();
+
+        Ok(with_connection!(connection, |connection| {
+            finished_transactions::table
+                .inner_join(transactions::table)
+                .filter(transactions::stage.eq(TransactionStage::Finished))
+                .filter(finished_transactions::created_at.between(from, to))
+                .order(finished_transactions::created_at.asc())
+                .load::<(FinishedTransaction, Transaction)>(connection)
+        })?)
+    }
+
+    fn pnl_report(&self, from: NaiveDateTime, to: NaiveDateTime, quote_currency: &str) -> Result<PnlReport> {
+        Ok(PnlReport::load(&crate::DATABASE.get_connection(), from, to, quote_currency)?)
+    }
+
+    fn record_equity(&self, snapshot: &[NewEquitySnapshot]) -> Result<usize> {
+        Ok(equity::record_snapshot(&crate::DATABASE.get_connection(), snapshot)?)
+    }
+
+    fn prune_equity(&self, now: NaiveDateTime, keep_all: Duration, keep_hourly: Duration) -> Result<usize> {
+        Ok(equity::downsample(&crate::DATABASE.get_connection(), now, keep_all, keep_hourly)?)
+    }
+}
+
+// The step into SellTransactionOpen a held transaction takes before it finishes, see `finish`.
+fn sold_outside(transaction: &Transaction, finished: &FinishedTransaction) -> Option<(Transaction, UpdateTransactionStageForm)> {
+    if transaction.stage != TransactionStage::Hodl {
+        return None;
+    }
+
+    let mut selling = transaction.clone();
+    selling.stage = TransactionStage::SellTransactionOpen;
+    selling.sell_exchange_id = finished.sell_exchange_id.clone();
+
+    let changes = UpdateTransactionStageForm {
+        stage: TransactionStage::SellTransactionOpen,
+        sell_exchange_id: Some(finished.sell_exchange_id.clone()),
+        updated_at: finished.created_at,
+        amount: transaction.amount,
+        price: None,
+    };
+
+    Some((selling, changes))
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    transactions: Vec<Transaction>,
+    finished: Vec<FinishedTransaction>,
+    history: Vec<StageTransition>,
+    fills: Vec<Fill>,
+    equity: Vec<NewEquitySnapshot>,
+}
+
+// Keeps everything in memory, with the same rules as the database: stage transitions are validated
+// and fills are unique per exchange and trade id.
+#[derive(Debug, Default)]
+pub struct InMemoryTransactionRepository {
+    tables: Mutex<Tables>,
+}
+
+impl InMemoryTransactionRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn finished(&self) -> Vec<FinishedTransaction> {
+        self.tables.lock().finished.clone()
+    }
+}
+
+impl Tables {
+    // The stored side of `TransactionRepository::transition`.
+    fn apply_transition(&mut self, transaction: &Transaction, changes: UpdateTransactionStageForm) -> Result<()> {
+        let stored = self
+            .transactions
+            .iter_mut()
+            .find(|t| t.id == transaction.id)
+            .ok_or_else(|| anyhow!("Transaction {} doesn't exist", transaction.id))?;
+
+        if stored.stage != transaction.stage {
+            return Err(anyhow!("Transaction {} is no longer {}, not moving it to {}", &transaction.id, transaction.stage, changes.stage));
+        }
+
+        let from_stage = stored.stage;
+        stored.stage = from_stage.transition_to(changes.stage)?;
+        stored.amount = changes.amount;
+
+        if let Some(price) = changes.price {
+            stored.price = price;
+        }
+
+        if let Some(sell_exchange_id) = changes.sell_exchange_id {
+            stored.sell_exchange_id = sell_exchange_id;
+        }
+
+        if let Some(updated_at) = changes.updated_at {
+            stored.updated_at = Some(updated_at);
+        }
+
+        self.record_transition(&transaction.id, Some(from_stage), changes.stage);
+
+        Ok(())
+    }
+
+    fn finished_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<(FinishedTransaction, Transaction)> {
+        let mut finished = self
+            .finished
+            .iter()
+            .filter(|f| f.created_at.map_or(false, |at| at >= from && at <= to))
+            .filter_map(|f| {
+                self.transactions
+                    .iter()
+                    .find(|t| t.id == f.transaction_id && t.stage == TransactionStage::Finished)
+                    .map(|t| (f.clone(), t.clone()))
+            })
+            .collect::<Vec<_>>();
+        finished.sort_by_key(|(f, _)| f.created_at);
+
+        finished
+    }
+
+    fn record_transition(&mut self, transaction_id: &str, from_stage: Option<TransactionStage>, to_stage: TransactionStage) {
+        let id = self.history.len() as i64 + 1;
+
+        self.history.push(StageTransition {
+            id,
+            transaction_id: transaction_id.to_string(),
+            from_stage,
+            to_stage,
+            created_at: Utc::now().naive_utc(),
+        });
+    }
+}
+
+impl TransactionRepository for InMemoryTransactionRepository {
+    fn count_for_pair(&self, symbol: &str, stages: &[TransactionStage]) -> Result<i64> {
+        Ok(self.for_pair(symbol, stages)?.len() as i64)
+    }
+
+    fn for_pair(&self, symbol: &str, stages: &[TransactionStage]) -> Result<Vec<Transaction>> {
+        Ok(self
+            .tables
+            .lock()
+            .transactions
+            .iter()
+            .filter(|t| t.symbol == symbol && stages.contains(&t.stage))
+            .cloned()
+            .collect())
+    }
+
+    fn open_for_exchange(&self, exchange: &str) -> Result<Vec<Transaction>> {
+        let open = TransactionStage::open();
+
+        Ok(self
+            .tables
+            .lock()
+            .transactions
+            .iter()
+            .filter(|t| t.exchange_name == exchange && open.contains(&t.stage))
+            .cloned()
+            .collect())
+    }
+
+    fn find(&self, id: &str) -> Result<Option<Transaction>> {
+        Ok(self.tables.lock().transactions.iter().find(|t| t.id == id).cloned())
+    }
+
+    fn find_by_order(&self, exchange: &str, order_id: &str) -> Result<Option<Transaction>> {
+        Ok(self
+            .tables
+            .lock()
+            .transactions
+            .iter()
+            .find(|t| {
+                t.exchange_name == exchange
+                    && (t.buy_exchange_id.as_deref() == Some(order_id) || t.sell_exchange_id.as_deref() == Some(order_id))
+            })
+            .cloned())
+    }
+
+    fn insert(&self, transaction: &Transaction) -> Result<()> {
+        let mut tables = self.tables.lock();
+
+        if tables.transactions.iter().any(|t| t.id == transaction.id) {
+            return Err(anyhow!("Transaction {} already exists", transaction.id));
+        }
+
+        tables.transactions.push(transaction.clone());
+        tables.record_transition(&transaction.id, None, transaction.stage);
+
+        Ok(())
+    }
+
+    fn transition(&self, transaction: &Transaction, changes: UpdateTransactionStageForm) -> Result<()> {
+        self.tables.lock().apply_transition(transaction, changes)
+    }
+
+    fn finish(&self, transaction: &Transaction, changes: UpdateTransactionStageForm, finished: &FinishedTransaction) -> Result<()> {
+        if changes.stage != TransactionStage::Finished {
+            return Err(anyhow!("Can't finish transaction {} by moving it to {}", &transaction.id, changes.stage));
+        }
+
+        let mut tables = self.tables.lock();
+
+        // Both steps are legal from Hodl, so once the first one goes through the second one does too.
+        let selling = match sold_outside(transaction, finished) {
+            Some((selling, changes)) => {
+                tables.apply_transition(transaction, changes)?;
+                selling
+            }
+            None => transaction.clone(),
+        };
+
+        tables.apply_transition(&selling, changes)?;
+        tables.finished.push(finished.clone());
+
+        Ok(())
+    }
+
+    fn stage_history(&self, transaction_id: &str) -> Result<Vec<StageTransition>> {
+        Ok(self
+            .tables
+            .lock()
+            .history
+            .iter()
+            .filter(|h| h.transaction_id == transaction_id)
+            .cloned()
+            .collect())
+    }
+
+    fn record_fills(&self, fills: &[NewFill]) -> Result<usize> {
+        let mut tables = self.tables.lock();
+        let mut recorded = 0;
+
+        for fill in fills.iter() {
+            if tables.fills.iter().any(|f| f.exchange_name == fill.exchange_name && f.trade_id == fill.trade_id) {
+                continue;
+            }
+
+            let id = tables.fills.len() as i64 + 1;
+
+            tables.fills.push(Fill {
+                id,
+                transaction_id: fill.transaction_id.clone(),
+                exchange_name: fill.exchange_name.clone(),
+                order_id: fill.order_id.clone(),
+                trade_id: fill.trade_id.clone(),
+                side: fill.side,
+                quantity: fill.quantity,
+                price: fill.price,
+                fee: fill.fee,
+                fee_asset: fill.fee_asset.clone(),
+                executed_at: fill.executed_at,
+            });
+            recorded += 1;
+        }
+
+        Ok(recorded)
+    }
+
+    fn fills_for(&self, transaction_id: &str) -> Result<Vec<Fill>> {
+        let mut fills = self
+            .tables
+            .lock()
+            .fills
+            .iter()
+            .filter(|f| f.transaction_id == transaction_id)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        fills.sort_by_key(|f| f.executed_at);
+
+        Ok(fills)
+    }
+
+    fn realised_pnl(&self) -> Result<Decimal> {
+        let tables = self.tables.lock();
+
+        Ok(tables
+            .finished
+            .iter()
+            .filter(|f| tables.transactions.iter().any(|t| t.id == f.transaction_id && t.stage == TransactionStage::Finished))
+            .map(|f| f.amount_sold * f.sell_price - f.amount_bought * f.buy_price)
+            .sum())
+    }
+
+    fn finished_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<Vec<(FinishedTransaction, Transaction)>> {
+        Ok(self.tables.lock().finished_between(from, to))
+    }
+
+    fn pnl_report(&self, from: NaiveDateTime, to: NaiveDateTime, quote_currency: &str) -> Result<PnlReport> {
+        let tables = self.tables.lock();
+        let held_stages = PnlReport::held_stages();
+
+        let finished = tables.finished_between(from, to);
+
+        let mut held = tables
+            .transactions
+            .iter()
+            .filter(|t| held_stages.contains(&t.stage))
+            .cloned()
+            .collect::<Vec<_>>();
+        held.sort_by_key(|t| t.created_at);
+
+        let fills = tables
+            .fills
+            .iter()
+            .filter(|f| finished.iter().any(|(_, t)| t.id == f.transaction_id) || held.iter().any(|t| t.id == f.transaction_id))
+            .cloned()
+            .collect();
+
+        Ok(PnlReport::new(from, to, quote_currency, finished, held, fills))
+    }
+
+    fn record_equity(&self, snapshot: &[NewEquitySnapshot]) -> Result<usize> {
+        self.tables.lock().equity.extend_from_slice(snapshot);
+
+        Ok(snapshot.len())
+    }
+
+    fn prune_equity(&self, now: NaiveDateTime, keep_all: Duration, keep_hourly: Duration) -> Result<usize> {
+        let mut tables = self.tables.lock();
+
+        let times = tables
+            .equity
+            .iter()
+            .map(|row| (row.exchange_name.clone(), row.taken_at))
+            .collect::<Vec<_>>();
+        let dropped = equity::to_downsample(times, now, keep_all, keep_hourly);
+
+        let before = tables.equity.len();
+        tables
+            .equity
+            .retain(|row| !dropped.get(&row.exchange_name).map_or(false, |times| times.contains(&row.taken_at)));
+
+        Ok(before - tables.equity.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn transaction(stage: TransactionStage) -> Transaction {
+        Transaction {
+            id: "transaction".to_string(),
+            exchange_name: "mandala".to_string(),
+            buy_exchange_id: Some("buy".to_string()),
+            sell_exchange_id: None,
+            amount: dec!(100),
+            symbol: "ADA".to_string(),
+            price: dec!(0.94),
+            stage,
+            created_at: Some(Utc::now().naive_utc()),
+            updated_at: Some(Utc::now().naive_utc()),
+        }
+    }
+
+    fn changes(stage: TransactionStage, sell_exchange_id: Option<Option<String>>) -> UpdateTransactionStageForm {
+        UpdateTransactionStageForm {
+            stage,
+            sell_exchange_id,
+            updated_at: Some(Utc::now().naive_utc()),
+            amount: dec!(100),
+            price: None,
+        }
+    }
+
+    fn finished(sell_price: Decimal) -> FinishedTransaction {
+        FinishedTransaction {
+            id: "finished".to_string(),
+            transaction_id: "transaction".to_string(),
+            amount_bought: dec!(100),
+            buy_price: dec!(0.94),
+            amount_sold: dec!(100),
+            sell_price,
+            created_at: Some(Utc::now().naive_utc()),
+            updated_at: Some(Utc::now().naive_utc()),
+            buy_exchange_id: Some("buy".to_string()),
+            sell_exchange_id: Some("sell".to_string()),
+        }
+    }
+
+    // Moves the stored transaction on, the way callers do: from what they loaded.
+    fn advance(repository: &InMemoryTransactionRepository, changes: UpdateTransactionStageForm) -> Result<()> {
+        let stored = repository.find("transaction")?.expect("Transaction is stored");
+        repository.transition(&stored, changes)
+    }
+
+    #[test]
+    fn walks_the_whole_life_of_a_transaction() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::BuyTransactionOpen)).unwrap();
+
+        advance(&repository, changes(TransactionStage::BuyTransactionPartiallyFilled, None)).unwrap();
+        advance(&repository, changes(TransactionStage::Hodl, None)).unwrap();
+        advance(&repository, changes(TransactionStage::SellTransactionOpen, Some(Some("sell".to_string())))).unwrap();
+
+        let selling = repository.find("transaction").unwrap().unwrap();
+        assert_eq!(selling.sell_exchange_id.as_deref(), Some("sell"));
+
+        repository.finish(&selling, changes(TransactionStage::Finished, None), &finished(dec!(1.06))).unwrap();
+
+        let stages = repository
+            .stage_history("transaction")
+            .unwrap()
+            .iter()
+            .map(|h| h.to_stage)
+            .collect::<Vec<_>>();
+
+        assert_eq!(stages, vec![
+            TransactionStage::BuyTransactionOpen,
+            TransactionStage::BuyTransactionPartiallyFilled,
+            TransactionStage::Hodl,
+            TransactionStage::SellTransactionOpen,
+            TransactionStage::Finished,
+        ]);
+        assert_eq!(repository.realised_pnl().unwrap(), dec!(12));
+    }
+
+    #[test]
+    fn refuses_illegal_transitions() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::BuyTransactionOpen)).unwrap();
+
+        assert!(advance(&repository, changes(TransactionStage::SellTransactionOpen, None)).is_err());
+        assert!(advance(&repository, changes(TransactionStage::Finished, None)).is_err());
+
+        advance(&repository, changes(TransactionStage::Canceled, None)).unwrap();
+        assert!(advance(&repository, changes(TransactionStage::Hodl, None)).is_err());
+        assert_eq!(repository.stage_history("transaction").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refuses_transitions_from_a_stale_stage() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::Hodl)).unwrap();
+
+        let loaded = repository.find("transaction").unwrap().unwrap();
+        repository.transition(&loaded, changes(TransactionStage::SellTransactionOpen, Some(Some("sell".to_string())))).unwrap();
+
+        // Someone else already placed a sell for what `loaded` still thinks is held.
+        assert!(repository.transition(&loaded, changes(TransactionStage::SellTransactionOpen, Some(Some("other".to_string())))).is_err());
+        assert_eq!(repository.find("transaction").unwrap().unwrap().sell_exchange_id.as_deref(), Some("sell"));
+    }
+
+    #[test]
+    fn a_canceled_sell_goes_back_on_hold_without_its_order() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::Hodl)).unwrap();
+
+        advance(&repository, changes(TransactionStage::SellTransactionOpen, Some(Some("sell".to_string())))).unwrap();
+        advance(&repository, changes(TransactionStage::Hodl, Some(None))).unwrap();
+
+        let held = repository.find("transaction").unwrap().unwrap();
+        assert_eq!(held.stage, TransactionStage::Hodl);
+        assert_eq!(held.sell_exchange_id, None);
+        assert!(repository.finished().is_empty());
+    }
+
+    #[test]
+    fn only_books_a_sale_when_finishing() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::BuyTransactionOpen)).unwrap();
+
+        let buying = repository.find("transaction").unwrap().unwrap();
+        assert!(repository.finish(&buying, changes(TransactionStage::Finished, None), &finished(dec!(1.06))).is_err());
+        assert!(repository.finish(&buying, changes(TransactionStage::Hodl, None), &finished(dec!(1.06))).is_err());
+        assert!(repository.finished().is_empty());
+        assert_eq!(repository.find("transaction").unwrap().unwrap().stage, TransactionStage::BuyTransactionOpen);
+    }
+
+    #[test]
+    fn a_sale_outside_the_bot_passes_through_selling() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::Hodl)).unwrap();
+
+        let held = repository.find("transaction").unwrap().unwrap();
+        repository.finish(&held, changes(TransactionStage::Finished, None), &finished(dec!(1.06))).unwrap();
+
+        let history = repository.stage_history("transaction").unwrap();
+        let stages = history.iter().map(|h| h.to_stage).collect::<Vec<_>>();
+
+        assert_eq!(stages, vec![TransactionStage::Hodl, TransactionStage::SellTransactionOpen, TransactionStage::Finished]);
+        assert_eq!(repository.find("transaction").unwrap().unwrap().sell_exchange_id.as_deref(), Some("sell"));
+        assert_eq!(repository.finished().len(), 1);
+    }
+
+    #[test]
+    fn inserts_each_transaction_once() {
+        let repository = InMemoryTransactionRepository::new();
+        repository.insert(&transaction(TransactionStage::BuyTransactionOpen)).unwrap();
+
+        assert!(repository.insert(&transaction(TransactionStage::BuyTransactionOpen)).is_err());
+        assert_eq!(repository.stage_history("transaction").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::bound::Bound;
+use diesel::expression::AsExpression;
+use diesel::mysql::Mysql;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::{HasSqlType, Numeric, Text};
+use diesel::sqlite::Sqlite;
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::str::FromStr;
+
+// Prices, amounts and balances. MySQL keeps them in DECIMAL columns, SQLite has no exact numeric type
+// so they're stored as TEXT there, which round trips a Decimal without losing anything.
+#[derive(SqlType, QueryId)]
+pub struct Money;
+
+impl HasSqlType<Money> for Mysql {
+    fn metadata(lookup: &Self::MetadataLookup) -> Self::TypeMetadata {
+        <Mysql as HasSqlType<Numeric>>::metadata(lookup)
+    }
+}
+
+impl HasSqlType<Money> for Sqlite {
+    fn metadata(lookup: &Self::MetadataLookup) -> Self::TypeMetadata {
+        <Sqlite as HasSqlType<Text>>::metadata(lookup)
+    }
+}
+
+impl ToSql<Money, Mysql> for Decimal {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Mysql>) -> serialize::Result {
+        ToSql::<Numeric, Mysql>::to_sql(self, out)
+    }
+}
+
+impl FromSql<Money, Mysql> for Decimal {
+    fn from_sql(bytes: Option<&<Mysql as Backend>::RawValue>) -> deserialize::Result<Self> {
+        FromSql::<Numeric, Mysql>::from_sql(bytes)
+    }
+}
+
+impl ToSql<Money, Sqlite> for Decimal {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        ToSql::<Text, Sqlite>::to_sql(&self.to_string(), out)
+    }
+}
+
+impl FromSql<Money, Sqlite> for Decimal {
+    fn from_sql(bytes: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        let text: String = FromSql::<Text, Sqlite>::from_sql(bytes)?;
+
+        Ok(Decimal::from_str(&text)?)
+    }
+}
+
+impl AsExpression<Money> for Decimal {
+    type Expression = Bound<Money, Self>;
+
+    fn as_expression(self) -> Self::Expression {
+        Bound::new(self)
+    }
+}
+
+impl<'a> AsExpression<Money> for &'a Decimal {
+    type Expression = Bound<Money, Self>;
+
+    fn as_expression(self) -> Self::Expression {
+        Bound::new(self)
+    }
+}
@@ -7,7 +7,7 @@ use crate::CONFIG;
 use hashbrown::HashMap;
 use parking_lot::Mutex;
 use serde_json::Value;
-use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::{
     mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
@@ -18,21 +18,44 @@ use tokio::time::{Duration, Instant};
 
 use crate::crypto::orderbook::{Order, OrderBook, OrderSide};
 use futures_util::{SinkExt, StreamExt};
-use std::sync::atomic::Ordering::Release;
 use tokio_tungstenite::connect_async;
 use tungstenite::Message;
 
 use std::rc::Weak;
 use hashbrown::hash_map::{Iter, DefaultHashBuilder};
 use crate::crypto::treasury::TransactionIntent;
+use crate::exchanges::mandala::client::Client;
+use crate::exchanges::mandala::recorder::Recorder;
+use crate::metrics;
+use crate::utils::get_timestamp;
 
 //
-type Sender = UnboundedSender<DepthUpdate>;
-type Receiver = UnboundedReceiver<DepthUpdate>;
+type Sender = UnboundedSender<BookieEvent>;
+type Receiver = UnboundedReceiver<BookieEvent>;
+
+// How long a bookie waits before requesting a new snapshot after losing sync, so a flapping stream
+// doesn't hammer the (weighted) depth endpoint.
+const RESYNC_DELAY: Duration = Duration::from_millis(1000);
+// Binance pings every 3 minutes, we ping it a bit more often to notice dead connections ourselves.
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+// With @100ms streams for liquid pairs, this long without any message means the connection is dead.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+const HEALTHY_CONNECTION: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum BookieEvent {
+    Update(DepthUpdate),
+    // The feed was interrupted, the book has to be rebuilt from a new snapshot.
+    Resync,
+}
 
 pub struct Bookkeeper {
     coins: Vec<Coin>,
     bookies: HashMap<String, Bookie>,
+    reconnects: Arc<AtomicU64>,
+    recorder: Option<Recorder>,
 }
 
 impl Bookkeeper {
@@ -40,6 +63,8 @@ impl Bookkeeper {
         Self {
             coins: vec![],
             bookies: HashMap::new(),
+            reconnects: Arc::new(AtomicU64::new(0)),
+            recorder: CONFIG.recorder.as_ref().map(|config| Recorder::start(&config.path)),
         }
     }
 
@@ -48,12 +73,13 @@ impl Bookkeeper {
 
         for coin in coins.iter() {
             let book = Arc::new(Mutex::new(OrderBook::new(&coin.symbol)));
-            let bookie = Bookie::new(&coin.symbol, Arc::clone(&book));
+            let bookie = Bookie::new(&coin.symbol, Arc::clone(&book), self.recorder.clone());
 
             self.bookies.insert(coin.symbol.clone(), bookie);
         }
 
-        self.boot_websockets(&coins).await;
+        self.boot_websockets(&coins);
+        self.watch_staleness();
         futures::future::join_all(
             self.bookies
                 .values_mut()
@@ -63,69 +89,196 @@ impl Bookkeeper {
         .await;
     }
 
-    async fn boot_websockets(&self, coins: &Vec<Coin>) {
+    // Spawns the supervisor owning the depth websocket. Whenever the connection drops, errors or goes
+    // quiet it's replaced with a new one (backing off exponentially), which resubscribes to all
+    // streams and makes every bookie take a fresh snapshot.
+    fn boot_websockets(&self, coins: &Vec<Coin>) {
         let quote_currency = &CONFIG.quote_currency;
         let params: Vec<_> = coins
             .into_iter()
             .map(|coin| format!("{}{}@depth@100ms", coin.symbol, quote_currency).to_lowercase())
             .collect();
         let request = WebsocketRequest::new(1, "SUBSCRIBE", params);
+        let json_request = serde_json::to_string(&request).expect("Error serializing subscription");
         let senders: HashMap<_, _> = self
             .bookies
             .iter()
             .map(|(coin, bookie)| (coin.clone(), bookie.get_sender()))
             .collect();
+        let books = self.iter_books();
+        let reconnects = Arc::clone(&self.reconnects);
+        let recorder = self.recorder.clone();
+
+        tokio::spawn(async move {
+            let mut backoff = MIN_RECONNECT_BACKOFF;
+            let mut connected_before = false;
+
+            loop {
+                let started = Instant::now();
+
+                match Self::run_websocket(&json_request, &senders, recorder.as_ref(), connected_before).await {
+                    Ok(()) => info!("[Mandala][Bookkeeper]: Depth websocket closed."),
+                    Err(error) => error!("[Mandala][Bookkeeper]: Depth websocket failed: {:?}", error),
+                }
+
+                connected_before = true;
+
+                // Until the bookies have resynced on the new connection the books are frozen.
+                for book in books.values() {
+                    book.lock().mark_stale();
+                }
+
+                // A connection that stayed up for a while doesn't count as flapping.
+                if started.elapsed() > HEALTHY_CONNECTION {
+                    backoff = MIN_RECONNECT_BACKOFF;
+                }
+
+                let count = reconnects.fetch_add(1, Ordering::Relaxed) + 1;
+                metrics::WEBSOCKET_RECONNECTS.inc();
+                warn!(
+                    "[Mandala][Bookkeeper]: Reconnecting depth websocket in {:?}. (reconnect #{})",
+                    backoff,
+                    count
+                );
 
-        let (stream, _) = connect_async(BINANCE_WSS_URL)
-            .await
-            .expect("Failed to connect");
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, MAX_RECONNECT_BACKOFF);
+            }
+        });
+    }
+
+    // Runs a single websocket session, returning when the server closes it or it has to be replaced.
+    async fn run_websocket(subscription: &str, senders: &HashMap<String, Arc<Sender>>, recorder: Option<&Recorder>, resync: bool) -> anyhow::Result<()> {
+        let (stream, _) = connect_async(BINANCE_WSS_URL).await?;
         let (mut write, mut read) = stream.split();
 
-        let json_request = serde_json::to_string(&request).expect("Error serializing subscription");
-        write.send(Message::Text(json_request)).await.unwrap();
+        write.send(Message::Text(subscription.to_string())).await?;
+        info!("[Mandala][Bookkeeper]: Subscribed to depth streams.");
 
-        // Handle first message
-        if let Some(Ok(message)) = read.next().await {
-            if let Message::Text(message) = message {
-                Bookkeeper::handle_update(message, &senders).await;
+        // Anything received on the old connection is useless now, updates on this one are buffered
+        // by the bookies until their new snapshot is in.
+        if resync {
+            for (symbol, sender) in senders.iter() {
+                if sender.send(BookieEvent::Resync).is_err() {
+                    error!("[Mandala][Bookkeeper]: Bookie for {} stopped, can't resync it.", symbol);
+                }
             }
-        } else {
-            panic!("websocket receive faulted")
         }
 
-        tokio::spawn(async move {
-            while let Some(Ok(message)) = read.next().await {
-                match message {
-                    Message::Text(message) => Bookkeeper::handle_update(message, &senders).await,
-                    Message::Binary(_) => {
-                        info!("[Mandala]: Received Binary");
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+
+        loop {
+            tokio::select! {
+                message = tokio::time::timeout(READ_TIMEOUT, read.next()) => {
+                    let message = match message {
+                        Err(_) => return Err(anyhow!("No message received in {:?}", READ_TIMEOUT)),
+                        Ok(None) => return Ok(()),
+                        Ok(Some(message)) => message?,
+                    };
+
+                    match message {
+                        Message::Text(message) => Bookkeeper::handle_update(message, senders, recorder).await,
+                        Message::Binary(_) => {
+                            info!("[Mandala]: Received Binary");
+                        }
+                        Message::Ping(payload) => {
+                            debug!("[Mandala]: Received Ping");
+                            write.send(Message::Pong(payload)).await?;
+                        }
+                        Message::Pong(_) => {
+                            debug!("[Mandala]: Received Pong");
+                        }
+                        Message::Close(frame) => {
+                            info!("[Mandala]: Received Close: {:?}", frame);
+
+                            return Ok(());
+                        }
                     }
-                    Message::Ping(_) => {
-                        info!("[Mandala]: Received Ping");
-                    }
-                    Message::Pong(_) => {
-                        info!("[Mandala]: Received Pong");
+                }
+                _ = ping.tick() => {
+                    write.send(Message::Ping(vec![])).await?;
+                }
+            }
+        }
+    }
+
+    // Flags books that haven't been updated in a while, so brokers stop trading them until fresh
+    // data comes in. How long that is is set per coin, quiet pairs legitimately go a while without
+    // changes.
+    fn watch_staleness(&self) {
+        let books = self.iter_books();
+        let stale_after: HashMap<String, Duration> = CONFIG
+            .coins
+            .iter()
+            .map(|coin| (coin.symbol.clone(), Duration::from_secs(coin.stale_after)))
+            .collect();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+            loop {
+                interval.tick().await;
+
+                for (symbol, book) in books.iter() {
+                    let stale_after = match stale_after.get(symbol) {
+                        Some(stale_after) => *stale_after,
+                        None => continue,
+                    };
+                    let mut book = book.lock();
+
+                    if book.is_stale() || !book.is_valid() {
+                        continue;
                     }
-                    Message::Close(_) => {
-                        info!("[Mandala]: Received Close");
+
+                    if book.updated_at().map_or(false, |at| at.elapsed() > stale_after) {
+                        warn!(
+                            "[Mandala][Bookkeeper]: No updates for {} in {:?}, marking book stale.",
+                            symbol,
+                            stale_after
+                        );
+
+                        book.mark_stale();
                     }
                 }
             }
         });
     }
 
-    async fn handle_update(message: String, senders: &HashMap<String, Arc<Sender>>) {
-        let update: Value = serde_json::from_str(message.as_str()).unwrap();
+    async fn handle_update(message: String, senders: &HashMap<String, Arc<Sender>>, recorder: Option<&Recorder>) {
+        let update: Value = match serde_json::from_str(message.as_str()) {
+            Ok(update) => update,
+            Err(error) => {
+                error!("[Mandala][Bookkeeper]: Received invalid message: {:?}", error);
+
+                return;
+            }
+        };
 
         if update.get("a").is_none() || update.get("b").is_none() {
             return;
         }
 
-        let update: DepthUpdate = serde_json::from_value(update).expect("Invalid message");
+        let update: DepthUpdate = match serde_json::from_value(update) {
+            Ok(update) => update,
+            Err(error) => {
+                error!("[Mandala][Bookkeeper]: Received invalid depth update: {:?}", error);
+
+                return;
+            }
+        };
         let symbol = update.symbol.clone().replace(&CONFIG.quote_currency, "");
 
         debug!("[Mandala][Bookkeeper]: Received update for {}", &symbol);
 
+        let lag = get_timestamp() as i64 - update.event_time;
+        metrics::BOOK_LAG
+            .with_label_values(&[&symbol])
+            .set(lag as f64 / 1000.0);
+
+        if let Some(recorder) = recorder {
+            recorder.record_update(&update);
+        }
+
         match senders.get(&symbol) {
             None => {
                 error!(
@@ -134,7 +287,9 @@ impl Bookkeeper {
                 );
             }
             Some(sender) => {
-                sender.send(update).unwrap();
+                if sender.send(BookieEvent::Update(update)).is_err() {
+                    error!("[Mandala][Bookkeeper]: Bookie for {} stopped, dropping update.", &symbol);
+                }
             }
         }
     }
@@ -143,6 +298,12 @@ impl Bookkeeper {
         for (symbol, bookie) in self.bookies.iter() {
             let book = bookie.book.lock();
 
+            if !book.is_valid() {
+                warn!("[Mandala][Bookkeeper]: Book for {} is not in sync, waiting for resync.", symbol);
+            } else if book.is_stale() {
+                warn!("[Mandala][Bookkeeper]: Book for {} is stale, waiting for updates.", symbol);
+            }
+
             if book.lowest_ask() < book.highest_bid() {
                 error!("Found lower ask than bid: {}", symbol);
                 error!("[Mandala]: Top 5 asks and bids:");
@@ -155,6 +316,27 @@ impl Bookkeeper {
                 }
             }
         }
+
+        let resyncs = self.resync_counts();
+        if resyncs.values().any(|count| *count > 0) {
+            info!("[Mandala][Bookkeeper]: Resyncs per book: {:?}", resyncs);
+        }
+
+        let reconnects = self.reconnect_count();
+        if reconnects > 0 {
+            info!("[Mandala][Bookkeeper]: Depth websocket reconnects: {}", reconnects);
+        }
+    }
+
+    pub fn reconnect_count(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn resync_counts(&self) -> HashMap<String, u64> {
+        self.bookies
+            .iter()
+            .map(|(symbol, bookie)| (symbol.clone(), bookie.resync_count()))
+            .collect()
     }
 
     pub fn get_book<T: Into<String>>(&mut self, coin: T) -> Option<Arc<Mutex<OrderBook>>> {
@@ -181,119 +363,233 @@ impl Bookkeeper {
 
 pub struct Bookie {
     symbol: String,
-    can_process: Arc<Notify>,
-    last_update_id: Arc<AtomicI64>,
+    ready: Arc<Notify>,
+    resyncs: Arc<AtomicU64>,
     book: Arc<Mutex<OrderBook>>,
-    sender: Arc<Sender>
+    sender: Arc<Sender>,
+    recorder: Option<Recorder>,
+}
+
+// Why a bookie stopped following the diff stream.
+enum SyncOutcome {
+    Resync(String),
+    Closed,
 }
 
 impl Bookie {
-    pub fn new<T: Into<String>>(symbol: T, book: Arc<Mutex<OrderBook>>) -> Self {
+    pub fn new<T: Into<String>>(symbol: T, book: Arc<Mutex<OrderBook>>, recorder: Option<Recorder>) -> Self {
         let (sender, receiver) = unbounded_channel();
 
         let mut bookie = Self {
             symbol: symbol.into(),
-            can_process: Arc::new(Notify::new()),
-            last_update_id: Arc::new(AtomicI64::new(0)),
+            ready: Arc::new(Notify::new()),
+            resyncs: Arc::new(AtomicU64::new(0)),
             book,
             sender: Arc::new(sender),
+            recorder,
         };
 
         bookie.start(receiver);
         bookie
     }
 
+    // Keeps the book in sync following Binance's diff depth procedure:
+    //   1. Updates are buffered (in the channel) while the REST snapshot is being fetched.
+    //   2. Buffered updates with `u` <= `lastUpdateId` are dropped.
+    //   3. The first applied update must satisfy `U` <= `lastUpdateId` + 1 <= `u`.
+    //   4. Every following update must start right after the previous one: `U` == previous `u` + 1.
+    // Whenever 3, 4 or the book itself (crossed) doesn't check out the book is invalidated and the
+    // whole procedure starts over, replaying whatever was buffered in the meantime.
     fn start(&mut self, mut receiver: Receiver) {
         let symbol_name = format!("{}_{}", self.symbol, CONFIG.quote_currency);
         info!("[Mandala][Bookie]: Starting bookie for {}", &symbol_name);
 
-        let mut can_process = Arc::clone(&self.can_process);
-        let mut last_update = Arc::clone(&self.last_update_id);
+        let symbol = self.symbol.clone();
+        let ready = Arc::clone(&self.ready);
+        let resyncs = Arc::clone(&self.resyncs);
         let book = Arc::clone(&self.book);
+        let recorder = self.recorder.clone();
 
         tokio::spawn(async move {
-            can_process.notified().await;
+            // Give the websocket some time to start buffering before taking the first snapshot.
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+
+            loop {
+                let last_update_id = match Self::fetch_snapshot(&symbol, &book, recorder.as_ref()).await {
+                    Ok(last_update_id) => last_update_id,
+                    Err(error) => {
+                        error!(
+                            "[Mandala][Bookie]: Error while requesting depth snapshot for {}: {:?}",
+                            &symbol_name,
+                            error
+                        );
+
+                        tokio::time::sleep(RESYNC_DELAY).await;
+                        continue;
+                    }
+                };
 
-            while let Some(update) = receiver.recv().await {
-                if update.last_id < last_update.load(Ordering::Relaxed) {
-                    continue;
-                }
+                debug!(
+                    "[Mandala][Bookie]: Finished processing snapshot for {}, unlocking...",
+                    &symbol_name,
+                );
 
-                debug!("[Mandala][Bookie]: Handled update for {}", &symbol_name);
+                ready.notify_one();
 
-                last_update.store(update.last_id.clone(), Release);
+                match Self::follow(&symbol, &symbol_name, &book, &mut receiver, last_update_id).await {
+                    SyncOutcome::Resync(reason) => {
+                        book.lock().invalidate();
+                        let count = resyncs.fetch_add(1, Ordering::Relaxed) + 1;
+                        metrics::BOOK_RESYNCS.with_label_values(&[&symbol]).inc();
 
-                let mut bids = update
-                    .bids
-                    .iter()
-                    .map(|order| Self::convert_record(order, OrderSide::Buy))
-                    .collect::<Vec<_>>();
+                        warn!(
+                            "[Mandala][Bookie]: {} is out of sync ({}), resyncing. (resync #{})",
+                            &symbol_name,
+                            reason,
+                            count
+                        );
 
-                let mut asks = update
-                    .asks
-                    .iter()
-                    .map(|order| Self::convert_record(order, OrderSide::Sell))
-                    .collect::<Vec<_>>();
+                        tokio::time::sleep(RESYNC_DELAY).await;
+                    }
+                    SyncOutcome::Closed => {
+                        book.lock().invalidate();
+                        info!("[Mandala][Bookie]: Update queue for {} closed, stopping.", &symbol_name);
 
-                book.as_ref().lock().update(bids, asks);
+                        return;
+                    }
+                }
             }
         });
     }
 
-    pub async fn boot(&mut self) {
-        let symbol = format!("{}{}", &self.symbol, CONFIG.quote_currency);
-
-        tokio::time::sleep(Duration::from_millis(1000)).await;
+    async fn follow(symbol: &str, symbol_name: &str, book: &Arc<Mutex<OrderBook>>, receiver: &mut Receiver, mut last_update_id: i64) -> SyncOutcome {
+        let mut bridged = false;
 
-        let result = reqwest::get(format!(
-            "{}/depth?symbol={}&limit=1000",
-            BINANCE_API_URL,
-            symbol.to_uppercase()
-        )).await;
+        while let Some(event) = receiver.recv().await {
+            let update = match event {
+                BookieEvent::Update(update) => update,
+                BookieEvent::Resync => return SyncOutcome::Resync("feed interrupted".to_string()),
+            };
 
-        match result {
-            Ok(response) => {
-                let snapshot: DepthSnapshot = response.json().await.expect("Invalid json");
+            if update.last_id <= last_update_id {
+                continue;
+            }
 
-                self.last_update_id
-                    .store(snapshot.last_update_id.clone(), Release);
+            let expected = last_update_id + 1;
+            if breaks_sequence(update.first_id, expected, bridged) {
+                return SyncOutcome::Resync(format!(
+                    "expected update {}, got {}..{}",
+                    expected, update.first_id, update.last_id
+                ));
+            }
 
-                let mut bids = snapshot
-                    .bids
-                    .iter()
-                    .map(|order| Self::convert_record(order, OrderSide::Buy))
-                    .collect::<Vec<_>>();
+            bridged = true;
+            last_update_id = update.last_id;
+
+            debug!("[Mandala][Bookie]: Handled update for {}", symbol_name);
+
+            let bids = update
+                .bids
+                .iter()
+                .map(|order| Self::convert_record(order, OrderSide::Buy))
+                .collect::<Vec<_>>();
+
+            let asks = update
+                .asks
+                .iter()
+                .map(|order| Self::convert_record(order, OrderSide::Sell))
+                .collect::<Vec<_>>();
+
+            let mut book = book.lock();
+            book.update(bids, asks);
+            metrics::BOOK_UPDATES.with_label_values(&[symbol]).inc();
+
+            if book.is_crossed() {
+                return SyncOutcome::Resync(format!(
+                    "crossed book, bid {:?} ask {:?}",
+                    book.highest_bid(),
+                    book.lowest_ask()
+                ));
+            }
+        }
 
-                let mut asks = snapshot
-                    .asks
-                    .iter()
-                    .map(|order| Self::convert_record(order, OrderSide::Sell))
-                    .collect::<Vec<_>>();
+        SyncOutcome::Closed
+    }
 
-                self.book.as_ref().lock().reload(bids, asks);
+    // Reloads the book from the REST snapshot and returns the snapshot's last update id.
+    async fn fetch_snapshot(symbol: &str, book: &Arc<Mutex<OrderBook>>, recorder: Option<&Recorder>) -> anyhow::Result<i64> {
+        let symbol = format!("{}{}", symbol, CONFIG.quote_currency).to_uppercase();
 
-                debug!(
-                    "[Mandala][Bookie]: Finished processing snapshot for {}, unlocking...",
-                    format!("{}_{}", &self.symbol, CONFIG.quote_currency),
-                );
+        let snapshot: DepthSnapshot =
+            Client::fetch(BINANCE_API_URL, "/depth", format!("symbol={}&limit=1000", symbol), None).await?;
 
-                self.can_process.notify_waiters();
-            }
-            Err(error) => {
-                error!(
-                    "[Mandala][Bookie]: Error while requesting depth snapshot for {}: {:?}",
-                    format!("{}_{}", &self.symbol, CONFIG.quote_currency),
-                    error
-                );
-            }
+        if let Some(recorder) = recorder {
+            recorder.record_snapshot(&symbol, &snapshot);
         }
+
+        let bids = snapshot
+            .bids
+            .iter()
+            .map(|order| Self::convert_record(order, OrderSide::Buy))
+            .collect::<Vec<_>>();
+
+        let asks = snapshot
+            .asks
+            .iter()
+            .map(|order| Self::convert_record(order, OrderSide::Sell))
+            .collect::<Vec<_>>();
+
+        let mut book = book.lock();
+        book.reload(bids, asks);
+        book.validate();
+
+        Ok(snapshot.last_update_id)
+    }
+
+    // Resolves once the book has been loaded from its first snapshot.
+    pub async fn boot(&mut self) {
+        self.ready.notified().await;
     }
 
     pub fn get_sender(&self) -> Arc<Sender> {
         Arc::clone(&self.sender)
     }
 
-    fn convert_record(order: &MandalaOrder, side: OrderSide) -> Order {
+    pub fn resync_count(&self) -> u64 {
+        self.resyncs.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn convert_record(order: &MandalaOrder, side: OrderSide) -> Order {
         Order::new(side, order.1, order.0)
     }
-}
\ No newline at end of file
+}
+
+// Whether an update starting at `first_id` can't follow when `expected` is the next id. The first one
+// applied after a snapshot only has to cover `expected` (step 3 in `Bookie::start`), every later one
+// has to start right at it (step 4).
+fn breaks_sequence(first_id: i64, expected: i64, bridged: bool) -> bool {
+    first_id > expected || (bridged && first_id != expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_update_may_start_before_the_snapshot() {
+        assert!(!breaks_sequence(95, 101, false));
+        assert!(!breaks_sequence(101, 101, false));
+    }
+
+    #[test]
+    fn first_update_after_a_gap_resyncs() {
+        assert!(breaks_sequence(102, 101, false));
+    }
+
+    #[test]
+    fn bridged_updates_have_to_follow_exactly() {
+        assert!(!breaks_sequence(101, 101, true));
+        assert!(breaks_sequence(102, 101, true));
+        assert!(breaks_sequence(100, 101, true));
+    }
+}
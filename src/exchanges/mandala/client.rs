@@ -1,12 +1,22 @@
 use crate::exchanges::mandala::{DEFAULT_RECV_WINDOW, MANDALA_API_URL};
+use crate::metrics;
 use crate::utils::get_timestamp;
 use hmac::{Hmac, Mac, NewMac};
 use minreq::{Error, Method, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use sha2::Sha256;
 use std::collections::BTreeMap;
+use std::time::Instant;
 
 type HmacSha256 = Hmac<Sha256>;
 
+// Just the `code` of a response. Mandala answers errors with HTTP 200 and a non-zero code.
+#[derive(Deserialize)]
+struct ResponseCode {
+    code: Option<i32>,
+}
+
 pub struct Client {
     api_key: String,
     api_secret: String,
@@ -27,18 +37,81 @@ impl Client {
         mut params: BTreeMap<String, String>,
         signed: bool,
     ) -> Result<Response, Error> {
+        let endpoint = endpoint.into();
+        let method_name = format!("{:?}", method).to_uppercase();
         let mut param_string = Self::create_param_string(params);
 
         if signed {
             param_string = Self::sign_params(param_string);
         }
 
-        minreq::Request::new(
+        let started = Instant::now();
+        let result = minreq::Request::new(
             method,
-            format!("{}{}?{}", MANDALA_API_URL, endpoint.into(), param_string),
+            format!("{}{}?{}", MANDALA_API_URL, &endpoint, param_string),
         )
         .with_header("X-MBX-APIKEY", self.api_key.clone())
-        .send()
+        .send();
+
+        let status = result
+            .as_ref()
+            .ok()
+            .map(|response| Self::status_of(response.status_code, response.as_bytes()));
+        metrics::observe_rest(&method_name, &endpoint, status, started.elapsed());
+
+        result
+    }
+
+    // GETs `endpoint` of `base_url` asynchronously, with the API key when `api_key` is given, and
+    // counts it like `request` does.
+    pub async fn fetch<T: DeserializeOwned>(base_url: &str, endpoint: &str, query: String, api_key: Option<&str>) -> anyhow::Result<T> {
+        let mut request = reqwest::Client::new().get(format!("{}{}?{}", base_url, endpoint, query));
+
+        if let Some(api_key) = api_key {
+            request = request.header("X-MBX-APIKEY", api_key);
+        }
+
+        let started = Instant::now();
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(error) => {
+                metrics::observe_rest("GET", endpoint, None, started.elapsed());
+                return Err(error.into());
+            }
+        };
+
+        let http_status = response.status();
+        let body = response.bytes().await;
+        let status = body
+            .as_ref()
+            .ok()
+            .map(|body| Self::status_of(http_status.as_u16() as i32, body));
+        metrics::observe_rest("GET", endpoint, status, started.elapsed());
+
+        let body = body?;
+
+        if !http_status.is_success() || status != Some(http_status.as_u16() as i32) {
+            return Err(anyhow!(
+                "GET {} answered {:?}: {}",
+                endpoint,
+                status,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    // The HTTP status, or the `code` of the body when a 200 carries an error.
+    fn status_of(status_code: i32, body: &[u8]) -> i32 {
+        if status_code != 200 {
+            return status_code;
+        }
+
+        match serde_json::from_slice::<ResponseCode>(body) {
+            Ok(ResponseCode { code: Some(code) }) if code != 0 => code,
+            _ => status_code,
+        }
     }
 
     pub fn sign_params<T: Into<String>>(params: T) -> String {
@@ -1,16 +1,19 @@
 use crate::crypto::balances::{BalanceMap, Balance};
 use crate::crypto::coin::Coin;
+use crate::crypto::orderbook::{OrderBook, OrderSide as BookSide};
 use crate::crypto::{Fees};
-use crate::database::{Transaction, TransactionStage, UpdateTransactionStageForm};
+use crate::database::{Fill, FillSide, FillTotals, FinishedTransaction, NewFill, Transaction, TransactionStage, UpdateTransactionStageForm};
+use crate::database::equity::NewEquitySnapshot;
+use crate::database::repository::TransactionRepository;
 use crate::exchanges::mandala::bookkeeper::Bookkeeper;
 use crate::exchanges::mandala::client::Client;
-use crate::exchanges::mandala::utils::{DepthUpdate, ListedResponse, MandalaResponse, Order, OrderStatus, RequestedOrder, Symbol, WebsocketRequest, OrderType, OrderSide, OrderRequest, PlaceOrderResponse, AccountInfo};
+use crate::exchanges::mandala::utils::{DepthSnapshot, TickerPrice, DepthUpdate, ListedResponse, MandalaResponse, Order, OrderStatus, RequestedOrder, ExecutedTrade, Symbol, WebsocketRequest, OrderType, OrderSide, OrderRequest, PlaceOrderResponse, AccountInfo};
 use crate::exchanges::Exchange;
+use crate::exchanges::reconcile::{missing_holdings, Reconciliation};
 use crate::CONFIG;
 use anyhow::Result;
 use async_trait::async_trait;
-use chrono::Utc;
-use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl};
+use chrono::{NaiveDateTime, Utc};
 use hashbrown::HashMap;
 use hmac::Hmac;
 use minreq::{Method, Response, Error};
@@ -26,11 +29,13 @@ use crate::bot::trading::broker::Broker;
 use crate::crypto::treasury::{Treasured, TransactionIntent, ExecutableTransaction};
 use tokio::sync::watch::Sender;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use round::round_down;
+use rust_decimal::{Decimal, RoundingStrategy};
+use uuid::Uuid;
 
 
 mod bookkeeper;
 mod client;
+pub mod recorder;
 pub mod utils;
 
 const DEFAULT_RECV_WINDOW: usize = 5000;
@@ -45,10 +50,12 @@ pub struct Mandala {
     balances: BalanceMap,
     trader: Trader,
     trader_sender: Sender<Tick>,
+    repository: Arc<dyn TransactionRepository>,
+    last_snapshot: Option<std::time::Instant>,
 }
 
 impl Mandala {
-    pub fn new() -> Self {
+    pub fn new(repository: Arc<dyn TransactionRepository>) -> Self {
         let api_key = CONFIG.mandala.api_key.clone();
         let api_secret = CONFIG.mandala.api_secret.clone();
         let (trader_sender, trader_receiver) =  tokio::sync::watch::channel(Tick::Output);
@@ -57,8 +64,10 @@ impl Mandala {
             client: Client::new(api_key, api_secret),
             bookkeeper: Bookkeeper::new(),
             balances: BalanceMap::new(),
-            trader: Trader::new(trader_receiver.clone()),
+            trader: Trader::new(trader_receiver.clone(), Arc::clone(&repository)),
             trader_sender,
+            repository,
+            last_snapshot: None,
           }
     }
 
@@ -72,22 +81,331 @@ impl Mandala {
         }
     }
 
-    pub fn get_open_orders(&self) -> QueryResult<Vec<Transaction>> {
-        let search_stage = TransactionStage::open()
+    // Every execution of `order_id` so far, from the account trade list.
+    async fn fetch_fills(transaction: &Transaction, order_id: &str, sell: bool) -> Result<Vec<NewFill>> {
+        let endpoint = "/open/v1/orders/trades";
+        let mut params = BTreeMap::new();
+        params.insert("symbol".to_string(), format!("{}_{}", transaction.symbol, CONFIG.quote_currency));
+        params.insert("orderId".to_string(), order_id.to_string());
+
+        let param_string = Client::sign_params(Client::create_param_string(params));
+        let response: MandalaResponse<ListedResponse<ExecutedTrade>> =
+            Client::fetch(MANDALA_API_URL, endpoint, param_string, Some(&CONFIG.mandala.api_key)).await?;
+
+        Ok(response
+            .data
+            .list
             .into_iter()
-            .map(|x| x.to_string())
+            .map(|trade| NewFill {
+                transaction_id: transaction.id.clone(),
+                exchange_name: transaction.exchange_name.clone(),
+                order_id: trade.order_id,
+                trade_id: trade.trade_id,
+                side: if sell { FillSide::Sell } else { FillSide::Buy },
+                quantity: trade.quantity,
+                price: trade.price,
+                fee: trade.commission,
+                fee_asset: trade.commission_asset,
+                executed_at: NaiveDateTime::from_timestamp(trade.time / 1000, (trade.time % 1000) as u32 * 1_000_000),
+            })
+            .collect())
+    }
+
+    // The top of the Binance book `symbol` trades on, from a REST snapshot. It isn't kept in sync.
+    pub async fn fetch_book(symbol: &str) -> Result<OrderBook> {
+        let symbol = format!("{}{}", symbol, CONFIG.quote_currency).to_uppercase();
+        let snapshot: DepthSnapshot =
+            Client::fetch(BINANCE_API_URL, "/depth", format!("symbol={}&limit=5", symbol), None).await?;
+
+        let mut book = OrderBook::new(&symbol);
+        book.reload(
+            snapshot.bids.iter().map(|order| Bookkeeper::convert_record(order, BookSide::Buy)).collect(),
+            snapshot.asks.iter().map(|order| Bookkeeper::convert_record(order, BookSide::Sell)).collect(),
+        );
+
+        Ok(book)
+    }
+
+    pub async fn fetch_mid_price(symbol: &str) -> Result<Option<Decimal>> {
+        Ok(Mandala::fetch_book(symbol).await?.mid())
+    }
+
+    // Price of the last trade on `symbol`.
+    pub async fn fetch_last_price(symbol: &str) -> Result<Decimal> {
+        let symbol = format!("{}{}", symbol, CONFIG.quote_currency).to_uppercase();
+        let ticker: TickerPrice =
+            Client::fetch(BINANCE_API_URL, "/ticker/price", format!("symbol={}", symbol), None).await?;
+
+        Ok(ticker.price)
+    }
+
+    // Every asset in the balances, valued at the mid of its order book.
+    fn equity_snapshot(&self) -> Vec<NewEquitySnapshot> {
+        let taken_at = Utc::now().naive_utc();
+        let books = self.bookkeeper.iter_books();
+
+        self.balances
+            .iter()
+            .filter(|balance| !(balance.available + balance.locked).is_zero())
+            .map(|balance| {
+                let price = if balance.symbol == CONFIG.quote_currency {
+                    Some(Decimal::new(1, 0))
+                } else {
+                    books.get(&balance.symbol).and_then(|book| {
+                        let book = book.lock();
+
+                        if book.is_tradable() { book.mid() } else { None }
+                    })
+                };
+
+                NewEquitySnapshot {
+                    exchange_name: self.get_identifier(),
+                    taken_at,
+                    asset: balance.symbol.clone(),
+                    free: balance.available,
+                    locked: balance.locked,
+                    price,
+                    value: price.map(|price| price * (balance.available + balance.locked)),
+                }
+            })
+            .collect()
+    }
+
+    fn snapshot_equity(&mut self) {
+        let config = &CONFIG.equity_snapshots;
+        let due = self
+            .last_snapshot
+            .map_or(true, |at| at.elapsed() >= std::time::Duration::from_secs(config.interval));
+
+        if !due {
+            return;
+        }
+
+        self.last_snapshot = Some(std::time::Instant::now());
+
+        let snapshot = self.equity_snapshot();
+
+        if let Err(error) = self.repository.record_equity(&snapshot) {
+            error!("[Mandala]: Error saving equity snapshot: {:?}", error);
+            return;
+        }
+
+        let pruned = self.repository.prune_equity(
+            Utc::now().naive_utc(),
+            chrono::Duration::days(config.keep_all_days),
+            chrono::Duration::days(config.keep_hourly_days),
+        );
+
+        match pruned {
+            Ok(0) => {}
+            Ok(pruned) => info!("[Mandala]: Pruned {} old equity snapshot rows", pruned),
+            Err(error) => error!("[Mandala]: Error pruning equity snapshots: {:?}", error),
+        }
+    }
+
+    pub fn get_open_orders(&self) -> Result<Vec<Transaction>> {
+        self.repository.open_for_exchange(&self.get_identifier())
+    }
+
+    async fn fetch_order(order_id: &str) -> Result<RequestedOrder> {
+        let endpoint = "/open/v1/orders/detail";
+        let mut params = BTreeMap::new();
+        params.insert("orderId".to_string(), order_id.to_string());
+
+        let param_string = Client::sign_params(Client::create_param_string(params));
+        let response: MandalaResponse<RequestedOrder> =
+            Client::fetch(MANDALA_API_URL, endpoint, param_string, Some(&CONFIG.mandala.api_key)).await?;
+
+        Ok(response.data)
+    }
+
+    // The most recent orders for `symbol`, open or closed.
+    async fn fetch_orders(symbol: &str) -> Result<Vec<RequestedOrder>> {
+        let endpoint = "/open/v1/orders";
+        let mut params = BTreeMap::new();
+        params.insert("symbol".to_string(), format!("{}_{}", symbol, CONFIG.quote_currency));
+        params.insert("limit".to_string(), "100".to_string());
+
+        let param_string = Client::sign_params(Client::create_param_string(params));
+        let response: MandalaResponse<ListedResponse<RequestedOrder>> =
+            Client::fetch(MANDALA_API_URL, endpoint, param_string, Some(&CONFIG.mandala.api_key)).await?;
+
+        Ok(response.data.list)
+    }
+
+    // Moves `order` to the stage matching its order on the exchange and stores the fills that came with
+    // it. Returns the new stage, None when nothing changed.
+    async fn apply_order_state(repository: &dyn TransactionRepository, order: &Transaction, remote: &RequestedOrder) -> Result<Option<TransactionStage>> {
+        let sell = order.sell_exchange_id.is_some();
+        let mut stage = order.stage;
+
+        match remote.status {
+            OrderStatus::PartiallyFilled if sell => stage = TransactionStage::SellTransactionPartiallyFilled,
+            OrderStatus::PartiallyFilled => stage = TransactionStage::BuyTransactionPartiallyFilled,
+            OrderStatus::Filled if sell => stage = TransactionStage::Finished,
+            OrderStatus::Filled => stage = TransactionStage::Hodl,
+            OrderStatus::Canceled | OrderStatus::Expired | OrderStatus::Rejected => {
+                // A sell that didn't go through leaves the coins on hold. A buy that got
+                // partially filled before it was canceled still holds what was bought.
+                if sell || remote.executed_quantity > Decimal::new(0, 0) {
+                    stage = TransactionStage::Hodl;
+                } else {
+                    stage = TransactionStage::Canceled;
+                }
+            }
+            _ => {}
+        }
+
+        if order.stage == stage {
+            return Ok(None);
+        }
+
+        let mut amount = order.amount;
+        let mut price = None;
+        let mut sold = None;
+
+        if remote.executed_quantity > Decimal::new(0, 0) {
+            // Without the fills we don't know the amount held, the next check tries again.
+            let fills = Mandala::fetch_fills(order, &remote.order_id, sell)
+                .await
+                .map_err(|error| anyhow!("Error fetching fills of order {}: {:?}", &remote.order_id, error))?;
+
+            repository.record_fills(&fills)?;
+            let recorded = repository.fills_for(&order.id)?;
+
+            if sell {
+                let (this_order, all_sells) = sell_totals(&recorded, &remote.order_id, &order.symbol)
+                    .ok_or_else(|| anyhow!("No fills found for sell order {}", &remote.order_id))?;
+
+                // A sell canceled partway only leaves what it didn't sell on hold.
+                if stage == TransactionStage::Hodl {
+                    amount = order.amount - this_order.quantity;
+                }
+
+                sold = Some((this_order, all_sells));
+            } else if stage == TransactionStage::Hodl {
+                match FillTotals::from_fills(&recorded, FillSide::Buy, &order.symbol) {
+                    Some(totals) => {
+                        amount = totals.net_quantity();
+                        price = Some(totals.price);
+                    }
+                    None => return Err(anyhow!("No fills found for filled order {}", &remote.order_id)),
+                }
+            }
+        }
+
+        info!(
+            "[Mandala]: Updating status for order {} from {} to {}",
+            order.id,
+            order.stage,
+            stage
+        );
+
+        let now = Some(Utc::now().naive_utc());
+        let change_set = UpdateTransactionStageForm {
+            stage,
+            // A sell that didn't go through leaves nothing to follow up on.
+            sell_exchange_id: if sell && stage == TransactionStage::Hodl { Some(None) } else { None },
+            updated_at: now,
+            amount,
+            price,
+        };
+
+        if stage != TransactionStage::Finished {
+            repository.transition(order, change_set)?;
+
+            return Ok(Some(stage));
+        }
+
+        let (this_order, all_sells) = sold.ok_or_else(|| anyhow!("No fills found for filled order {}", &remote.order_id))?;
+        let finished = book_sale(order, &remote.order_id, this_order, all_sells);
+
+        repository.finish(order, change_set, &finished)?;
+
+        info!("[Mandala]: Success!! Bought {} {} at {}. Sold {} {} at {}. making a profit of {:.2} {}.",
+              &finished.amount_bought,
+              &order.symbol,
+              &finished.buy_price,
+              &finished.amount_sold,
+              &order.symbol,
+              &finished.sell_price,
+              ((finished.amount_sold * finished.sell_price) - (finished.amount_bought * finished.buy_price)).round_dp(2),
+              CONFIG.quote_currency.clone()
+        );
+
+        Ok(Some(stage))
+    }
+
+    // Books a filled sell placed outside the bot against the held transaction whose coins it sold.
+    async fn finish_with_sell(&self, transaction: &Transaction, sell: &RequestedOrder) -> Result<()> {
+        let fills = Mandala::fetch_fills(transaction, &sell.order_id, true).await?;
+        self.repository.record_fills(&fills)?;
+
+        let (this_order, all_sells) = sell_totals(&self.repository.fills_for(&transaction.id)?, &sell.order_id, &transaction.symbol)
+            .ok_or_else(|| anyhow!("No fills found for sell order {}", &sell.order_id))?;
+
+        let finishing = UpdateTransactionStageForm {
+            stage: TransactionStage::Finished,
+            sell_exchange_id: None,
+            updated_at: Some(Utc::now().naive_utc()),
+            amount: transaction.amount,
+            price: None,
+        };
+
+        // Passes through SellTransactionOpen on the way, in the same database transaction.
+        self.repository.finish(transaction, finishing, &book_sale(transaction, &sell.order_id, this_order, all_sells))
+    }
+
+    // Coins sold by hand leave transactions on hold that the account can't back anymore. A filled sell
+    // that no transaction placed, for exactly the amount of exactly one held transaction, is taken to
+    // be that transaction's sell.
+    async fn book_manual_sells(&self, symbol: &str, open: &[Transaction], orders: &[RequestedOrder], reconciliation: &mut Reconciliation) {
+        // Order quantities get rounded down to one decimal when they're placed.
+        fn sold(transaction: &Transaction, order: &RequestedOrder) -> bool {
+            order.executed_quantity == transaction.amount
+                || order.executed_quantity == transaction.amount.round_dp_with_strategy(1, RoundingStrategy::RoundDown)
+        }
+
+        let exchange = self.get_identifier();
+
+        let held = open
+            .iter()
+            .filter(|t| t.symbol == symbol && t.stage == TransactionStage::Hodl)
             .collect::<Vec<_>>();
 
-        use crate::schema::transactions::dsl::*;
+        let mut sells = vec![];
+
+        for order in orders.iter().filter(|o| o.side == OrderSide::Sell && o.status == OrderStatus::Filled) {
+            match self.repository.find_by_order(&exchange, &order.order_id) {
+                Ok(None) => sells.push(order),
+                Ok(Some(_)) => {}
+                Err(error) => reconciliation.report(format!("Could not look up order {}: {:?}", &order.order_id, error)),
+            }
+        }
+
+        for sell in sells.iter() {
+            let matching = held.iter().filter(|t| sold(t, sell)).collect::<Vec<_>>();
+
+            if matching.len() != 1 || sells.iter().filter(|other| sold(matching[0], other)).count() != 1 {
+                continue;
+            }
 
-        let connection = crate::DATABASE.get_connection();
-        transactions
-            .filter(exchange_name.eq(self.get_identifier()))
-            .filter(stage.eq_any(search_stage))
-            .load::<Transaction>(&connection)
+            let transaction = matching[0];
+
+            match self.finish_with_sell(transaction, sell).await {
+                Ok(()) => reconciliation.fix(format!(
+                    "Transaction {} held {} {}, which were sold outside the bot by order {}, marked it finished",
+                    &transaction.id, &transaction.amount, symbol, &sell.order_id
+                )),
+                Err(error) => reconciliation.report(format!(
+                    "Could not book sell order {} against transaction {}: {:?}",
+                    &sell.order_id, &transaction.id, error
+                )),
+            }
+        }
     }
 
-    fn reload_balances(&mut self) {
+    pub fn reload_balances(&mut self) {
         info!("Reloading balances");
         let endpoint = "/open/v1/account/spot";
         let result = self.client.request(minreq::Method::Get, endpoint, BTreeMap::new(), true);
@@ -171,6 +489,11 @@ impl Exchange for Mandala {
                 }
 
                 self.bookkeeper.boot(tradable_coins).await;
+
+                // The database has to agree with the exchange before anything trades on it.
+                let reconciliation = self.reconcile().await;
+                reconciliation.log("Mandala");
+
                 self.spawn_brokers(intent_sender);
             }
             Err(error) => {
@@ -204,6 +527,7 @@ impl Exchange for Mandala {
 
             self.reload_balances();
             self.check_open_orders();
+            self.snapshot_equity();
         }
 
         self.trader_sender.send(tick).expect("Error");
@@ -213,85 +537,130 @@ impl Exchange for Mandala {
         &self.balances
     }
 
+    fn books(&self) -> HashMap<String, Arc<Mutex<OrderBook>>> {
+        self.bookkeeper.iter_books()
+    }
+
     fn get_fees(&self) -> &Fees {
         unimplemented!()
     }
 
-    fn check_open_orders(&self) {
-        let open_orders = self.get_open_orders().expect("no open orders");
+    async fn reconcile(&mut self) -> Reconciliation {
+        let mut reconciliation = Reconciliation::new();
+        let exchange = self.get_identifier();
 
-        for order in open_orders.into_iter() {
-            tokio::spawn(async move {
-                let endpoint = "/open/v1/orders/detail";
-                let mut params = BTreeMap::new();
-                let mut sell = false;
-                if order.sell_exchange_id.is_some() {
-                    sell = true;
-                    params.insert(
-                        "orderId".into(),
-                        order.sell_exchange_id.as_ref().unwrap().clone(),
-                    );
-                } else {
-                    params.insert(
-                        "orderId".into(),
-                        order.buy_exchange_id.as_ref().unwrap().clone(),
-                    );
+        let open = match self.get_open_orders() {
+            Ok(open) => open,
+            Err(error) => {
+                reconciliation.report(format!("Could not load open transactions: {:?}", error));
+                return reconciliation;
+            }
+        };
+
+        // Recent orders of every configured coin, including the ones placed or canceled on the website.
+        let mut history: HashMap<String, Vec<RequestedOrder>> = HashMap::new();
+
+        for coin in CONFIG.coins.iter() {
+            match Mandala::fetch_orders(&coin.symbol).await {
+                Ok(orders) => {
+                    history.insert(coin.symbol.clone(), orders);
+                }
+                Err(error) => {
+                    reconciliation.report(format!("Could not fetch the order history of {}: {:?}", &coin.symbol, error));
+                }
+            }
+        }
+
+        for transaction in open.iter() {
+            let order_id = match transaction.sell_exchange_id.as_ref().or(transaction.buy_exchange_id.as_ref()) {
+                Some(order_id) => order_id.clone(),
+                None => {
+                    reconciliation.report(format!("Transaction {} has no order on the exchange", &transaction.id));
+                    continue;
                 }
+            };
+
+            let known = history
+                .get(&transaction.symbol)
+                .and_then(|orders| orders.iter().find(|o| o.order_id == order_id))
+                .cloned();
+
+            let remote = match known {
+                Some(remote) => remote,
+                None => match Mandala::fetch_order(&order_id).await {
+                    Ok(remote) => remote,
+                    Err(error) => {
+                        reconciliation.report(format!("Order {} of transaction {} wasn't found on the exchange: {:?}", &order_id, &transaction.id, error));
+                        continue;
+                    }
+                },
+            };
+
+            match Mandala::apply_order_state(&*self.repository, transaction, &remote).await {
+                Ok(Some(stage)) => reconciliation.fix(format!(
+                    "Transaction {} moved from {} to {}, order {} is {:?} on the exchange",
+                    &transaction.id, transaction.stage, stage, &order_id, remote.status
+                )),
+                Ok(None) => {}
+                Err(error) => reconciliation.report(format!("Could not update transaction {}: {:?}", &transaction.id, error)),
+            }
+        }
 
-                let mut param_string = Client::create_param_string(params);
-                param_string = Client::sign_params(param_string);
-                let client = reqwest::Client::new();
+        // Open orders no transaction knows about were placed by hand, or by a run that crashed before
+        // saving them. Which one it was can't be told from here.
+        for (symbol, orders) in history.iter() {
+            for order in orders.iter().filter(|o| o.status == OrderStatus::New || o.status == OrderStatus::PartiallyFilled) {
+                match self.repository.find_by_order(&exchange, &order.order_id) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => reconciliation.report(format!(
+                        "{:?} order {} for {} {} is open on the exchange, but no transaction tracks it",
+                        order.side, &order.order_id, &order.original_quantity, symbol
+                    )),
+                    Err(error) => reconciliation.report(format!("Could not look up order {}: {:?}", &order.order_id, error)),
+                }
+            }
+        }
 
-                let result = client.get(format!("{}{}?{}", MANDALA_API_URL, endpoint, param_string))
-                    .header("X-MBX-APIKEY", &CONFIG.mandala.api_key).send().await;
+        self.reload_balances();
 
+        let open = self.get_open_orders().unwrap_or_default();
 
-                match result {
-                    Ok(response) => {
-                        let response: MandalaResponse<RequestedOrder> =
-                            response.json().await.expect("Invalid json");
-                        let mut stage = TransactionStage::BuyTransactionOpen;
-                        let mut amount = order.amount;
+        for (symbol, _, _) in missing_holdings(&open, &self.balances) {
+            if let Some(orders) = history.get(&symbol) {
+                self.book_manual_sells(&symbol, &open, orders, &mut reconciliation).await;
+            }
+        }
 
-                        if sell {
-                            stage = TransactionStage::SellTransactionOpen;
-                        }
+        let open = self.get_open_orders().unwrap_or_default();
 
-                        if response.data.status == OrderStatus::PartiallyFilled {
-                            stage = TransactionStage::BuyTransactionPartiallyFilled;
+        for (symbol, expected, actual) in missing_holdings(&open, &self.balances) {
+            reconciliation.report(format!(
+                "Open transactions hold {} {}, but the account only has {}",
+                expected, symbol, actual
+            ));
+        }
 
-                            if sell {
-                                stage = TransactionStage::SellTransactionPartiallyFilled;
-                            }
-                        }
+        reconciliation
+    }
 
-                        if response.data.status == OrderStatus::Filled {
-                            stage = TransactionStage::Finished;
+    fn check_open_orders(&self) {
+        let open_orders = self.get_open_orders().expect("no open orders");
 
-                            if !sell {
-                                stage = TransactionStage::Hodl;
-                                amount = (response.data.executed_quantity * 0.99);
-                            }
-                        }
+        for order in open_orders.into_iter() {
+            let repository = Arc::clone(&self.repository);
 
-                        let change_set = UpdateTransactionStageForm {
-                            stage: stage.to_string(),
-                            sell_exchange_id: None,
-                            updated_at: Some(Utc::now().naive_utc()),
-                            amount
-                        };
-
-                        if order.stage != stage.to_string() {
-                            info!(
-                                "[Mandala]: Updating status for order {} from {} to {}",
-                                order.id,
-                                order.stage,
-                                stage.to_string()
-                            );
-                        }
+            tokio::spawn(async move {
+                let order_id = if order.sell_exchange_id.is_some() {
+                    order.sell_exchange_id.as_ref().unwrap().clone()
+                } else {
+                    order.buy_exchange_id.as_ref().unwrap().clone()
+                };
 
-                        let connection = crate::DATABASE.get_connection();
-                        diesel::update(&order).set(change_set).execute(&connection);
+                match Mandala::fetch_order(&order_id).await {
+                    Ok(remote) => {
+                        if let Err(error) = Mandala::apply_order_state(&*repository, &order, &remote).await {
+                            error!("[Mandala]: Error updating transaction {}: {:?}", &order.id, error);
+                        }
                     }
                     Err(e) => {
                         error!(
@@ -362,10 +731,55 @@ impl Exchange for Mandala {
 
 
     }
+
+    fn cancel_order(&mut self, order_id: &str) -> Result<()> {
+        let mut params = BTreeMap::new();
+        params.insert("orderId".to_string(), order_id.to_string());
+
+        let response: MandalaResponse<Value> = self
+            .client
+            .request(Method::Post, "/open/v1/orders/cancel", params, true)?
+            .json()?;
+
+        if response.code != 0 {
+            return Err(anyhow!("Error canceling order {}: {}", order_id, response.msg));
+        }
+
+        self.reload_balances();
+
+        Ok(())
+    }
 }
 
 impl Treasured for Mandala {
     fn request_balances(&self) -> &BalanceMap {
         &self.balances
     }
-}
\ No newline at end of file
+}
+
+// What the fills of sell order `order_id` add up to, and what every sell of the transaction does.
+fn sell_totals(fills: &[Fill], order_id: &str, symbol: &str) -> Option<(FillTotals, FillTotals)> {
+    let this_order = fills.iter().filter(|f| f.order_id == order_id).cloned().collect::<Vec<_>>();
+    let this_order = FillTotals::from_fills(&this_order, FillSide::Sell, symbol)?;
+
+    Some((this_order, FillTotals::from_fills(fills, FillSide::Sell, symbol).unwrap_or(this_order)))
+}
+
+// The finished row of a transaction whose sell order `order_id` filled. Sells canceled partway before
+// it already took what they sold off the amount held, that's added back to get what was bought.
+fn book_sale(transaction: &Transaction, order_id: &str, this_order: FillTotals, all_sells: FillTotals) -> FinishedTransaction {
+    let now = Some(Utc::now().naive_utc());
+
+    FinishedTransaction {
+        id: Uuid::new_v4().to_string(),
+        transaction_id: transaction.id.clone(),
+        amount_bought: transaction.amount + all_sells.quantity - this_order.quantity,
+        buy_price: transaction.price,
+        amount_sold: all_sells.quantity,
+        sell_price: all_sells.price,
+        created_at: now,
+        updated_at: now,
+        buy_exchange_id: transaction.buy_exchange_id.clone(),
+        sell_exchange_id: Some(order_id.to_string()),
+    }
+}
@@ -0,0 +1,364 @@
+//! Records the depth data the bookkeeper receives, and reads it back.
+//!
+//! Every snapshot and diff update is appended to a gzip compressed file per pair, (UTC) day and run of
+//! the bot, the segment being the time the recorder started in milliseconds since the epoch:
+//!
+//! `<path>/<PAIR>/<YYYY-MM-DD>.<segment>.jsonl.gz`, e.g. `recordings/ADAUSDT/2021-03-20.1616198400000.jsonl.gz`
+//!
+//! Each line holds one JSON record, stamped with the (local) time it was received in milliseconds
+//! since the epoch. Snapshots and updates keep Binance's own layout, prices and quantities as strings:
+//!
+//! ```text
+//! {"received_at":1616198400123,"event":{"type":"snapshot","data":{"lastUpdateId":160,"bids":[["1.2000","10.0"]],"asks":[...]}}}
+//! {"received_at":1616198400201,"event":{"type":"update","data":{"E":1616198400199,"s":"ADAUSDT","U":157,"u":160,"b":[...],"a":[...]}}}
+//! ```
+//!
+//! A day is read by going through its segments in order. Writes are flushed every second, whether or
+//! not new data came in, so a crash loses at most the last second of data. It leaves a truncated
+//! segment, which the reader reads up to the cut and then moves on to the next one.
+//!
+//! A new snapshot is recorded every time a bookie (re)syncs, so the book can be reconstructed from
+//! any snapshot onwards by applying the updates that follow it, see `BookReplay`.
+
+use crate::crypto::orderbook::{OrderBook, OrderSide};
+use crate::exchanges::mandala::bookkeeper::Bookie;
+use crate::exchanges::mandala::utils::{DepthSnapshot, DepthUpdate};
+use crate::utils::get_timestamp;
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// How many days back `book_at` looks for a snapshot to start from.
+const MAX_SNAPSHOT_LOOKBACK_DAYS: i64 = 7;
+const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// Updates a replay holds on to while it waits for a snapshot. The one bridging a snapshot is received
+// moments before it, this only has to cover the time the snapshot request takes.
+const MAX_PENDING_UPDATES: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Record {
+    pub received_at: i64,
+    pub event: RecordedEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Snapshot(DepthSnapshot),
+    Update(DepthUpdate),
+}
+
+// Cheap to clone handle to the recording thread.
+#[derive(Clone)]
+pub struct Recorder {
+    sender: UnboundedSender<(String, Record)>,
+}
+
+impl Recorder {
+    pub fn start<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let segment = get_timestamp();
+        let (sender, receiver) = unbounded_channel();
+
+        info!("[Mandala][Recorder]: Recording depth data to {} (segment {})", path.display(), segment);
+
+        // File IO is blocking, so the writer gets a thread of its own instead of a task.
+        std::thread::spawn(move || Self::write_loop(path, segment, receiver));
+
+        Self { sender }
+    }
+
+    pub fn record_snapshot<T: Into<String>>(&self, pair: T, snapshot: &DepthSnapshot) {
+        self.record(pair.into(), RecordedEvent::Snapshot(snapshot.clone()));
+    }
+
+    pub fn record_update(&self, update: &DepthUpdate) {
+        self.record(update.symbol.clone(), RecordedEvent::Update(update.clone()));
+    }
+
+    fn record(&self, pair: String, event: RecordedEvent) {
+        let record = Record {
+            received_at: get_timestamp() as i64,
+            event,
+        };
+
+        if self.sender.send((pair, record)).is_err() {
+            error!("[Mandala][Recorder]: Recording thread is gone, dropping record.");
+        }
+    }
+
+    fn write_loop(path: PathBuf, segment: u64, mut receiver: UnboundedReceiver<(String, Record)>) {
+        let mut files: HashMap<String, (NaiveDate, GzEncoder<File>)> = HashMap::new();
+        let mut last_flush = Instant::now();
+
+        // Only used to wait on the channel with a timeout, so buffered data gets flushed when the
+        // stream goes quiet too.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("Error creating the recorder runtime");
+
+        loop {
+            match runtime.block_on(tokio::time::timeout(FLUSH_INTERVAL, receiver.recv())) {
+                Ok(Some((pair, record))) => {
+                    if let Err(error) = Self::write(&path, segment, &mut files, &pair, &record) {
+                        error!("[Mandala][Recorder]: Error while recording {}: {:?}", &pair, error);
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {}
+            }
+
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                for (pair, (_, file)) in files.iter_mut() {
+                    if let Err(error) = file.flush() {
+                        error!("[Mandala][Recorder]: Error while flushing {}: {:?}", pair, error);
+                    }
+                }
+
+                last_flush = Instant::now();
+            }
+        }
+
+        for (_, (_, file)) in files.into_iter() {
+            let _ = file.finish();
+        }
+    }
+
+    fn write(path: &Path, segment: u64, files: &mut HashMap<String, (NaiveDate, GzEncoder<File>)>, pair: &str, record: &Record) -> Result<()> {
+        let day = Utc.timestamp_millis(record.received_at).naive_utc().date();
+
+        // Roll over to a new file at midnight.
+        if files.get(pair).map_or(true, |(file_day, _)| *file_day != day) {
+            if let Some((_, old)) = files.remove(pair) {
+                old.finish()?;
+            }
+
+            let file_path = recording_path(path, pair, day, segment);
+            create_dir_all(file_path.parent().expect("Recording path has no parent"))?;
+
+            let file = OpenOptions::new().create(true).append(true).open(&file_path)?;
+            files.insert(pair.to_string(), (day, GzEncoder::new(file, Compression::default())));
+        }
+
+        let (_, file) = files.get_mut(pair).expect("Recording file was just opened");
+        serde_json::to_writer(&mut *file, record)?;
+        file.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+pub fn recording_path(path: &Path, pair: &str, day: NaiveDate, segment: u64) -> PathBuf {
+    path.join(pair.to_uppercase())
+        .join(format!("{}.{}.jsonl.gz", day.format("%Y-%m-%d"), segment))
+}
+
+// The files holding records of `pair` on `day`, oldest segment first. A file from before recordings
+// were split per run (`<YYYY-MM-DD>.jsonl.gz`) goes first.
+fn day_files(path: &Path, pair: &str, day: NaiveDate) -> Result<Vec<PathBuf>> {
+    let directory = path.join(pair.to_uppercase());
+
+    if !directory.exists() {
+        return Ok(vec![]);
+    }
+
+    let day = day.format("%Y-%m-%d").to_string();
+    let mut files = vec![];
+
+    for entry in read_dir(&directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let segment = match name.strip_suffix(".jsonl.gz") {
+            Some(stem) if stem == day => 0,
+            Some(stem) => match stem.strip_prefix(&day).and_then(|rest| rest.strip_prefix('.')) {
+                Some(segment) => match segment.parse::<u64>() {
+                    Ok(segment) => segment,
+                    Err(_) => continue,
+                },
+                None => continue,
+            },
+            None => continue,
+        };
+
+        files.push((segment, entry.path()));
+    }
+
+    files.sort();
+
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+// Reads all records of a single day, in the order they were received.
+pub fn read_day<P: AsRef<Path>>(path: P, pair: &str, day: NaiveDate) -> Result<Vec<Record>> {
+    let mut records = vec![];
+
+    for file_path in day_files(path.as_ref(), pair, day)? {
+        let reader = BufReader::new(MultiGzDecoder::new(File::open(&file_path)?));
+
+        for line in reader.lines() {
+            // A run that crashed leaves its segment cut off, possibly halfway through a line. What
+            // came before is fine and the next segment picks up after the restart.
+            let record = line
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(serde_json::from_str::<Record>(&line)?));
+
+            match record {
+                Ok(record) => records.push(record),
+                Err(error) => {
+                    warn!("[Mandala][Recorder]: Stopped reading {} early: {:?}", file_path.display(), error);
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+// Lazily iterates the records of all days in `from..=to`, skipping days that can't be read.
+pub fn records<P: Into<PathBuf>>(path: P, pair: &str, from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = Record> {
+    let path = path.into();
+    let pair = pair.to_string();
+
+    std::iter::successors(Some(from), |day| day.succ_opt())
+        .take_while(move |day| *day <= to)
+        .flat_map(move |day| match read_day(&path, &pair, day) {
+            Ok(records) => records,
+            Err(error) => {
+                error!("[Mandala][Recorder]: Error while reading {} for {}: {:?}", &pair, day, error);
+                vec![]
+            }
+        })
+}
+
+// Rebuilds a book by applying recorded events in order, with the same sequencing rules the bookies
+// use: the book is valid from a snapshot onwards, until an update doesn't line up with the previous one.
+//
+// The update bridging a snapshot is usually received (and recorded) before the snapshot itself, so
+// updates are held on to while there is no snapshot, and the ones past it are applied once it's in.
+pub struct BookReplay {
+    book: OrderBook,
+    last_update_id: Option<i64>,
+    pending: VecDeque<DepthUpdate>,
+}
+
+impl BookReplay {
+    pub fn new<T: Into<String>>(symbol: T) -> Self {
+        Self {
+            book: OrderBook::new(symbol),
+            last_update_id: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn apply(&mut self, record: &Record) {
+        match &record.event {
+            RecordedEvent::Snapshot(snapshot) => {
+                let bids = snapshot.bids.iter().map(|o| Bookie::convert_record(o, OrderSide::Buy)).collect();
+                let asks = snapshot.asks.iter().map(|o| Bookie::convert_record(o, OrderSide::Sell)).collect();
+
+                self.book.reload(bids, asks);
+                self.book.validate();
+                self.last_update_id = Some(snapshot.last_update_id);
+
+                // Older ones are skipped by their ids.
+                for update in std::mem::take(&mut self.pending) {
+                    self.apply_update(&update);
+                }
+            }
+            RecordedEvent::Update(update) => self.apply_update(update),
+        }
+    }
+
+    fn apply_update(&mut self, update: &DepthUpdate) {
+        let last_update_id = match self.last_update_id {
+            Some(id) => id,
+            None => {
+                // Nothing to apply this to until a snapshot comes by.
+                self.hold(update);
+
+                return;
+            }
+        };
+
+        if update.last_id <= last_update_id {
+            return;
+        }
+
+        if update.first_id > last_update_id + 1 {
+            // Missed updates while recording, wait for the resync snapshot.
+            self.book.invalidate();
+            self.last_update_id = None;
+            self.hold(update);
+
+            return;
+        }
+
+        let bids = update.bids.iter().map(|o| Bookie::convert_record(o, OrderSide::Buy)).collect();
+        let asks = update.asks.iter().map(|o| Bookie::convert_record(o, OrderSide::Sell)).collect();
+
+        self.book.update(bids, asks);
+        self.last_update_id = Some(update.last_id);
+    }
+
+    fn hold(&mut self, update: &DepthUpdate) {
+        if self.pending.len() == MAX_PENDING_UPDATES {
+            self.pending.pop_front();
+        }
+
+        self.pending.push_back(update.clone());
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn into_book(self) -> OrderBook {
+        self.book
+    }
+}
+
+// Reconstructs the book of `pair` as it was at `at`, starting from the last snapshot before it.
+pub fn book_at<P: AsRef<Path>>(path: P, pair: &str, at: DateTime<Utc>) -> Result<OrderBook> {
+    let at_millis = at.timestamp_millis();
+    let day = at.naive_utc().date();
+
+    // Find the most recent day that has a snapshot before `at`.
+    let start = (0..=MAX_SNAPSHOT_LOOKBACK_DAYS)
+        .map(|days_back| day - Duration::days(days_back))
+        .find(|candidate| {
+            read_day(path.as_ref(), pair, *candidate)
+                .map(|records| {
+                    records.iter().any(|record| {
+                        record.received_at <= at_millis
+                            && matches!(record.event, RecordedEvent::Snapshot(_))
+                    })
+                })
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| anyhow!("No snapshot for {} in the {} days before {}", pair, MAX_SNAPSHOT_LOOKBACK_DAYS, at))?;
+
+    let mut replay = BookReplay::new(pair);
+
+    for record in records(path.as_ref().to_path_buf(), pair, start, day) {
+        if record.received_at > at_millis {
+            break;
+        }
+
+        replay.apply(&record);
+    }
+
+    Ok(replay.into_book())
+}
@@ -1,9 +1,10 @@
-use crate::utils::f64_from_string;
+use crate::utils::decimal_from_string;
 use crate::utils::bool_from_int;
+use crate::utils::string_from_number;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::collections::BTreeMap;
-use round::round_down;
+use rust_decimal::{Decimal, RoundingStrategy};
 use crate::CONFIG as Config;
 
 #[derive(Debug, Deserialize)]
@@ -47,7 +48,7 @@ impl WebsocketRequest {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DepthUpdate {
     #[serde(rename = "E")]
     pub event_time: i64,
@@ -63,7 +64,7 @@ pub struct DepthUpdate {
     pub asks: Vec<Order>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DepthSnapshot {
     #[serde(rename = "lastUpdateId")]
     pub last_update_id: i64,
@@ -71,16 +72,24 @@ pub struct DepthSnapshot {
     pub asks: Vec<Order>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize)]
+pub struct TickerPrice {
+    pub symbol: String,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub price: Decimal,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 // PRICE, QUANTITY
 pub struct Order(
-    #[serde(deserialize_with = "f64_from_string")] pub f64,
-    #[serde(deserialize_with = "f64_from_string")] pub f64,
+    #[serde(deserialize_with = "decimal_from_string")] pub Decimal,
+    #[serde(deserialize_with = "decimal_from_string")] pub Decimal,
 );
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct RequestedOrder {
     #[serde(rename = "orderId")]
+    #[serde(deserialize_with = "string_from_number")]
     pub order_id: String,
     #[serde(rename = "clientId")]
     pub client_id: String,
@@ -90,24 +99,45 @@ pub struct RequestedOrder {
     pub order_type: OrderType,
     pub status: OrderStatus,
     #[serde(rename = "origQty")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub original_quantity: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub original_quantity: Decimal,
     #[serde(rename = "origQuoteQty")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub original_quote_quantity: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub original_quote_quantity: Decimal,
     #[serde(rename = "executedQty")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub executed_quantity: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub executed_quantity: Decimal,
     #[serde(rename = "executedPrice")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub executed_price: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub executed_price: Decimal,
     #[serde(rename = "executedQuoteQty")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub executed_quote_quantity: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub executed_quote_quantity: Decimal,
     #[serde(rename = "createTime")]
     pub create_time: i64,
 }
 
+// A single execution of an order, from the account trade list.
+#[derive(Deserialize, Debug)]
+pub struct ExecutedTrade {
+    #[serde(rename = "id")]
+    #[serde(deserialize_with = "string_from_number")]
+    pub trade_id: String,
+    #[serde(rename = "orderId")]
+    #[serde(deserialize_with = "string_from_number")]
+    pub order_id: String,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub price: Decimal,
+    #[serde(rename = "qty")]
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub quantity: Decimal,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub commission: Decimal,
+    #[serde(rename = "commissionAsset")]
+    pub commission_asset: String,
+    pub time: i64,
+}
+
 #[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialOrd, PartialEq)]
 #[repr(u8)]
 pub enum OrderSide {
@@ -142,7 +172,8 @@ pub enum OrderStatus {
 #[derive(Deserialize, Debug)]
 pub struct PlaceOrderResponse {
     #[serde(rename = "orderId")]
-    pub order_id: i64,
+    #[serde(deserialize_with = "string_from_number")]
+    pub order_id: String,
     #[serde(rename = "createTime")]
     pub create_time: i64
 }
@@ -152,16 +183,16 @@ pub struct OrderRequest {
     symbol: String,
     side: OrderSide,
     order_type: OrderType,
-    quantity: Option<f64>,
-    quote_order_quantity: Option<f64>,
-    price: Option<f64>,
-    stop_price: Option<f64>,
+    quantity: Option<Decimal>,
+    quote_order_quantity: Option<Decimal>,
+    price: Option<Decimal>,
+    stop_price: Option<Decimal>,
     client_id: Option<String>,
-    iceberg_qty: Option<f64>
+    iceberg_qty: Option<Decimal>
 }
 
 impl OrderRequest {
-    pub fn new(symbol: String, side: OrderSide, quantity: Option<f64>, price: Option<f64>) -> Self {
+    pub fn new(symbol: String, side: OrderSide, quantity: Option<Decimal>, price: Option<Decimal>) -> Self {
         assert_eq!(symbol.contains(format!("_{}", &Config.quote_currency).as_str()), true, "Symbol must be in BASE_QUOTE format");
 
         Self {
@@ -187,7 +218,7 @@ impl OrderRequest {
         map.insert("type".into(), format!("{}", self.order_type as u8));
 
         if let Some(quantity) = self.quantity {
-            map.insert("quantity".into(), format!("{}", round_down(quantity, 1)));
+            map.insert("quantity".into(), format!("{}", quantity.round_dp_with_strategy(1, RoundingStrategy::RoundDown)));
         }
 
         if let Some(quote_order_quantity) = self.quote_order_quantity {
@@ -217,20 +248,20 @@ impl OrderRequest {
 #[derive(Deserialize, Debug)]
 pub struct AccountInfo {
     #[serde(rename = "makerCommission")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub maker_commission: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub maker_commission: Decimal,
 
     #[serde(rename = "takerCommission")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub taker_commission: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub taker_commission: Decimal,
 
     #[serde(rename = "buyerCommission")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub buyer_commission: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub buyer_commission: Decimal,
 
     #[serde(rename = "sellerCommission")]
-    #[serde(deserialize_with = "f64_from_string")]
-    pub seller_commission: f64,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub seller_commission: Decimal,
 
     #[serde(rename = "canTrade")]
     #[serde(deserialize_with = "bool_from_int")]
@@ -251,8 +282,8 @@ pub struct AccountInfo {
 #[derive(Deserialize, Debug)]
 pub struct AccountAsset {
     pub asset: String,
-    #[serde(deserialize_with = "f64_from_string")]
-    pub free: f64,
-    #[serde(deserialize_with = "f64_from_string")]
-    pub locked: f64
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub free: Decimal,
+    #[serde(deserialize_with = "decimal_from_string")]
+    pub locked: Decimal
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use crate::crypto::balances::BalanceMap;
 use crate::crypto::Fees;
+use crate::crypto::orderbook::OrderBook;
 use crate::database::{FinishedTransaction, TransactionStage};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -9,8 +10,10 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 use crate::crypto::treasury::{Treasured, TransactionIntent, ExecutableTransaction};
 use tokio::sync::mpsc::UnboundedSender;
+use crate::exchanges::reconcile::Reconciliation;
 
 pub mod mandala;
+pub mod reconcile;
 
 
 #[async_trait]
@@ -20,8 +23,14 @@ pub trait Exchange: Treasured {
     fn get_display_name(&self) -> String;
     async fn tick(&mut self, debug: bool, actionable: bool);
     fn balances(&self) -> &BalanceMap;
+    // The live order books, by coin.
+    fn books(&self) -> HashMap<String, Arc<parking_lot::Mutex<OrderBook>>>;
     fn get_fees(&self) -> &Fees;
+    // Compares open transactions with the orders and balances on the exchange, fixing what can only
+    // have one explanation. Done at boot, before any broker trades.
+    async fn reconcile(&mut self) -> Reconciliation;
     fn check_open_orders(&self);
     fn get_orders(&self, symbol: Option<String>, stages: Option<Vec<TransactionStage>>) {}
     fn execute_transaction(&mut self, transaction: &ExecutableTransaction) -> Result<String>;
+    fn cancel_order(&mut self, order_id: &str) -> Result<()>;
 }
@@ -0,0 +1,90 @@
+use crate::crypto::balances::BalanceMap;
+use crate::database::{Transaction, TransactionStage};
+use hashbrown::HashMap;
+use rust_decimal::Decimal;
+
+// The outcome of comparing open transactions with what the exchange reports, done at boot before any
+// broker starts trading.
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    // Changes made because the exchange state only allowed one explanation.
+    pub fixed: Vec<String>,
+    // Disagreements a human has to look at.
+    pub unresolved: Vec<String>,
+}
+
+impl Reconciliation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fix<T: Into<String>>(&mut self, message: T) {
+        self.fixed.push(message.into());
+    }
+
+    pub fn report<T: Into<String>>(&mut self, message: T) {
+        self.unresolved.push(message.into());
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.unresolved.is_empty()
+    }
+
+    pub fn log(&self, exchange: &str) {
+        for message in self.fixed.iter() {
+            info!("[{}]: Reconciled: {}", exchange, message);
+        }
+
+        for message in self.unresolved.iter() {
+            warn!("[{}]: Needs attention: {}", exchange, message);
+        }
+
+        info!(
+            "[{}]: Reconciliation done, fixed {} and left {} for manual review",
+            exchange,
+            self.fixed.len(),
+            self.unresolved.len()
+        );
+    }
+}
+
+// Coins the open transactions expect to be in the account, per symbol. Partially filled buys are left
+// out, how much they hold is only known once they fill.
+pub fn expected_holdings(transactions: &[Transaction]) -> HashMap<String, Decimal> {
+    let mut holdings = HashMap::new();
+
+    for transaction in transactions.iter() {
+        match transaction.stage {
+            TransactionStage::Hodl
+            | TransactionStage::SellTransactionOpen
+            | TransactionStage::SellTransactionPartiallyFilled => {
+                *holdings.entry(transaction.symbol.clone()).or_insert_with(|| Decimal::new(0, 0)) += transaction.amount;
+            }
+            _ => {}
+        }
+    }
+
+    holdings
+}
+
+// Symbols where the account holds less than the open transactions expect, with the expected and the
+// actual balance.
+pub fn missing_holdings(transactions: &[Transaction], balances: &BalanceMap) -> Vec<(String, Decimal, Decimal)> {
+    let mut missing = expected_holdings(transactions)
+        .into_iter()
+        .filter_map(|(symbol, expected)| {
+            let actual = balances
+                .get_balance_for_symbol(&symbol)
+                .map_or(Decimal::new(0, 0), |balance| balance.available + balance.locked);
+
+            if actual < expected {
+                Some((symbol, expected, actual))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    missing.sort_by(|a, b| a.0.cmp(&b.0));
+    missing
+}
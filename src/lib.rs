@@ -0,0 +1,33 @@
+#![feature(async_closure)]
+#![feature(atomic_from_mut)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate anyhow;
+#[macro_use]
+extern crate lazy_static;
+
+use crate::database::DatabaseManager;
+use crate::utils::config::Config;
+
+pub mod api;
+pub mod backtest;
+pub mod bot;
+pub mod chat;
+pub mod crypto;
+pub mod database;
+pub mod exchanges;
+pub mod metrics;
+pub mod notify;
+pub mod schema;
+pub mod utils;
+
+lazy_static! {
+    pub static ref CONFIG: Config = { Config::load() };
+    pub static ref DATABASE: DatabaseManager = { DatabaseManager::new() };
+}
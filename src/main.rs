@@ -1,43 +1,216 @@
-#![feature(async_closure)]
-#![feature(atomic_from_mut)]
-
-#[macro_use]
-extern crate diesel;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate anyhow;
-#[macro_use]
-extern crate lazy_static;
 
+use anyhow::Result;
+use poppy::api;
+use poppy::chat;
+use poppy::bot::control;
+use poppy::bot::Poppy;
+use poppy::database::repository::{DieselTransactionRepository, TransactionRepository};
+use poppy::exchanges::mandala::Mandala;
+use poppy::exchanges::Exchange;
+use poppy::notify::Notifier;
+use poppy::utils::config::Config;
+use poppy::{CONFIG, DATABASE};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use structopt::StructOpt;
 
-use crate::bot::Poppy;
-use crate::database::DatabaseManager;
-use crate::exchanges::mandala::Mandala;
-use crate::utils::config::Config;
+#[derive(StructOpt)]
+#[structopt(name = "poppy", about = "Trades the configured coins on Mandala.")]
+struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
 
-mod bot;
-mod crypto;
-mod database;
-mod exchanges;
-mod schema;
-mod utils;
+#[derive(StructOpt)]
+enum Command {
+    /// Starts trading (the default)
+    Run,
+    /// Shows open positions and the top of every configured book
+    Status,
+    /// Lists open transactions
+    Positions,
+    /// Sells a held transaction at the best bid, or at a limit price. Don't use it while the bot is
+    /// running, it doesn't coordinate with it and both may sell the same transaction; use the admin API
+    /// or chat commands instead.
+    Sell {
+        /// Id of the transaction to sell
+        transaction: String,
 
-lazy_static! {
-    pub static ref CONFIG: Config = { Config::load() };
-    pub static ref DATABASE: DatabaseManager = { DatabaseManager::new() };
+        /// Limit price, defaults to the best bid so the order fills right away
+        #[structopt(long)]
+        price: Option<Decimal>,
+    },
+    /// Cancels an order on the exchange
+    Cancel {
+        /// Exchange id of the order
+        order: String,
+    },
+    /// Shows the balances on the exchange
+    Balances,
+    /// Brings the database schema up to date
+    Migrate,
+    /// Reads config.json and reports settings that can't work
+    CheckConfig,
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     env_logger::init();
-    info!("Starting Poppy...");
 
-    let mut mandala = Mandala::new();
+    match Opt::from_args().command.unwrap_or(Command::Run) {
+        Command::Run => run().await,
+        Command::Status => status().await,
+        Command::Positions => positions(),
+        Command::Sell { transaction, price } => sell(&transaction, price).await,
+        Command::Cancel { order } => cancel(&order),
+        Command::Balances => balances(),
+        Command::Migrate => DATABASE.run_migrations(),
+        Command::CheckConfig => check_config(),
+    }
+}
+
+fn repository() -> Arc<dyn TransactionRepository> {
+    Arc::new(DieselTransactionRepository)
+}
 
+async fn run() -> Result<()> {
+    info!("Starting Poppy...");
+
+    let repository = repository();
+    let mandala = Mandala::new(Arc::clone(&repository));
 
-    let mut poppy = Poppy::new();
+    let mut poppy = Poppy::new(repository, Notifier::from_config());
     poppy.register_exchange(Box::new(mandala)).await;
 
+    if let Some(admin) = &CONFIG.admin {
+        tokio::spawn(api::serve(poppy.control(), admin.port, admin.token.clone()));
+    }
+
+    if let Some(discord) = CONFIG.discord.as_ref().filter(|discord| !discord.allowed_users.is_empty()) {
+        tokio::spawn(chat::serve_discord(poppy.control(), discord.token.clone(), discord.allowed_users.clone()));
+    }
+
     poppy.run().await;
+
+    Ok(())
+}
+
+fn positions() -> Result<()> {
+    let repository = repository();
+    let mandala = Mandala::new(Arc::clone(&repository));
+    let open = repository.open_for_exchange(&mandala.get_identifier())?;
+
+    println!(
+        "{:<36} {:<8} {:<32} {:>14} {:>12} {:<20}",
+        "Transaction", "Coin", "Stage", "Amount", "Price", "Since"
+    );
+
+    for transaction in open.iter() {
+        println!(
+            "{:<36} {:<8} {:<32} {:>14} {:>12} {:<20}",
+            transaction.id,
+            transaction.symbol,
+            transaction.stage.to_string(),
+            transaction.amount,
+            transaction.price,
+            transaction.created_at.map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default()
+        );
+    }
+
+    println!("{} open transactions", open.len());
+
+    Ok(())
+}
+
+async fn status() -> Result<()> {
+    positions()?;
+
+    println!();
+    println!("{:<8} {:>14} {:>14} {:>14}", "Coin", "Bid", "Ask", "Spread");
+
+    for coin in CONFIG.coins.iter() {
+        match Mandala::fetch_book(&coin.symbol).await {
+            Ok(book) => {
+                let price = |price: Option<Decimal>| price.map_or("-".to_string(), |price| price.to_string());
+
+                println!(
+                    "{:<8} {:>14} {:>14} {:>14}",
+                    coin.symbol,
+                    price(book.highest_bid()),
+                    price(book.lowest_ask()),
+                    price(book.spread().map(|spread| spread.abs()))
+                );
+            }
+            Err(error) => println!("{:<8} error fetching book: {:?}", coin.symbol, error),
+        }
+    }
+
+    Ok(())
+}
+
+async fn sell(id: &str, price: Option<Decimal>) -> Result<()> {
+    let repository = repository();
+    let transaction = repository
+        .find(id)?
+        .ok_or_else(|| anyhow::anyhow!("Transaction {} doesn't exist", id))?;
+
+    let price = match price {
+        Some(price) => price,
+        None => Mandala::fetch_book(&transaction.symbol)
+            .await?
+            .highest_bid()
+            .ok_or_else(|| anyhow::anyhow!("Nobody is bidding on {}", &transaction.symbol))?,
+    };
+
+    let mut mandala = Mandala::new(Arc::clone(&repository));
+    // Posting would race the process exiting, so sells from here aren't announced.
+    let order_id = control::force_sell(&mut mandala, &*repository, &Notifier::disabled(), &transaction, price)?;
+    println!("Placed sell order {} for {} {} at {}", order_id, transaction.amount, transaction.symbol, price);
+
+    Ok(())
+}
+
+fn cancel(order: &str) -> Result<()> {
+    let mut mandala = Mandala::new(repository());
+    mandala.cancel_order(order)?;
+
+    // The transaction follows on the bot's next check of its orders.
+    println!("Canceled order {}", order);
+
+    Ok(())
+}
+
+fn balances() -> Result<()> {
+    let mut mandala = Mandala::new(repository());
+    mandala.reload_balances();
+
+    let mut balances = mandala.balances().iter().collect::<Vec<_>>();
+    balances.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+    println!("{:<8} {:>20} {:>20}", "Asset", "Available", "Locked");
+
+    for balance in balances.iter().filter(|b| !(b.available + b.locked).is_zero()) {
+        println!("{:<8} {:>20} {:>20}", balance.symbol, balance.available, balance.locked);
+    }
+
+    Ok(())
+}
+
+fn check_config() -> Result<()> {
+    let config = Config::try_load()?;
+    let problems = config.problems();
+
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!("Found {} problems in config.json", problems.len()));
+    }
+
+    println!("config.json looks fine, {} coins configured", config.coins.len());
+
+    Ok(())
 }
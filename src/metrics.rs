@@ -0,0 +1,110 @@
+use prometheus::{
+    register_gauge, register_gauge_vec, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Gauge, GaugeVec, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::time::Duration;
+
+// Operational metrics of the running bot, scraped from `GET /metrics` on the admin API. Rates (book
+// updates per second, intents per minute) are left to Prometheus, everything here only counts.
+lazy_static! {
+    pub static ref BOOK_UPDATES: IntCounterVec = register_int_counter_vec!(
+        "poppy_book_updates_total",
+        "Depth updates applied to a book",
+        &["symbol"]
+    )
+    .unwrap();
+    pub static ref BOOK_LAG: GaugeVec = register_gauge_vec!(
+        "poppy_book_lag_seconds",
+        "Time between the exchange sending the last depth update and it being received",
+        &["symbol"]
+    )
+    .unwrap();
+    pub static ref BOOK_RESYNCS: IntCounterVec = register_int_counter_vec!(
+        "poppy_book_resyncs_total",
+        "Times a book lost sync and was rebuilt from a new snapshot",
+        &["symbol"]
+    )
+    .unwrap();
+    pub static ref WEBSOCKET_RECONNECTS: IntCounter = register_int_counter!(
+        "poppy_websocket_reconnects_total",
+        "Times the depth websocket was replaced"
+    )
+    .unwrap();
+    pub static ref REST_LATENCY: HistogramVec = register_histogram_vec!(
+        "poppy_rest_request_duration_seconds",
+        "Duration of REST requests to the exchange",
+        &["method", "endpoint"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+    )
+    .unwrap();
+    pub static ref REST_RESPONSES: IntCounterVec = register_int_counter_vec!(
+        "poppy_rest_responses_total",
+        "REST responses from the exchange by HTTP status, \"error\" when no response came back",
+        &["method", "endpoint", "status"]
+    )
+    .unwrap();
+    pub static ref INTENTS_EMITTED: IntCounterVec = register_int_counter_vec!(
+        "poppy_intents_emitted_total",
+        "Intents sent by the brokers",
+        &["symbol", "side"]
+    )
+    .unwrap();
+    pub static ref INTENTS_REJECTED: IntCounterVec = register_int_counter_vec!(
+        "poppy_intents_rejected_total",
+        "Intents that weren't executed, by reason",
+        &["symbol", "side", "reason"]
+    )
+    .unwrap();
+    pub static ref INTENTS_EXECUTED: IntCounterVec = register_int_counter_vec!(
+        "poppy_intents_executed_total",
+        "Intents placed as orders on the exchange",
+        &["symbol", "side"]
+    )
+    .unwrap();
+    pub static ref OPEN_POSITIONS: IntGaugeVec = register_int_gauge_vec!(
+        "poppy_open_positions",
+        "Transactions in an open stage",
+        &["exchange", "symbol"]
+    )
+    .unwrap();
+    pub static ref REALISED_PNL: Gauge = register_gauge!(
+        "poppy_realised_pnl",
+        "Realised PnL of every finished transaction in the quote currency, before fees"
+    )
+    .unwrap();
+    pub static ref QUOTE_BALANCE: GaugeVec = register_gauge_vec!(
+        "poppy_quote_balance",
+        "Balance of the quote currency",
+        &["exchange", "state"]
+    )
+    .unwrap();
+}
+
+pub fn observe_rest(method: &str, endpoint: &str, status: Option<i32>, elapsed: Duration) {
+    let status = status.map_or("error".to_string(), |status| status.to_string());
+
+    REST_LATENCY
+        .with_label_values(&[method, endpoint])
+        .observe(elapsed.as_secs_f64());
+    REST_RESPONSES
+        .with_label_values(&[method, endpoint, &status])
+        .inc();
+}
+
+pub fn as_f64(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
+// Everything registered, in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = vec![];
+
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Error encoding metrics");
+
+    String::from_utf8(buffer).expect("Metrics aren't valid UTF-8")
+}
@@ -0,0 +1,77 @@
+use crate::notify::{Notification, NotificationSink};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+
+pub const DISCORD_API_URL: &str = "https://discord.com/api/v8";
+// Discord refuses longer messages.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+#[derive(Serialize)]
+struct CreateMessage {
+    content: String,
+}
+
+// Posts notifications to a channel as the bot user `token` belongs to.
+pub struct DiscordSink {
+    token: String,
+    channel_id: String,
+    client: reqwest::Client,
+}
+
+impl DiscordSink {
+    pub fn new<T: Into<String>>(token: T, channel_id: T) -> Self {
+        Self {
+            token: token.into(),
+            channel_id: channel_id.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn post<T: Into<String>>(&self, content: T) -> Result<()> {
+        let content = fit_message(content);
+
+        let response = self
+            .client
+            .post(format!("{}/channels/{}/messages", DISCORD_API_URL, &self.channel_id))
+            .header("Authorization", format!("Bot {}", &self.token))
+            .json(&CreateMessage { content })
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("Discord answered {}: {}", status, response.text().await?));
+        }
+
+        Ok(())
+    }
+}
+
+// Cuts `content` short enough for Discord to accept it.
+pub fn fit_message<T: Into<String>>(content: T) -> String {
+    let mut content = content.into();
+
+    if content.len() > MAX_MESSAGE_LENGTH {
+        let mut end = MAX_MESSAGE_LENGTH - 3;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        content.truncate(end);
+        content.push_str("...");
+    }
+
+    content
+}
+
+#[async_trait]
+impl NotificationSink for DiscordSink {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        self.post(notification.to_string()).await
+    }
+}
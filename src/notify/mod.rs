@@ -0,0 +1,282 @@
+use crate::notify::discord::DiscordSink;
+use crate::CONFIG;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+pub mod discord;
+
+// Something the people running the bot want to hear about.
+#[derive(Debug, Clone)]
+pub enum Notification {
+    Bought {
+        exchange: String,
+        symbol: String,
+        amount: Decimal,
+        price: Decimal,
+        order_id: String,
+    },
+    Sold {
+        exchange: String,
+        symbol: String,
+        amount: Decimal,
+        price: Decimal,
+        order_id: String,
+        // Quote currency made on the transaction, as logged in `Poppy::update_transaction_for_sale`.
+        profit: Decimal,
+    },
+    OrderFailed {
+        exchange: String,
+        symbol: String,
+        side: String,
+        error: String,
+    },
+    // Trading was stopped for something, a coin or all buying, by hand or by the bot itself when a book
+    // goes out of sync or the funds run out.
+    Halted {
+        what: String,
+    },
+    Resumed {
+        what: String,
+    },
+    DailySummary {
+        day: NaiveDate,
+        trades: usize,
+        realised_pnl: Decimal,
+        fees: Decimal,
+        open_positions: usize,
+        quote_balance: Decimal,
+    },
+}
+
+impl Notification {
+    // The message posted for it, amounts in `quote`.
+    pub fn describe(&self, quote: &str) -> String {
+        match self {
+            Notification::Bought { exchange, symbol, amount, price, order_id } => format!(
+                "[{}] Buying {} {} at {} (order {})",
+                exchange, amount, symbol, price, order_id
+            ),
+            Notification::Sold { exchange, symbol, amount, price, order_id, profit } => format!(
+                "[{}] Selling {} {} at {} (order {}), making a profit of {:.2} {}",
+                exchange, amount, symbol, price, order_id, profit.round_dp(2), quote
+            ),
+            Notification::OrderFailed { exchange, symbol, side, error } => format!(
+                "[{}] Placing a {} order for {} failed: {}",
+                exchange, side, symbol, error
+            ),
+            Notification::Halted { what } => format!("Halted {}", what),
+            Notification::Resumed { what } => format!("Resumed {}", what),
+            Notification::DailySummary { day, trades, realised_pnl, fees, open_positions, quote_balance } => format!(
+                "Summary of {}: {} trades, realised PnL {:.2} {} after {:.2} {} in fees. {} open positions, {:.2} {} on the exchanges.",
+                day, trades, realised_pnl.round_dp(2), quote, fees.round_dp(2), quote, open_positions, quote_balance.round_dp(2), quote
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.describe(&CONFIG.quote_currency))
+    }
+}
+
+// Somewhere notifications can be posted, e.g. a chat channel.
+#[async_trait]
+pub trait NotificationSink: Send + Sync {
+    fn name(&self) -> &str;
+    async fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+// Hands notifications to every sink. Delivery happens in the background, so whatever notifies never
+// waits on a chat service and a failing sink only gets logged.
+#[derive(Clone)]
+pub struct Notifier {
+    sender: Option<UnboundedSender<Notification>>,
+}
+
+impl Notifier {
+    pub fn new(sinks: Vec<Box<dyn NotificationSink>>) -> Self {
+        if sinks.is_empty() {
+            return Self::disabled();
+        }
+
+        let (sender, mut receiver) = unbounded_channel::<Notification>();
+
+        tokio::spawn(async move {
+            while let Some(notification) = receiver.recv().await {
+                for sink in sinks.iter() {
+                    if let Err(error) = sink.send(&notification).await {
+                        warn!("[Notify]: Couldn't post to {}: {:?}", sink.name(), error);
+                    }
+                }
+            }
+        });
+
+        Self { sender: Some(sender) }
+    }
+
+    // Drops everything.
+    pub fn disabled() -> Self {
+        Self { sender: None }
+    }
+
+    // The sinks configured in config.json.
+    pub fn from_config() -> Self {
+        let mut sinks: Vec<Box<dyn NotificationSink>> = vec![];
+
+        if let Some(discord) = &CONFIG.discord {
+            if let Some(channel_id) = &discord.channel_id {
+                sinks.push(Box::new(DiscordSink::new(&discord.token, channel_id)));
+            }
+        }
+
+        Self::new(sinks)
+    }
+
+    pub fn notify(&self, notification: Notification) {
+        if let Some(sender) = &self.sender {
+            if let Err(error) = sender.send(notification) {
+                warn!("[Notify]: Delivery stopped, dropping notification: {:?}", error.0);
+            }
+        }
+    }
+}
+
+// Keeps everything it's sent, for tests and dry runs.
+#[derive(Debug, Clone, Default)]
+pub struct MockSink {
+    sent: Arc<Mutex<Vec<Notification>>>,
+}
+
+impl MockSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<Notification> {
+        self.sent.lock().clone()
+    }
+}
+
+#[async_trait]
+impl NotificationSink for MockSink {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        self.sent.lock().push(notification.clone());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::control::Controls;
+    use crate::bot::Poppy;
+    use crate::notify::discord::fit_message;
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+
+    // What `sink` got from `notifier` so far. A marker is sent last and waited for, delivery keeps the
+    // order so everything before it has arrived by then.
+    async fn delivered(notifier: &Notifier, sink: &MockSink) -> Vec<String> {
+        notifier.notify(Notification::Resumed { what: "marker".to_string() });
+
+        for _ in 0..100 {
+            let mut sent = sink.sent().iter().map(|n| n.describe("USDT")).collect::<Vec<_>>();
+
+            if sent.last().map(String::as_str) == Some("Resumed marker") {
+                sent.pop();
+                return sent;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("The marker never arrived");
+    }
+
+    #[tokio::test]
+    async fn halts_are_only_announced_when_they_change() {
+        let sink = MockSink::new();
+        let notifier = Notifier::new(vec![Box::new(sink.clone())]);
+        let controls = Controls::new();
+        let halt = "trading ADA on mandala (book out of sync or stale)";
+
+        Poppy::announce_halt(&controls, &notifier, halt.to_string(), false);
+        Poppy::announce_halt(&controls, &notifier, halt.to_string(), true);
+        Poppy::announce_halt(&controls, &notifier, halt.to_string(), true);
+        Poppy::announce_halt(&controls, &notifier, halt.to_string(), false);
+        Poppy::announce_halt(&controls, &notifier, halt.to_string(), false);
+
+        assert_eq!(delivered(&notifier, &sink).await, vec![
+            format!("Halted {}", halt),
+            format!("Resumed {}", halt),
+        ]);
+    }
+
+    #[test]
+    fn halts_are_kept_apart() {
+        let controls = Controls::new();
+
+        assert!(controls.set_halted("buying on mandala (not enough USDT)", true));
+        assert!(controls.set_halted("trading ADA on mandala (book out of sync or stale)", true));
+        assert!(!controls.set_halted("buying on mandala (not enough USDT)", true));
+        assert!(controls.set_halted("buying on mandala (not enough USDT)", false));
+        assert!(!controls.set_halted("buying on mandala (not enough USDT)", false));
+    }
+
+    #[test]
+    fn describes_sales_with_their_profit() {
+        let sold = Notification::Sold {
+            exchange: "mandala".to_string(),
+            symbol: "ADA".to_string(),
+            amount: dec!(100),
+            price: dec!(1.05),
+            order_id: "42".to_string(),
+            profit: dec!(4.9876),
+        };
+
+        assert_eq!(sold.describe("USDT"), "[mandala] Selling 100 ADA at 1.05 (order 42), making a profit of 4.99 USDT");
+    }
+
+    #[test]
+    fn describes_daily_summaries() {
+        let summary = Notification::DailySummary {
+            day: NaiveDate::from_ymd(2021, 3, 21),
+            trades: 3,
+            realised_pnl: dec!(12.5),
+            fees: dec!(0.3333),
+            open_positions: 2,
+            quote_balance: dec!(250),
+        };
+
+        assert_eq!(
+            summary.describe("USDT"),
+            "Summary of 2021-03-21: 3 trades, realised PnL 12.50 USDT after 0.33 USDT in fees. 2 open positions, 250.00 USDT on the exchanges."
+        );
+    }
+
+    #[test]
+    fn cuts_long_messages_on_a_char_boundary() {
+        let short = "All good".to_string();
+        assert_eq!(fit_message(short.clone()), short);
+
+        // Three bytes a char, so the cut at 1997 bytes falls inside one.
+        let long = "€".repeat(1000);
+        let fitted = fit_message(long);
+
+        assert!(fitted.len() <= 2000);
+        assert!(fitted.ends_with("..."));
+        assert_eq!(fitted, format!("{}...", "€".repeat(665)));
+    }
+}
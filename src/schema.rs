@@ -1,31 +1,108 @@
 table! {
+    use diesel::sql_types::*;
+    use crate::database::sql_types::Money;
+
+    candles (exchange, symbol, timeframe, open_time) {
+        exchange -> Varchar,
+        symbol -> Varchar,
+        timeframe -> Varchar,
+        open_time -> Bigint,
+        open -> Money,
+        high -> Money,
+        low -> Money,
+        close -> Money,
+        volume -> Money,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::database::sql_types::Money;
+
+    equity_snapshots (id) {
+        id -> Bigint,
+        exchange_name -> Varchar,
+        taken_at -> Timestamp,
+        asset -> Varchar,
+        free -> Money,
+        locked -> Money,
+        price -> Nullable<Money>,
+        value -> Nullable<Money>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::database::sql_types::Money;
+    use crate::database::FillSideMapping;
+
+    fills (id) {
+        id -> Bigint,
+        transaction_id -> Char,
+        exchange_name -> Varchar,
+        order_id -> Varchar,
+        trade_id -> Varchar,
+        side -> FillSideMapping,
+        quantity -> Money,
+        price -> Money,
+        fee -> Money,
+        fee_asset -> Varchar,
+        executed_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::database::sql_types::Money;
+
     finished_transactions (id) {
         id -> Char,
         transaction_id -> Char,
-        amount_bought -> Double,
-        buy_price -> Double,
-        amount_sold -> Double,
-        sell_price -> Double,
+        amount_bought -> Money,
+        buy_price -> Money,
+        amount_sold -> Money,
+        sell_price -> Money,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
+        buy_exchange_id -> Nullable<Varchar>,
+        sell_exchange_id -> Nullable<Varchar>,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::database::TransactionStageMapping;
+
+    transaction_stage_history (id) {
+        id -> Bigint,
+        transaction_id -> Char,
+        from_stage -> Nullable<TransactionStageMapping>,
+        to_stage -> TransactionStageMapping,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::database::sql_types::Money;
+    use crate::database::TransactionStageMapping;
+
     transactions (id) {
         id -> Char,
         exchange_name -> Varchar,
         buy_exchange_id -> Nullable<Varchar>,
         sell_exchange_id -> Nullable<Varchar>,
-        amount -> Double,
+        amount -> Money,
         symbol -> Varchar,
-        price -> Double,
-        stage -> Varchar,
+        price -> Money,
+        stage -> TransactionStageMapping,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(fills -> transactions (transaction_id));
 joinable!(finished_transactions -> transactions (transaction_id));
+joinable!(transaction_stage_history -> transactions (transaction_id));
 
-allow_tables_to_appear_in_same_query!(finished_transactions, transactions,);
+allow_tables_to_appear_in_same_query!(candles, equity_snapshots, fills, finished_transactions, transaction_stage_history, transactions,);
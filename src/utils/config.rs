@@ -1,25 +1,38 @@
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::BufReader;
+use rust_decimal::Decimal;
 
 use std::env;
 
 #[derive(Deserialize)]
 pub struct Config {
     pub quote_currency: String,
-    pub max_trade_size: f64,
-    pub min_trade_size: f64,
+    pub max_trade_size: Decimal,
+    pub min_trade_size: Decimal,
     pub max_transaction_per_coin: i64,
     pub coins: Vec<Coin>,
     pub mandala: MandalaConfig,
     pub database_url: String,
+    pub recorder: Option<RecorderConfig>,
+    #[serde(default)]
+    pub equity_snapshots: EquitySnapshotConfig,
+    pub admin: Option<AdminConfig>,
+    pub discord: Option<DiscordConfig>,
 }
 
 #[derive(Deserialize, Clone, Debug, Serialize)]
 pub struct Coin {
     pub symbol: String,
-    pub support: f64,
-    pub profit_wanted: f64,
+    pub support: Decimal,
+    pub profit_wanted: Decimal,
+    // Seconds the book can go without updates before it's flagged stale, see `Bookkeeper`.
+    #[serde(default = "default_stale_after")]
+    pub stale_after: u64,
+}
+
+fn default_stale_after() -> u64 {
+    15
 }
 
 #[derive(Deserialize)]
@@ -29,20 +42,142 @@ pub struct MandalaConfig {
     pub api_secret: String,
 }
 
+#[derive(Deserialize)]
+pub struct RecorderConfig {
+    // Directory the depth recordings are written to, see `exchanges::mandala::recorder`.
+    pub path: String,
+}
+
+// Shortest admin token the API accepts, anything shorter is too easy to guess.
+pub const MIN_ADMIN_TOKEN_LENGTH: usize = 16;
+
+#[derive(Deserialize)]
+pub struct AdminConfig {
+    // Port on localhost the admin API listens on, see `api`.
+    pub port: u16,
+    // Bearer token the endpoints that change anything ask for.
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct DiscordConfig {
+    // Token of the bot user that posts notifications, see `notify::discord`.
+    pub token: String,
+    // Channel the notifications are posted in. Without one nothing is posted, the commands still work.
+    #[serde(default)]
+    pub channel_id: Option<String>,
+    // Discord user ids allowed to send the bot commands, see `chat`. Nobody when empty.
+    #[serde(default)]
+    pub allowed_users: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct EquitySnapshotConfig {
+    // Seconds between two snapshots of the balances, see `database::equity`.
+    pub interval: u64,
+    // Days every snapshot is kept for.
+    pub keep_all_days: i64,
+    // Days one snapshot per hour is kept for, older ones are thinned out to one per day.
+    pub keep_hourly_days: i64,
+}
+
+impl Default for EquitySnapshotConfig {
+    fn default() -> Self {
+        Self {
+            interval: 300,
+            keep_all_days: 7,
+            keep_hourly_days: 90,
+        }
+    }
+}
+
 impl Config {
     pub fn load() -> Self {
+        match Self::try_load() {
+            Ok(config) => config,
+            Err(error) => panic!("Couldn't read config file: {:?}", error),
+        }
+    }
+
+    pub fn try_load() -> anyhow::Result<Self> {
         info!("Reading config");
-        let path = env::current_dir().unwrap();
-        let path = path.as_os_str().to_str().unwrap();
-        let file_path = format!("{}/config.json", path.to_string());
-
-        if let Ok(file) = File::open(file_path) {
-            let reader = BufReader::new(file);
-            if let Ok(config) = serde_json::from_reader(reader) {
-                return config;
+        let file_path = env::current_dir()?.join("config.json");
+        let reader = BufReader::new(File::open(file_path)?);
+
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    // Settings that parse but can't work. Empty when the config is fine.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let zero = Decimal::new(0, 0);
+
+        if self.quote_currency.is_empty() {
+            problems.push("quote_currency is empty".to_string());
+        }
+
+        if self.min_trade_size <= zero {
+            problems.push("min_trade_size has to be above zero".to_string());
+        }
+
+        if self.max_trade_size < self.min_trade_size {
+            problems.push("max_trade_size is below min_trade_size".to_string());
+        }
+
+        if self.max_transaction_per_coin < 1 {
+            problems.push("max_transaction_per_coin has to be at least 1".to_string());
+        }
+
+        if self.coins.is_empty() {
+            problems.push("no coins are configured".to_string());
+        }
+
+        for (i, coin) in self.coins.iter().enumerate() {
+            if self.coins[..i].iter().any(|other| other.symbol == coin.symbol) {
+                problems.push(format!("{} is configured more than once", coin.symbol));
+            }
+
+            if coin.support <= zero {
+                problems.push(format!("support of {} has to be above zero", coin.symbol));
+            }
+
+            if coin.profit_wanted <= zero {
+                problems.push(format!("profit_wanted of {} has to be above zero", coin.symbol));
+            }
+
+            if coin.stale_after == 0 {
+                problems.push(format!("stale_after of {} has to be above zero", coin.symbol));
             }
         }
 
-        panic!("Couldn't read config file.");
+        if self.mandala.enabled && (self.mandala.api_key.is_empty() || self.mandala.api_secret.is_empty()) {
+            problems.push("mandala is enabled without an api_key and api_secret".to_string());
+        }
+
+        if !self.database_url.starts_with("mysql://") && !self.database_url.starts_with("sqlite://") {
+            problems.push("database_url has to start with mysql:// or sqlite://".to_string());
+        }
+
+        if let Some(admin) = &self.admin {
+            if admin.token.len() < MIN_ADMIN_TOKEN_LENGTH {
+                problems.push(format!("admin.token has to be at least {} characters", MIN_ADMIN_TOKEN_LENGTH));
+            }
+        }
+
+        if let Some(discord) = &self.discord {
+            if discord.token.is_empty() {
+                problems.push("discord needs a token".to_string());
+            }
+
+            if discord.channel_id.as_deref().map_or(false, str::is_empty) {
+                problems.push("discord.channel_id is empty, leave it out to post nothing".to_string());
+            }
+        }
+
+        if self.equity_snapshots.keep_hourly_days < self.equity_snapshots.keep_all_days {
+            problems.push("equity_snapshots.keep_hourly_days is below keep_all_days".to_string());
+        }
+
+        problems
     }
 }
@@ -4,11 +4,9 @@ use barrel::Migration;
 use serde::de::Unexpected;
 use serde::{de, Deserialize, Deserializer};
 use serde_json::Value;
+use rust_decimal::Decimal;
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::database::{TransactionStage, Transaction};
-use diesel::{QueryDsl, ExpressionMethods, QueryResult};
-use crate::diesel::RunQueryDsl;
-use diesel::dsl::count;
 use crate::exchanges::Exchange;
 use crate::crypto::treasury::Treasured;
 
@@ -35,33 +33,26 @@ where
     }
 }
 
-pub fn f64_from_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+// Exchanges send prices and quantities as strings ("0.00001230"), parsing them straight into a
+// Decimal keeps them exact. Plain numbers go through their textual form for the same reason, which
+// serde_json writes in scientific notation for small values (1e-7).
+pub fn decimal_from_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
     Ok(match Value::deserialize(deserializer)? {
-        Value::String(s) => s.parse().map_err(de::Error::custom)?,
-        Value::Number(num) => num.as_f64().ok_or(de::Error::custom("Invalid number"))?,
+        Value::String(s) => parse_decimal(&s).map_err(de::Error::custom)?,
+        Value::Number(num) => parse_decimal(&num.to_string()).map_err(de::Error::custom)?,
         _ => return Err(de::Error::custom("wrong type")),
     })
 }
 
-pub fn count_transactions_for_pair<T: Into<String>>(search_symbol: T, search_stage: Vec<TransactionStage>) -> Result<i64, diesel::result::Error> {
-    let search_stage = search_stage.into_iter().map(|x| x.to_string()).collect::<Vec<_>>();
-    use crate::schema::transactions::dsl::*;
-
-    let connection = crate::DATABASE.get_connection();
-    transactions
-        .filter(stage.eq_any(search_stage))
-        .filter(symbol.eq(search_symbol.into()))
-        .select(count(id))
-        .first(&connection)
+// Ids are strings in some responses and plain numbers in others, either way they're kept as text.
+pub fn string_from_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => s,
+        Value::Number(num) => num.to_string(),
+        _ => return Err(de::Error::custom("wrong type")),
+    })
 }
 
-pub fn get_transactions_for_pair<T: Into<String>>(search_symbol: T, search_stage: Vec<TransactionStage>) -> QueryResult<Vec<Transaction>> {
-    let search_stage = search_stage.into_iter().map(|x| x.to_string()).collect::<Vec<_>>();
-    use crate::schema::transactions::dsl::*;
-
-    let connection = crate::DATABASE.get_connection();
-    transactions
-        .filter(stage.eq_any(search_stage))
-        .filter(symbol.eq(search_symbol.into()))
-        .load::<Transaction>(&connection)
+fn parse_decimal(s: &str) -> Result<Decimal, rust_decimal::Error> {
+    Decimal::from_str(s).or_else(|_| Decimal::from_scientific(s))
 }